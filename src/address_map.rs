@@ -0,0 +1,287 @@
+use std::{
+    fmt::{Display, UpperHex},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Error};
+use canparse::pgn::PgnDefinition;
+
+use crate::packet_repo::{frame_id, is_standard, pgn_of, sa};
+
+/// Source address mapping applied to DBC messages.
+///
+/// Text form is `FROM:TO[@PGN]` in hex, with `*` for any.  `FE:*` duplicates each message
+/// with SA 0xFE for every source address seen on the bus.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressMapping {
+    /// DBC source address to replace. None matches any.
+    pub from: Option<u8>,
+    /// New source address. None duplicates the message for every SA seen on the bus.
+    pub to: Option<u8>,
+    /// Only apply to this PGN. None matches all.
+    pub pgn: Option<u32>,
+}
+
+impl AddressMapping {
+    pub fn new(from: u8, to: u8) -> AddressMapping {
+        AddressMapping {
+            from: Some(from),
+            to: Some(to),
+            pgn: None,
+        }
+    }
+
+    fn matches(&self, pgn: &PgnDefinition) -> bool {
+        !is_standard(frame_id(pgn.id))
+            && self.from.is_none_or(|f| f == sa(pgn.id))
+            && self.pgn.is_none_or(|p| p == pgn_of(pgn.id))
+    }
+}
+
+/// Apply `mappings` to the original DBC definitions. The first matching mapping wins.
+///
+/// `seen` returns the source addresses on the bus for a DBC id, ignoring its SA.
+pub fn apply(
+    mappings: &[AddressMapping],
+    pgns: &[PgnDefinition],
+    seen: impl Fn(u32) -> Vec<u8>,
+) -> Vec<PgnDefinition> {
+    pgns.iter()
+        .flat_map(|pgn| {
+            let with_sa = |sa: u8| {
+                let mut pgn = pgn.clone();
+                pgn.id = 0xFFFFFF00 & pgn.id | sa as u32;
+                pgn
+            };
+            match mappings.iter().find(|m| m.matches(pgn)) {
+                None => vec![pgn.clone()],
+                Some(AddressMapping { to: Some(to), .. }) => vec![with_sa(*to)],
+                Some(AddressMapping { to: None, .. }) => {
+                    let addresses = seen(pgn.id);
+                    if addresses.is_empty() {
+                        vec![pgn.clone()]
+                    } else {
+                        addresses.into_iter().map(with_sa).collect()
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// A DBC message that is sent on the bus from source addresses other than the DBC's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Discovered {
+    pub pgn: u32,
+    pub dbc_sa: u8,
+    pub seen: Vec<u8>,
+}
+
+impl Discovered {
+    /// Mapping to show this message for the discovered addresses.
+    pub fn mapping(&self) -> AddressMapping {
+        AddressMapping {
+            from: Some(self.dbc_sa),
+            to: match self.seen.as_slice() {
+                [sa] => Some(*sa),
+                _ => None,
+            },
+            pgn: Some(self.pgn),
+        }
+    }
+}
+
+impl Display for Discovered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seen: Vec<String> = self.seen.iter().map(|sa| format!("{sa:02X}")).collect();
+        write!(
+            f,
+            "PGN {:04X} SA {:02X} seen from {}",
+            self.pgn,
+            self.dbc_sa,
+            seen.join(", ")
+        )
+    }
+}
+
+/// Find DBC messages whose source address is not on the bus, but are sent by other addresses.
+/// Priority is ignored.
+pub fn discover(pgns: &[PgnDefinition], seen: impl Fn(u32) -> Vec<u8>) -> Vec<Discovered> {
+    let mut found: Vec<Discovered> = pgns
+        .iter()
+        .filter_map(|pgn| {
+            let addresses = seen(pgn.id);
+            let found = !is_standard(frame_id(pgn.id)) && !addresses.is_empty();
+            (found && !addresses.contains(&sa(pgn.id))).then(|| Discovered {
+                pgn: pgn_of(pgn.id),
+                dbc_sa: sa(pgn.id),
+                seen: addresses,
+            })
+        })
+        .collect();
+    found.sort_by_key(|d| (d.pgn, d.dbc_sa));
+    found.dedup();
+    found
+}
+
+fn hex_or_any<T: UpperHex>(v: &Option<T>, width: usize) -> String {
+    v.as_ref()
+        .map_or("*".to_string(), |v| format!("{v:0width$X}"))
+}
+
+impl Display for AddressMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            hex_or_any(&self.from, 2),
+            hex_or_any(&self.to, 2)
+        )?;
+        if self.pgn.is_some() {
+            write!(f, "@{}", hex_or_any(&self.pgn, 4))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_u8(s: &str) -> Result<Option<u8>, Error> {
+    let s = s.trim();
+    Ok(if s == "*" || s.is_empty() {
+        None
+    } else {
+        Some(u8::from_str_radix(s, 16)?)
+    })
+}
+
+impl FromStr for AddressMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addresses, pgn) = s.split_once('@').unwrap_or((s, "*"));
+        let (from, to) = addresses
+            .split_once(':')
+            .ok_or(anyhow!("expected FROM:TO[@PGN]"))?;
+        let pgn = pgn.trim();
+        Ok(AddressMapping {
+            from: parse_u8(from)?,
+            to: parse_u8(to)?,
+            pgn: if pgn == "*" || pgn.is_empty() {
+                None
+            } else {
+                Some(u32::from_str_radix(pgn, 16)?)
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dbc, packet_repo::dbc_key};
+
+    /// EEC1 and CCVS from SA FE, and an 11 bit message.
+    fn pgns() -> Vec<PgnDefinition> {
+        dbc::load_test_messages(&format!(
+            "{}\nBO_ 2566844926 CCVS: 8 ECU\n SG_ Speed : 8|16@1+ (0.00390625,0) [0|250.996] \"km/h\" Vector__XXX\n\nBO_ 291 Std: 8 ECU\n SG_ Level : 0|8@1+ (1,0) [0|255] \"\" Vector__XXX\n",
+            dbc::TEST_EEC1
+        ))
+    }
+
+    /// Sorted keys of DBC ids.
+    fn keys(ids: impl IntoIterator<Item = u32>) -> Vec<u32> {
+        let mut keys: Vec<u32> = ids.into_iter().map(dbc_key).collect();
+        keys.sort();
+        keys
+    }
+
+    fn mapped_keys(pgns: &[PgnDefinition]) -> Vec<u32> {
+        keys(pgns.iter().map(|p| p.id))
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let mappings = [
+            "FE:00@F004".parse().unwrap(),
+            AddressMapping::new(0xFE, 0x03),
+            AddressMapping::new(0xFE, 0x05),
+        ];
+        let mapped = apply(&mappings, &pgns(), |_| Vec::new());
+        assert_eq!(mapped_keys(&mapped), keys([0x8CF00400, 0x98FEF103, 0x123]));
+    }
+
+    #[test]
+    fn pgn_scope() {
+        let mapped = apply(&["*:17@FEF1".parse().unwrap()], &pgns(), |_| Vec::new());
+        assert_eq!(mapped_keys(&mapped), keys([0x8CF004FE, 0x98FEF117, 0x123]));
+        assert_eq!(
+            mapped_keys(&apply(&[], &pgns(), |_| Vec::new())),
+            mapped_keys(&pgns())
+        );
+    }
+
+    #[test]
+    fn every_address_seen() {
+        let seen = |id: u32| match pgn_of(id) {
+            0xFEF1 => vec![0x01, 0x02],
+            _ => Vec::new(),
+        };
+        let mapped = apply(&["FE:*".parse().unwrap()], &pgns(), seen);
+        // EEC1 isn't on the bus and keeps its address
+        assert_eq!(
+            mapped_keys(&mapped),
+            keys([0x8CF004FE, 0x98FEF101, 0x98FEF102, 0x123])
+        );
+        let speed = mapped.iter().filter(|p| pgn_of(p.id) == 0xFEF1);
+        assert!(speed.all(|p| p.spns.contains_key("Speed")));
+    }
+
+    #[test]
+    fn text() {
+        for s in ["FE:00", "FE:*", "*:03@FEF1", "00:17@F004"] {
+            assert_eq!(s.parse::<AddressMapping>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "fe:0".parse::<AddressMapping>().unwrap(),
+            AddressMapping::new(0xFE, 0)
+        );
+        assert!("FE".parse::<AddressMapping>().is_err());
+    }
+
+    #[test]
+    fn discovered_mapping() {
+        let one = Discovered {
+            pgn: 0xFEF1,
+            dbc_sa: 0xFE,
+            seen: vec![0x00],
+        };
+        assert_eq!(one.mapping().to_string(), "FE:00@FEF1");
+        let many = Discovered {
+            seen: vec![0x00, 0x03],
+            ..one
+        };
+        assert_eq!(many.mapping().to_string(), "FE:*@FEF1");
+    }
+
+    #[test]
+    fn discover_seen() {
+        let mut pgns = pgns();
+        pgns.extend(pgns.clone());
+        // EEC1 is seen from its DBC address too, the 11 bit message is never remapped
+        let seen = |id: u32| match pgn_of(id) {
+            0xF004 => vec![0x00, 0xFE],
+            0xFEF1 => vec![0x17, 0x03],
+            _ if is_standard(frame_id(id)) => vec![0x05],
+            _ => Vec::new(),
+        };
+        let found = discover(&pgns, seen);
+        assert_eq!(
+            found,
+            vec![Discovered {
+                pgn: 0xFEF1,
+                dbc_sa: 0xFE,
+                seen: vec![0x17, 0x03],
+            }]
+        );
+        assert!(discover(&pgns, |_| Vec::new()).is_empty());
+    }
+}
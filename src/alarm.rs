@@ -0,0 +1,420 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, Local};
+use fltk::{
+    browser::HoldBrowser,
+    button::{Button, CheckButton},
+    dialog::alert_default,
+    draw,
+    enums::{Align, Color},
+    frame::Frame,
+    group::{Flex, PackType},
+    input::{FloatInput, Input},
+    prelude::{BrowserExt, ButtonExt, GroupExt, InputExt, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::simple_model::DrawDelegate;
+use timer::Timer;
+
+/// Limits for one signal. Unset limits are not checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AlarmLimits {
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    /// maximum change per second, either direction
+    pub rate: Option<f64>,
+    /// maximum time without a packet
+    pub stale: Option<Duration>,
+    /// run with `sh -c` (`cmd /C` on Windows) when the alarm is raised, once the user allows it
+    pub command: Option<String>,
+    /// add a marker to the packet log when the alarm is raised
+    pub marker: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    Low(f64),
+    High(f64),
+    /// change per second
+    Rate(f64),
+    /// time since the last packet
+    Stale(Duration),
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Low(v) => write!(f, "low {v:0.3}"),
+            Violation::High(v) => write!(f, "high {v:0.3}"),
+            Violation::Rate(v) => write!(f, "rate {v:0.3}/s"),
+            Violation::Stale(d) => write!(f, "stale {:0.3}s", d.as_secs_f64()),
+        }
+    }
+}
+
+impl AlarmLimits {
+    pub fn is_empty(&self) -> bool {
+        self.low.is_none() && self.high.is_none() && self.rate.is_none() && self.stale.is_none()
+    }
+
+    /// `value` and `previous` are (packet time, decoded value). `now` is the current packet time.
+    pub fn check(
+        &self,
+        value: Option<(Duration, f64)>,
+        previous: Option<(Duration, f64)>,
+        now: Duration,
+    ) -> Option<Violation> {
+        let Some((time, value)) = value else {
+            // never seen is not stale, it is inactive
+            return None;
+        };
+        if let Some(stale) = self.stale {
+            let age = now.saturating_sub(time);
+            if age > stale {
+                return Some(Violation::Stale(age));
+            }
+        }
+        if self.low.is_some_and(|low| value < low) {
+            return Some(Violation::Low(value));
+        }
+        if self.high.is_some_and(|high| value > high) {
+            return Some(Violation::High(value));
+        }
+        if let (Some(rate), Some((t0, v0))) = (self.rate, previous) {
+            let dt = time.saturating_sub(t0).as_secs_f64();
+            if dt > 0.0 {
+                let r = (value - v0) / dt;
+                if r.abs() > rate {
+                    return Some(Violation::Rate(r));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A raised alarm.
+#[derive(Clone, Debug)]
+pub struct AlarmEvent {
+    pub wall: DateTime<Local>,
+    /// packet time
+    pub time: Duration,
+    pub signal: String,
+    pub violation: Violation,
+}
+
+impl Display for AlarmEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:0.3}s {}: {}",
+            self.wall.format("%H:%M:%S"),
+            self.time.as_secs_f64(),
+            self.signal,
+            self.violation
+        )
+    }
+}
+
+pub type AlarmEvents = Arc<Mutex<Vec<AlarmEvent>>>;
+
+/// Run the alarm command in the background.
+pub fn run_command(command: &str, event: &AlarmEvent) {
+    #[cfg(windows)]
+    let mut cmd = Command::new("cmd");
+    #[cfg(windows)]
+    cmd.arg("/C");
+    #[cfg(not(windows))]
+    let mut cmd = Command::new("sh");
+    #[cfg(not(windows))]
+    cmd.arg("-c");
+    let result = cmd
+        .arg(command)
+        .env("ALARM_SIGNAL", &event.signal)
+        .env("ALARM_VIOLATION", event.violation.to_string())
+        .env("ALARM_TIME", format!("{:0.3}", event.time.as_secs_f64()))
+        .spawn();
+    if let Err(err) = result {
+        eprintln!("Unable to run alarm command {command}: {err}");
+    }
+}
+
+/// Alarms are saved next to the DBC file.
+pub fn alarms_path(dbc: &Path) -> PathBuf {
+    let mut name = dbc.as_os_str().to_owned();
+    name.push(".alarms");
+    name.into()
+}
+
+fn optional<T: std::str::FromStr>(s: &str) -> Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let s = s.trim();
+    Ok(if s.is_empty() { None } else { Some(s.parse()?) })
+}
+
+/// Optional time in seconds.
+fn seconds(s: &str) -> Result<Option<Duration>> {
+    optional::<f64>(s)?
+        .map(|v| Duration::try_from_secs_f64(v).map_err(|_| anyhow!("Invalid time {}", s.trim())))
+        .transpose()
+}
+
+/// One line per signal: `key<TAB>low<TAB>high<TAB>rate<TAB>stale<TAB>marker<TAB>command`.
+pub fn load_alarms(path: &Path) -> Result<HashMap<String, AlarmLimits>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let text = fs::read_to_string(path)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(number, line)| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [key, low, high, rate, stale, marker, command] = fields[..] else {
+                return Err(anyhow!("expected 7 fields"))
+                    .with_context(|| format!("{}:{}", path.display(), number + 1));
+            };
+            let limits = || -> Result<AlarmLimits> {
+                Ok(AlarmLimits {
+                    low: optional(low)?,
+                    high: optional(high)?,
+                    rate: optional(rate)?,
+                    stale: seconds(stale)?,
+                    marker: marker.parse()?,
+                    command: optional(command)?,
+                })
+            };
+            Ok((
+                key.to_string(),
+                limits().with_context(|| format!("{}:{}", path.display(), number + 1))?,
+            ))
+        })
+        .collect()
+}
+
+pub fn save_alarms(path: &Path, alarms: &HashMap<String, AlarmLimits>) -> Result<()> {
+    let field = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let mut keys: Vec<&String> = alarms.keys().collect();
+    keys.sort();
+    let lines: Vec<String> = keys
+        .into_iter()
+        .map(|key| {
+            let a = &alarms[key];
+            format!(
+                "{key}\t{}\t{}\t{}\t{}\t{}\t{}",
+                field(a.low),
+                field(a.high),
+                field(a.rate),
+                field(a.stale.map(|s| s.as_secs_f64())),
+                a.marker,
+                a.command.clone().unwrap_or_default()
+            )
+        })
+        .collect();
+    fs::write(path, lines.join("\n"))
+        .with_context(|| format!("Unable to write alarms {}", path.display()))
+}
+
+/// Draws a cell highlighted because its alarm is active.
+pub struct AlarmCell {
+    pub text: String,
+}
+
+impl DrawDelegate for AlarmCell {
+    fn draw(&self, _row: i32, _col: i32, x: i32, y: i32, w: i32, h: i32, _selected: bool) {
+        draw::push_clip(x, y, w, h);
+        draw::draw_rect_fill(x, y, w, h, Color::Red);
+        draw::set_draw_color(Color::White);
+        draw::draw_text2(&self.text, x + 2, y, w - 4, h, Align::Left);
+        draw::pop_clip();
+    }
+}
+
+/// Edit the alarm limits for a signal. `save` is called with None to remove the alarm.
+pub fn alarm_editor(
+    signal: &str,
+    limits: AlarmLimits,
+    save: impl Fn(Option<AlarmLimits>) -> Result<()> + 'static,
+) {
+    let mut wind = Window::default()
+        .with_size(300, 260)
+        .with_label(&format!("Alarm: {signal}"));
+    let pack = Flex::default_fill()
+        .with_type(PackType::Vertical)
+        .size_of(&wind);
+
+    let field = |label: &str, value: Option<f64>| {
+        let row = Flex::default_fill().with_type(PackType::Horizontal);
+        Frame::default().with_label(label);
+        let mut input = FloatInput::default();
+        input.set_value(&value.map(|v| v.to_string()).unwrap_or_default());
+        row.end();
+        input
+    };
+    let low = field("Low", limits.low);
+    let high = field("High", limits.high);
+    let rate = field("Rate (/s)", limits.rate);
+    let stale = field("Stale (s)", limits.stale.map(|s| s.as_secs_f64()));
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Command");
+    let mut command = Input::default();
+    command.set_value(&limits.command.clone().unwrap_or_default());
+    command.set_tooltip(
+        "Run when the alarm is raised. ALARM_SIGNAL, ALARM_VIOLATION and ALARM_TIME are set.",
+    );
+    row.end();
+
+    let mut marker = CheckButton::default().with_label("Add marker to log");
+    marker.set_checked(limits.marker);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut ok = Button::default().with_label("Save");
+    let mut clear = Button::default().with_label("Remove");
+    row.end();
+
+    pack.end();
+    wind.end();
+    wind.make_modal(true);
+    wind.show();
+
+    let save = Arc::new(save);
+    {
+        let save = save.clone();
+        let mut wind = wind.clone();
+        ok.set_callback(move |_| {
+            let limits = || -> Result<AlarmLimits, Error> {
+                Ok(AlarmLimits {
+                    low: optional(&low.value())?,
+                    high: optional(&high.value())?,
+                    rate: optional(&rate.value())?,
+                    stale: seconds(&stale.value())?,
+                    command: optional(&command.value())?,
+                    marker: marker.is_checked(),
+                })
+            };
+            match limits().and_then(|l| save(Some(l))) {
+                Ok(_) => wind.hide(),
+                Err(err) => alert_default(&format!("Unable to save alarm: {err:#}")),
+            }
+        });
+    }
+    clear.set_callback(move |_| match save(None) {
+        Ok(_) => wind.hide(),
+        Err(err) => alert_default(&format!("Unable to save alarm: {err:#}")),
+    });
+}
+
+/// List alarm events as they are raised.
+pub fn alarm_events_window(title: &str, events: AlarmEvents, timer: &Timer) {
+    let mut wind = Window::default()
+        .with_size(500, 300)
+        .with_label(&format!("Alarm Events: {title}"));
+    let browser = HoldBrowser::default_fill();
+    wind.end();
+    wind.resizable(&browser);
+    wind.show();
+
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(500), move || {
+            let mut browser = browser.clone();
+            let events = events.lock().unwrap();
+            if wind.shown() && browser.size() as usize != events.len() {
+                events
+                    .iter()
+                    .skip(browser.size() as usize)
+                    .for_each(|e| browser.add(&e.to_string()));
+                browser.bottom_line(browser.size());
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: f64, v: f64) -> Option<(Duration, f64)> {
+        Some((Duration::from_secs_f64(s), v))
+    }
+
+    #[test]
+    fn limits() {
+        let limits = AlarmLimits {
+            low: Some(10.0),
+            high: Some(100.0),
+            rate: Some(50.0),
+            stale: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let now = Duration::from_secs(2);
+        assert_eq!(limits.check(None, None, now), None);
+        assert_eq!(limits.check(at(2.0, 50.0), at(1.9, 48.0), now), None);
+        assert_eq!(
+            limits.check(at(2.0, 5.0), None, now),
+            Some(Violation::Low(5.0))
+        );
+        assert_eq!(
+            limits.check(at(2.0, 105.0), None, now),
+            Some(Violation::High(105.0))
+        );
+        assert_eq!(
+            limits.check(at(2.0, 60.0), at(1.5, 20.0), now),
+            Some(Violation::Rate(80.0))
+        );
+        assert_eq!(
+            limits.check(at(0.5, 50.0), None, now),
+            Some(Violation::Stale(Duration::from_millis(1500)))
+        );
+    }
+
+    #[test]
+    fn save_load() {
+        let path = std::env::temp_dir().join(format!("alarm-test-{}.alarms", std::process::id()));
+        let mut alarms = HashMap::new();
+        alarms.insert(
+            "F004 Engine Speed".to_string(),
+            AlarmLimits {
+                high: Some(2500.0),
+                stale: Some(Duration::from_millis(500)),
+                command: Some("echo $ALARM_SIGNAL".into()),
+                marker: true,
+                ..Default::default()
+            },
+        );
+        alarms.insert(
+            "FEEE Engine Coolant Temperature".to_string(),
+            AlarmLimits {
+                low: Some(-40.0),
+                rate: Some(2.0),
+                ..Default::default()
+            },
+        );
+        save_alarms(&path, &alarms).unwrap();
+        let loaded = load_alarms(&path).unwrap();
+        assert_eq!(loaded, alarms);
+
+        // hand edited
+        for stale in ["-1", "inf", "NaN"] {
+            fs::write(
+                &path,
+                format!("\n\nF004 Engine Speed\t\t\t\t{stale}\tfalse\t"),
+            )
+            .unwrap();
+            let err = load_alarms(&path).unwrap_err();
+            assert!(format!("{err:#}").contains(":3: Invalid time"), "{err:#}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use fltk::{
+    browser::HoldBrowser,
+    draw,
+    enums::{Color, Font},
+    frame::Frame,
+    group::{Flex, PackType},
+    input::FloatInput,
+    prelude::{BrowserExt, GroupExt, InputExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{DrawDelegate, Order, SimpleModel},
+};
+use timer::Timer;
+
+use crate::{
+    dbc_table::DbcTables,
+    packet_repo::{channel_key, is_standard, pgn_of, pgn_text, repo_key, sa, sa_text, PacketRepo},
+    stats::open_cycle_times,
+};
+
+/// Periods used to learn the expected period when the DBC doesn't have one.
+const LEARN_PERIODS: usize = 10;
+/// An id is silent after this many expected periods without a packet.
+const SILENT_PERIODS: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// at least one broadcast missing
+    Gap,
+    /// sent at less than half the expected period
+    Burst,
+    /// period outside the tolerance
+    Jitter,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Gap => "Gap",
+            Kind::Burst => "Burst",
+            Kind::Jitter => "Jitter",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    /// time of the packet ending the period
+    pub time: Duration,
+    pub period: Duration,
+    pub kind: Kind,
+}
+
+/// Classify one period. `tolerance` is the allowed jitter as a fraction of `expected`.
+pub fn classify(period: Duration, expected: Duration, tolerance: f64) -> Option<Kind> {
+    let ratio = period.as_secs_f64() / expected.as_secs_f64();
+    if ratio > 1.5 {
+        Some(Kind::Gap)
+    } else if ratio < 0.5 {
+        Some(Kind::Burst)
+    } else if (ratio - 1.0).abs() > tolerance {
+        Some(Kind::Jitter)
+    } else {
+        None
+    }
+}
+
+/// Median period of chronological `times`.
+pub fn median_period(times: &[Duration]) -> Option<Duration> {
+    let mut periods: Vec<Duration> = times
+        .windows(2)
+        .map(|w| w[1].saturating_sub(w[0]))
+        .collect();
+    periods.sort();
+    periods.get(periods.len() / 2).copied()
+}
+
+#[derive(Clone, Debug, Default)]
+struct IdTiming {
+    expected: Option<Duration>,
+    from_dbc: bool,
+    /// packets already checked
+    processed: usize,
+    anomalies: Vec<Anomaly>,
+    last: Duration,
+}
+
+impl IdTiming {
+    fn count(&self, kind: Kind) -> usize {
+        self.anomalies.iter().filter(|a| a.kind == kind).count()
+    }
+
+    fn silent(&self, now: Duration) -> bool {
+        self.expected
+            .is_some_and(|e| now.saturating_sub(self.last) > e * SILENT_PERIODS)
+    }
+}
+
+/// (channel, `PacketRepo` key)
+type Key = (u8, u32);
+
+/// Learns the period of every id on each channel and records packets that break it.
+#[derive(Clone, Debug)]
+pub struct Detector {
+    ids: BTreeMap<Key, IdTiming>,
+    /// id -> DBC cycle time
+    cycle_times: HashMap<u32, Duration>,
+    tolerance: f64,
+    /// packets added so far
+    scanned: usize,
+    /// `PacketRepo::generation` when last scanned
+    generation: usize,
+    /// latest packet time, and when it was read
+    latest: Option<(Duration, Instant)>,
+}
+
+impl Detector {
+    pub fn new(cycle_times: HashMap<u32, Duration>, tolerance: f64) -> Detector {
+        Detector {
+            ids: BTreeMap::new(),
+            cycle_times,
+            tolerance,
+            scanned: 0,
+            generation: 0,
+            latest: None,
+        }
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// Change the tolerance and check everything again.
+    pub fn set_tolerance(&mut self, tolerance: f64) {
+        self.tolerance = tolerance;
+        for state in self.ids.values_mut() {
+            *state = IdTiming::default();
+        }
+    }
+
+    /// Check the packets received since the last update. Each channel is checked on its own,
+    /// as packets from several channels are not in time order.
+    pub fn update(&mut self, repo: &PacketRepo) {
+        if repo.generation() != self.generation || repo.packets().len() < self.scanned {
+            // cleared
+            self.ids.clear();
+            self.scanned = 0;
+            self.latest = None;
+            self.generation = repo.generation();
+        }
+        let new = repo.packets()[self.scanned..]
+            .iter()
+            .zip(&repo.channels()[self.scanned..]);
+        for (p, channel) in new {
+            self.ids.entry((*channel, repo_key(p.id()))).or_default();
+            let time = p.time().unwrap_or_default();
+            if self.latest.is_none_or(|(latest, _)| time > latest) {
+                self.latest = Some((time, Instant::now()));
+            }
+        }
+        self.scanned = repo.packets().len();
+        let keys: Vec<Key> = self.ids.keys().copied().collect();
+        for key in keys {
+            let times: Vec<Duration> = repo
+                .get_for(channel_key(Some(key.0), key.1))
+                .map(|v| v.iter().map(|p| p.time().unwrap_or_default()).collect())
+                .unwrap_or_default();
+            self.check(key, &times);
+        }
+    }
+
+    /// Packet time now, going by the wall clock since the latest packet, so that a bus that
+    /// went quiet is seen as silent.
+    pub fn now(&self) -> Duration {
+        self.latest
+            .map_or(Duration::ZERO, |(time, at)| time + at.elapsed())
+    }
+
+    /// Check new entries in the chronological `times` for an id on a channel.
+    pub fn check(&mut self, key: Key, times: &[Duration]) {
+        let state = self.ids.entry(key).or_default();
+        if times.len() < state.processed {
+            *state = IdTiming::default();
+        }
+        if state.expected.is_none() {
+            if let Some(e) = self.cycle_times.get(&key.1) {
+                state.expected = Some(*e);
+                state.from_dbc = true;
+            } else if times.len() > LEARN_PERIODS {
+                state.expected = median_period(&times[..=LEARN_PERIODS]);
+            }
+        }
+        state.last = times.last().copied().unwrap_or_default();
+        let Some(expected) = state.expected.filter(|e| !e.is_zero()) else {
+            return;
+        };
+        for i in state.processed.max(1)..times.len() {
+            let period = times[i].saturating_sub(times[i - 1]);
+            if let Some(kind) = classify(period, expected, self.tolerance) {
+                state.anomalies.push(Anomaly {
+                    time: times[i],
+                    period,
+                    kind,
+                });
+            }
+        }
+        state.processed = times.len();
+    }
+
+    pub fn anomalies(&self, key: Key) -> &[Anomaly] {
+        self.ids.get(&key).map_or(&[], |s| s.anomalies.as_slice())
+    }
+
+    /// (channel, source address) where every id with a known period has stopped, with the
+    /// time they were last heard.
+    pub fn silent(&self, now: Duration) -> Vec<(u8, u8, Duration)> {
+        let mut ecus: BTreeMap<(u8, u8), (bool, Duration)> = BTreeMap::new();
+        for ((channel, id), state) in &self.ids {
+            // 11 bit ids have no source address
+            if state.expected.is_none() || is_standard(*id) {
+                continue;
+            }
+            let ecu = ecus
+                .entry((*channel, sa(*id)))
+                .or_insert((true, Duration::ZERO));
+            ecu.0 &= state.silent(now);
+            ecu.1 = ecu.1.max(state.last);
+        }
+        ecus.into_iter()
+            .filter(|(_, (silent, _))| *silent)
+            .map(|((channel, sa), (_, last))| (channel, sa, last))
+            .collect()
+    }
+}
+
+/// Marks for each anomaly across the log.
+struct Timeline {
+    /// (fraction of the log, kind)
+    marks: Vec<(f64, Kind)>,
+}
+
+impl DrawDelegate for Timeline {
+    fn draw(&self, _row: i32, _col: i32, x: i32, y: i32, w: i32, h: i32, _selected: bool) {
+        draw::push_clip(x, y, w, h);
+        draw::draw_rect_fill(x, y, w, h, Color::White);
+        for (f, kind) in &self.marks {
+            draw::set_draw_color(match kind {
+                Kind::Gap => Color::Red,
+                Kind::Burst => Color::Blue,
+                Kind::Jitter => Color::DarkYellow,
+            });
+            let mx = x + (f * (w - 1) as f64) as i32;
+            draw::draw_line(mx, y + 1, mx, y + h - 2);
+        }
+        draw::pop_clip();
+    }
+}
+
+const COLUMNS: [&str; 10] = [
+    "Ch",
+    "PGN",
+    "SA",
+    "Expected (ms)",
+    "From",
+    "Gaps",
+    "Bursts",
+    "Jitter",
+    "Last Seen",
+    "Timeline",
+];
+
+/// Anomalies for each id, updated from the repo.
+pub struct AnomalyModel {
+    packets: Arc<RwLock<PacketRepo>>,
+    pub detector: Detector,
+    rows: Vec<Key>,
+    sort: Option<(usize, Order)>,
+}
+
+impl AnomalyModel {
+    pub fn new(packets: Arc<RwLock<PacketRepo>>, detector: Detector) -> AnomalyModel {
+        AnomalyModel {
+            packets,
+            detector,
+            rows: Vec::new(),
+            sort: None,
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.detector.update(&self.packets.read().unwrap());
+        self.rows = self.detector.ids.keys().copied().collect();
+        if let Some((col, order)) = self.sort.take() {
+            self.sort(col, order);
+        }
+    }
+
+    /// ECUs that went silent, as text.
+    pub fn silent(&self) -> Vec<String> {
+        self.detector
+            .silent(self.detector.now())
+            .into_iter()
+            .map(|(channel, sa, last)| {
+                format!(
+                    "Ch {channel} SA {sa:02X} silent since {:.3} s",
+                    last.as_secs_f64()
+                )
+            })
+            .collect()
+    }
+}
+
+impl SimpleModel for AnomalyModel {
+    fn row_count(&mut self) -> usize {
+        self.rows.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 30,
+            1 | 2 => 50,
+            4 => 60,
+            9 => 300,
+            _ => 80,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let key = *self.rows.get(row as usize)?;
+        let (channel, id) = key;
+        let state = self.detector.ids.get(&key)?;
+        match col {
+            0 => Some(channel.to_string()),
+            1 => Some(pgn_text(id)),
+            2 => Some(sa_text(id)),
+            3 => Some(
+                state
+                    .expected
+                    .map(|e| format!("{:0.1}", e.as_secs_f64() * 1000.0))
+                    .unwrap_or_default(),
+            ),
+            4 => Some(
+                match (state.expected, state.from_dbc) {
+                    (None, _) => "",
+                    (Some(_), true) => "DBC",
+                    (Some(_), false) => "Median",
+                }
+                .into(),
+            ),
+            5 => Some(state.count(Kind::Gap).to_string()),
+            6 => Some(state.count(Kind::Burst).to_string()),
+            7 => Some(state.count(Kind::Jitter).to_string()),
+            8 => Some(format!("{:.3}", state.last.as_secs_f64())),
+            _ => None,
+        }
+    }
+
+    fn cell_delegate(&mut self, row: i32, col: i32) -> Option<Box<dyn DrawDelegate>> {
+        if col != 9 {
+            return None;
+        }
+        let key = *self.rows.get(row as usize)?;
+        let (start, end) = {
+            let repo = self.packets.read().unwrap();
+            (repo.first_time(), repo.last_time())
+        };
+        let span = end.saturating_sub(start).as_secs_f64().max(f64::EPSILON);
+        let marks = self
+            .detector
+            .anomalies(key)
+            .iter()
+            .map(|a| (a.time.saturating_sub(start).as_secs_f64() / span, a.kind))
+            .collect();
+        Some(Box::new(Timeline { marks }) as Box<dyn DrawDelegate>)
+    }
+
+    fn sort(&mut self, col: usize, order: Order) {
+        let ids = &self.detector.ids;
+        let key = |key: &Key| -> (u128, Key) {
+            let state = &ids[key];
+            let (channel, id) = *key;
+            let n = match col {
+                0 => channel as u128,
+                1 => pgn_of(id) as u128,
+                2 => sa(id) as u128,
+                3 => state.expected.unwrap_or_default().as_micros(),
+                4 => state.from_dbc as u128,
+                5 => state.count(Kind::Gap) as u128,
+                6 => state.count(Kind::Burst) as u128,
+                7 => state.count(Kind::Jitter) as u128,
+                8 => state.last.as_micros(),
+                _ => state.anomalies.len() as u128,
+            };
+            (n, *key)
+        };
+        match order {
+            Order::None => {
+                self.rows.sort();
+                self.sort = None;
+                return;
+            }
+            Order::Ascending => self.rows.sort_by_key(key),
+            Order::Descending => self.rows.sort_by_key(|id| std::cmp::Reverse(key(id))),
+        }
+        self.sort = Some((col, order));
+    }
+}
+
+/// Per id timing anomalies and silent ECUs, updated every second.
+pub fn anomaly_window(packets: Arc<RwLock<PacketRepo>>, dbc_tables: DbcTables, timer: &Timer) {
+    let mut wind = Window::default()
+        .with_size(800, 500)
+        .with_label("Timing Anomalies");
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Jitter Tolerance (%)");
+    let mut tolerance = FloatInput::default();
+    tolerance.set_value("25");
+    tolerance.set_tooltip("Allowed period error. Gaps over 150% and bursts under 50% of the expected period are always flagged.");
+    Frame::default()
+        .with_label("Gap")
+        .set_label_color(Color::Red);
+    Frame::default()
+        .with_label("Burst")
+        .set_label_color(Color::Blue);
+    Frame::default()
+        .with_label("Jitter")
+        .set_label_color(Color::DarkYellow);
+    row.end();
+    flex.fixed(&row, 25);
+
+    let detector = Detector::new(open_cycle_times(&dbc_tables), 0.25);
+    let mut table = JoeTable::new(AnomalyModel::new(packets, detector));
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+
+    let label = Frame::default().with_label("Silent ECUs");
+    flex.fixed(&label, 20);
+    let mut silent = HoldBrowser::default();
+    flex.fixed(&silent, 100);
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    table.redraw_on(timer, chrono::Duration::milliseconds(500));
+
+    let model = table.model.clone();
+    timer
+        .schedule_repeating(chrono::Duration::seconds(1), move || {
+            if !wind.shown() {
+                return;
+            }
+            let mut model = model.lock().unwrap();
+            if let Ok(t) = tolerance.value().parse::<f64>() {
+                let t = t / 100.0;
+                if t != model.detector.tolerance() {
+                    model.detector.set_tolerance(t);
+                }
+            }
+            model.update();
+            silent.clear();
+            for line in model.silent() {
+                silent.add(&line);
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use can_adapter::j1939::j1939_packet::J1939Packet;
+
+    use super::*;
+
+    fn ms(ms: &[u64]) -> Vec<Duration> {
+        ms.iter().map(|m| Duration::from_millis(*m)).collect()
+    }
+
+    #[test]
+    fn learns_median() {
+        let mut d = Detector::new(HashMap::new(), 0.25);
+        let mut times: Vec<u64> = (0..=10).map(|i| i * 100).collect();
+        d.check((0, 1), &ms(&times));
+        assert_eq!(d.ids[&(0, 1)].expected, Some(Duration::from_millis(100)));
+        assert!(d.anomalies((0, 1)).is_empty());
+
+        // gap, burst and jitter
+        times.extend([1300, 1320, 1450]);
+        d.check((0, 1), &ms(&times));
+        let kinds: Vec<Kind> = d.anomalies((0, 1)).iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, [Kind::Gap, Kind::Burst, Kind::Jitter]);
+        assert_eq!(d.anomalies((0, 1))[0].period, Duration::from_millis(300));
+
+        // only new packets are checked
+        times.push(1550);
+        d.check((0, 1), &ms(&times));
+        assert_eq!(d.anomalies((0, 1)).len(), 3);
+    }
+
+    #[test]
+    fn dbc_period_and_silence() {
+        let cycle = HashMap::from([(0x18FEF100, Duration::from_millis(100))]);
+        let mut d = Detector::new(cycle, 0.25);
+        d.check((0, 0x18FEF100), &ms(&[0, 100, 250]));
+        assert!(d.ids[&(0, 0x18FEF100)].from_dbc);
+        assert_eq!(d.anomalies((0, 0x18FEF100)).len(), 1);
+        // an id without a known period doesn't make the ECU silent
+        d.check((0, 0x18FEF200), &ms(&[0, 1000]));
+
+        assert!(d.silent(Duration::from_millis(500)).is_empty());
+        assert_eq!(
+            d.silent(Duration::from_millis(800)),
+            [(0, 0x00, Duration::from_millis(250))]
+        );
+    }
+
+    #[test]
+    fn channels() {
+        let mut repo = PacketRepo::default();
+        let mut push = |channel: u8, ms: u64| {
+            let time = Some(Duration::from_millis(ms));
+            repo.push_from(channel, J1939Packet::new(time, 0, 0x18FEF100, &[]), 0);
+        };
+        // channel 1 is read behind channel 0, and its ECU stops at 1s
+        for i in 0..30u64 {
+            push(0, 500 + 100 * i);
+            if i <= 10 {
+                push(1, 100 * i);
+            }
+        }
+        let mut d = Detector::new(HashMap::new(), 0.25);
+        d.update(&repo);
+        assert!(d.anomalies((0, 0xFEF100)).is_empty());
+        assert!(d.anomalies((1, 0xFEF100)).is_empty());
+        assert_eq!(d.ids.len(), 2);
+        assert_eq!(d.now().as_millis() / 100, 34);
+        assert_eq!(
+            d.silent(Duration::from_millis(3400)),
+            [(1, 0x00, Duration::from_secs(1))]
+        );
+
+        // the bus going quiet counts
+        d.latest = Some((
+            Duration::from_millis(3400),
+            Instant::now() - Duration::from_secs(1),
+        ));
+        assert_eq!(d.silent(d.now()).len(), 2);
+
+        repo.clear();
+        d.update(&repo);
+        assert!(d.ids.is_empty());
+    }
+}
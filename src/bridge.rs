@@ -0,0 +1,505 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use can_adapter::j1939::j1939_packet::J1939Packet;
+use fltk::{
+    browser::HoldBrowser,
+    button::Button,
+    dialog::alert_default,
+    enums::Align,
+    frame::Frame,
+    group::{Flex, PackType},
+    input::Input,
+    menu::Choice,
+    prelude::{BrowserExt, GroupExt, InputExt, MenuExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use timer::Timer;
+
+use crate::{
+    channel::{self, Connections},
+    packet_repo::{ide_id, is_standard, pgn_of, sa, PacketRepo},
+};
+
+/// How long a forwarded frame may take to come back from the adapter it was sent on.
+const ECHO_TIME: Duration = Duration::from_secs(1);
+
+const DIRECTIONS: [&str; 2] = ["A to B", "B to A"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Allow,
+    Deny,
+    /// byte = (byte & !mask) | (value & mask)
+    Rewrite {
+        byte: usize,
+        value: u8,
+        mask: u8,
+    },
+}
+
+/// A forwarding rule. 11 bit ids match a rule by id, with any SA.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub pgn: Option<u32>,
+    pub sa: Option<u8>,
+    pub action: Action,
+}
+
+impl Rule {
+    fn matches(&self, id: u32) -> bool {
+        if is_standard(id) {
+            self.sa.is_none() && self.pgn.is_none_or(|p| p == id & 0x7FF)
+        } else {
+            self.pgn.is_none_or(|p| p == pgn_of(id)) && self.sa.is_none_or(|s| s == sa(id))
+        }
+    }
+}
+
+fn hex_or_any(v: Option<u32>, width: usize) -> String {
+    v.map_or("*".to_string(), |v| format!("{v:0width$X}"))
+}
+
+fn parse_hex(s: &str) -> Result<Option<u32>, Error> {
+    let s = s.trim();
+    Ok(if s == "*" || s.is_empty() {
+        None
+    } else {
+        Some(u32::from_str_radix(s, 16)?)
+    })
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filter = format!(
+            "{}:{}",
+            hex_or_any(self.pgn, 4),
+            hex_or_any(self.sa.map(u32::from), 2)
+        );
+        match self.action {
+            Action::Allow => write!(f, "allow {filter}"),
+            Action::Deny => write!(f, "deny {filter}"),
+            Action::Rewrite { byte, value, mask } => {
+                write!(f, "rewrite {filter} {byte}={value:02X}")?;
+                if mask != 0xFF {
+                    write!(f, "/{mask:02X}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let action = words.next().unwrap_or_default();
+        let (pgn, sa) = words
+            .next()
+            .and_then(|f| f.split_once(':'))
+            .ok_or(anyhow!("expected PGN:SA"))?;
+        let action = match action {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            "rewrite" => {
+                let (byte, value) = words
+                    .next()
+                    .and_then(|w| w.split_once('='))
+                    .ok_or(anyhow!("expected BYTE=VALUE[/MASK]"))?;
+                let (value, mask) = value.split_once('/').unwrap_or((value, "FF"));
+                Action::Rewrite {
+                    byte: byte.parse()?,
+                    value: u8::from_str_radix(value, 16)?,
+                    mask: u8::from_str_radix(mask, 16)?,
+                }
+            }
+            _ => return Err(anyhow!("expected allow, deny or rewrite")),
+        };
+        if words.next().is_some() {
+            return Err(anyhow!("unexpected text after rule"));
+        }
+        Ok(Rule {
+            pgn: parse_hex(pgn)?,
+            sa: parse_hex(sa)?
+                .map(u8::try_from)
+                .transpose()
+                .map_err(|_| anyhow!("SA is one byte"))?,
+            action,
+        })
+    }
+}
+
+/// Counts and forwarding latency for one direction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub forwarded: usize,
+    pub blocked: usize,
+    pub rewritten: usize,
+    pub errors: usize,
+    pub min: Option<Duration>,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl Stats {
+    fn sent(&mut self, latency: Duration) {
+        self.forwarded += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+        self.min = Some(self.min.map_or(latency, |m| m.min(latency)));
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} forwarded, {} blocked, {} rewritten, {} errors",
+            self.forwarded, self.blocked, self.rewritten, self.errors
+        )?;
+        if let Some(min) = self.min {
+            let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+            write!(
+                f,
+                ", latency {:.2}/{:.2}/{:.2} ms (min/mean/max)",
+                ms(min),
+                ms(self.total / self.forwarded as u32),
+                ms(self.max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Forwards frames between two channels. Both sides are logged: frames read on either
+/// channel by its reader, and forwarded frames when they are sent.
+#[derive(Clone, Debug, Default)]
+pub struct Bridge {
+    /// channels A and B, None when the bridge is off
+    pub sides: Option<(u8, u8)>,
+    /// rules for A to B and B to A, in order
+    pub rules: [Vec<Rule>; 2],
+    pub stats: [Stats; 2],
+    /// last failure to forward a frame
+    pub error: Option<String>,
+    /// frames sent, until the adapter echoes them: (channel, id, payload, when)
+    sent: Vec<(u8, u32, Vec<u8>, Instant)>,
+}
+
+impl Bridge {
+    /// Start forwarding between `a` and `b`, clearing the statistics.
+    pub fn start(&mut self, a: u8, b: u8) {
+        self.sides = Some((a, b));
+        self.stats = Default::default();
+        self.error = None;
+        self.sent.clear();
+    }
+    pub fn stop(&mut self) {
+        self.sides = None;
+    }
+
+    /// Where a frame read on `channel` goes: the direction, destination channel and
+    /// payload after rewrites. None if the bridge doesn't forward it.
+    pub fn route(&mut self, channel: u8, id: u32, payload: &[u8]) -> Option<(usize, u8, Vec<u8>)> {
+        let (a, b) = self.sides?;
+        let (direction, to) = match channel {
+            c if c == a => (0, b),
+            c if c == b => (1, a),
+            _ => return None,
+        };
+        let mut payload = payload.to_vec();
+        let mut rewritten = false;
+        let mut allowed = true;
+        for rule in self.rules[direction].iter().filter(|r| r.matches(id)) {
+            match rule.action {
+                Action::Allow => break,
+                Action::Deny => {
+                    allowed = false;
+                    break;
+                }
+                Action::Rewrite { byte, value, mask } => {
+                    if let Some(b) = payload.get_mut(byte) {
+                        *b = (*b & !mask) | (value & mask);
+                        rewritten = true;
+                    }
+                }
+            }
+        }
+        let stats = &mut self.stats[direction];
+        if !allowed {
+            stats.blocked += 1;
+            return None;
+        }
+        if rewritten {
+            stats.rewritten += 1;
+        }
+        let now = Instant::now();
+        self.sent
+            .retain(|(_, _, _, when)| now.duration_since(*when) < ECHO_TIME);
+        self.sent.push((to, id, payload.clone(), now));
+        Some((direction, to, payload))
+    }
+
+    /// True for a frame read on `channel` that the bridge sent there, which has already
+    /// been logged.
+    pub fn is_echo(&mut self, channel: u8, id: u32, payload: &[u8]) -> bool {
+        let Some(i) = self
+            .sent
+            .iter()
+            .position(|(c, i, p, _)| *c == channel && *i == id && p == payload)
+        else {
+            return false;
+        };
+        self.sent.remove(i);
+        true
+    }
+
+    pub fn status(&self) -> String {
+        let mut status = match self.sides {
+            Some((a, b)) => format!(
+                "A ({a}) to B ({b}): {}\nB ({b}) to A ({a}): {}",
+                self.stats[0], self.stats[1]
+            ),
+            None => "Stopped".into(),
+        };
+        if let Some(err) = &self.error {
+            status += &format!("\nLast error: {err}");
+        }
+        status
+    }
+}
+
+/// Forward a frame read from `channel`, if the bridge is on and the rules allow it.
+pub fn forward(
+    bridge: &Mutex<Bridge>,
+    connections: &Connections,
+    packets: &RwLock<PacketRepo>,
+    channel: u8,
+    p: &J1939Packet,
+) {
+    let start = Instant::now();
+    let Some((direction, to, payload)) = bridge.lock().unwrap().route(channel, p.id(), &p.payload)
+    else {
+        return;
+    };
+    let out = J1939Packet::new(p.time(), 0, p.id(), &payload);
+    let result = channel::send(
+        connections,
+        to,
+        &J1939Packet::new(p.time(), 0, ide_id(p.id()), &payload),
+    );
+    let mut bridge = bridge.lock().unwrap();
+    match result {
+        Ok(_) => {
+            bridge.stats[direction].sent(start.elapsed());
+            packets.write().unwrap().push_from(to, out, 0);
+        }
+        Err(err) => {
+            bridge.stats[direction].errors += 1;
+            bridge.error = Some(format!("{} {err:#}", DIRECTIONS[direction]));
+        }
+    }
+}
+
+/// Choose the two channels and edit the rules for each direction.
+pub fn bridge_window(bridge: Arc<Mutex<Bridge>>, connections: Connections, timer: &Timer) {
+    let mut wind = Window::default().with_size(700, 400).with_label("Bridge");
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+
+    let mut sides = Flex::default_fill().with_type(PackType::Horizontal);
+    let label = Frame::default().with_label("A");
+    sides.fixed(&label, 20);
+    let mut a = Choice::default();
+    let label = Frame::default().with_label("B");
+    sides.fixed(&label, 20);
+    let mut b = Choice::default();
+    let numbers: Vec<u8> = connections
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            let name = c.name().replace('/', "\\/");
+            a.add_choice(&name);
+            b.add_choice(&name);
+            c.number
+        })
+        .collect();
+    a.set_value(0);
+    b.set_value(if numbers.len() > 1 { 1 } else { 0 });
+    let mut start = Button::default().with_label("Start");
+    if numbers.len() < 2 {
+        start.deactivate();
+        start.set_tooltip("Open two channels to bridge them.");
+    }
+    let mut stop = Button::default().with_label("Stop");
+    sides.end();
+    flex.fixed(&sides, 25);
+
+    let rules = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut direction = Choice::default();
+    direction.add_choice(&DIRECTIONS.join("|"));
+    direction.set_value(0);
+    let rule = Input::default();
+    let mut add = Button::default().with_label("Add");
+    let mut remove = Button::default().with_label("Remove");
+    rules.end();
+    flex.fixed(&rules, 25);
+
+    let help = Frame::default().with_label(
+        "allow|deny PGN:SA or rewrite PGN:SA BYTE=VALUE[/MASK], hex, * for any. First allow or deny wins.",
+    );
+    flex.fixed(&help, 20);
+    let browser = HoldBrowser::default();
+    let mut status = Frame::default().with_align(Align::Left | Align::Inside);
+    flex.fixed(&status, 60);
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    let update = {
+        let bridge = bridge.clone();
+        let browser = browser.clone();
+        move || {
+            let mut browser = browser.clone();
+            browser.clear();
+            let bridge = bridge.lock().unwrap();
+            for (d, rules) in bridge.rules.iter().enumerate() {
+                rules
+                    .iter()
+                    .for_each(|r| browser.add(&format!("{}: {r}", DIRECTIONS[d])));
+            }
+        }
+    };
+    update();
+    {
+        let bridge = bridge.clone();
+        start.set_callback(move |_| {
+            let side = |c: &Choice| numbers.get(c.value() as usize).copied();
+            match (side(&a), side(&b)) {
+                (Some(a), Some(b)) if a != b => bridge.lock().unwrap().start(a, b),
+                _ => alert_default("Select two different channels."),
+            }
+        });
+    }
+    {
+        let bridge = bridge.clone();
+        stop.set_callback(move |_| bridge.lock().unwrap().stop());
+    }
+    {
+        let bridge = bridge.clone();
+        let update = update.clone();
+        add.set_callback(move |_| match rule.value().parse::<Rule>() {
+            Ok(r) => {
+                let d = direction.value().clamp(0, 1) as usize;
+                bridge.lock().unwrap().rules[d].push(r);
+                update();
+            }
+            Err(err) => alert_default(&format!("Invalid rule: {err}")),
+        });
+    }
+    {
+        let bridge = bridge.clone();
+        let browser = browser.clone();
+        remove.set_callback(move |_| {
+            let Some(mut i) = (browser.value() as usize).checked_sub(1) else {
+                return;
+            };
+            {
+                let mut bridge = bridge.lock().unwrap();
+                for rules in &mut bridge.rules {
+                    if i < rules.len() {
+                        rules.remove(i);
+                        break;
+                    }
+                    i -= rules.len();
+                }
+            }
+            update();
+        });
+    }
+
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(500), move || {
+            if wind.shown() {
+                status.set_label(&bridge.lock().unwrap().status());
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_text() {
+        for s in [
+            "allow FEF1:*",
+            "deny *:00",
+            "rewrite F004:00 3=7D",
+            "rewrite *:* 0=10/F0",
+        ] {
+            assert_eq!(s.parse::<Rule>().unwrap().to_string(), s);
+        }
+        assert!("allow FEF1".parse::<Rule>().is_err());
+        assert!("block *:*".parse::<Rule>().is_err());
+        assert!("deny *:100".parse::<Rule>().is_err());
+        assert!("rewrite *:* 3".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn routing() {
+        let mut bridge = Bridge::default();
+        assert_eq!(bridge.route(0, 0x18FEF100, &[1]), None);
+        bridge.rules[0] = ["rewrite *:00 1=F0/F0", "allow FEF1:*", "deny *:00"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect();
+        bridge.start(0, 1);
+
+        // allowed after the rewrite
+        assert_eq!(
+            bridge.route(0, 0x18FEF100, &[1, 2]),
+            Some((0, 1, vec![1, 0xF2]))
+        );
+        assert_eq!(bridge.route(0, 0x0CF00400, &[1, 2]), None);
+        // not from the A side
+        assert_eq!(bridge.route(2, 0x0CF00400, &[1]), None);
+        // no rules from B
+        assert_eq!(bridge.route(1, 0x0CF00400, &[3]), Some((1, 0, vec![3])));
+        // 11 bit ids only match rules for any SA
+        assert_eq!(bridge.route(0, 0x7DF, &[2]), Some((0, 1, vec![2])));
+        assert_eq!((bridge.stats[0].rewritten, bridge.stats[0].blocked), (1, 1));
+
+        // the adapter echoes what was sent on B once
+        assert!(bridge.is_echo(1, 0x18FEF100, &[1, 0xF2]));
+        assert!(!bridge.is_echo(1, 0x18FEF100, &[1, 0xF2]));
+        assert!(!bridge.is_echo(0, 0x0CF00400, &[1]));
+
+        bridge.error = Some("A to B Channel 1 is closed.".into());
+        assert!(bridge
+            .status()
+            .ends_with("\nLast error: A to B Channel 1 is closed."));
+        bridge.start(0, 1);
+        assert_eq!(bridge.error, None);
+    }
+
+    #[test]
+    fn latency() {
+        let mut stats = Stats::default();
+        stats.sent(Duration::from_millis(1));
+        stats.sent(Duration::from_millis(3));
+        assert_eq!(
+            stats.to_string(),
+            "2 forwarded, 0 blocked, 0 rewritten, 0 errors, latency 1.00/2.00/3.00 ms (min/mean/max)"
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use can_adapter::{connection::Connection, j1939::j1939_packet::J1939Packet};
+use fltk::{
+    browser::HoldBrowser,
+    button::Button,
+    group::{Flex, PackType},
+    prelude::{BrowserExt, GroupExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+
+use crate::session::AdapterChoice;
+
+/// Channel numbers are 0 to 14, so they fit in a `packet_repo::channel_key`.
+pub const MAX_CHANNELS: u8 = 15;
+
+/// Time between attempts to reopen a lost adapter.
+const RETRY: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum State {
+    Connected,
+    /// the adapter stopped, waiting to reopen it
+    Reconnecting,
+    /// the last attempt to reopen failed
+    Error(String),
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Connected => write!(f, "connected"),
+            State::Reconnecting => write!(f, "reconnecting"),
+            State::Error(err) => write!(f, "error: {err}"),
+        }
+    }
+}
+
+/// Connection health, updated by the channel's reader and the reconnect loop.
+#[derive(Clone, Debug)]
+pub struct Health {
+    pub state: State,
+    pub frames: usize,
+    /// failed sends, reads and attempts to reopen. Adapters report a failed read by ending
+    /// the read, so each lost adapter is one error.
+    pub errors: usize,
+    pub reconnects: usize,
+    /// frames per second
+    pub rate: f64,
+    /// when the adapter was lost
+    pub lost: Option<Instant>,
+    /// last attempt to reopen the adapter
+    attempt: Option<Instant>,
+    /// frames at the last rate update
+    counted: (Instant, usize),
+    /// packet time of the last frame read, and when it was read
+    last_frame: Option<(Duration, Instant)>,
+}
+
+impl Health {
+    pub fn new(now: Instant) -> Health {
+        Health {
+            state: State::Connected,
+            frames: 0,
+            errors: 0,
+            reconnects: 0,
+            rate: 0.0,
+            lost: None,
+            attempt: None,
+            counted: (now, 0),
+            last_frame: None,
+        }
+    }
+    /// Count a frame read, with its packet time.
+    pub fn frame(&mut self, time: Duration) {
+        self.frames += 1;
+        self.last_frame = Some((time, Instant::now()));
+    }
+    /// Packet time at `at`, going by the wall clock since the last frame. None before the
+    /// first frame.
+    pub fn packet_time(&self, at: Instant) -> Option<Duration> {
+        self.last_frame
+            .map(|(time, read)| time + at.saturating_duration_since(read))
+    }
+    /// Update the rate about once a second.
+    pub fn tick(&mut self, now: Instant) {
+        let (since, count) = self.counted;
+        let elapsed = now.duration_since(since);
+        if elapsed >= Duration::from_secs(1) {
+            self.rate = (self.frames - count) as f64 / elapsed.as_secs_f64();
+            self.counted = (now, self.frames);
+        }
+    }
+    /// True when a lost adapter should be tried again.
+    fn retry_due(&self, now: Instant) -> bool {
+        self.state != State::Connected
+            && self.attempt.is_none_or(|a| now.duration_since(a) >= RETRY)
+    }
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {:.0} frames/s, {} errors",
+            self.state, self.rate, self.errors
+        )?;
+        if self.reconnects > 0 {
+            write!(f, ", {} reconnects", self.reconnects)?;
+        }
+        Ok(())
+    }
+}
+
+/// An open adapter. Each one is read on its own thread and its packets are tagged with
+/// `number`.
+#[derive(Clone)]
+pub struct Channel {
+    pub number: u8,
+    pub adapter: AdapterChoice,
+    /// None while the adapter is being reopened
+    pub connection: Option<Arc<dyn Connection>>,
+    pub health: Arc<Mutex<Health>>,
+}
+
+impl Channel {
+    pub fn name(&self) -> String {
+        match &self.adapter {
+            AdapterChoice::Menu(name) => format!("{}: {name}", self.number),
+            AdapterChoice::Args(args) => format!("{}: {}", self.number, args.join(" ")),
+        }
+    }
+    /// True while reading from `connection`.
+    pub fn is(&self, connection: &Arc<dyn Connection>) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(|c| Arc::ptr_eq(c, connection))
+    }
+}
+
+/// The open adapters. Shared so other windows can send while the log is being read.
+pub type Connections = Arc<Mutex<Vec<Channel>>>;
+
+/// Lowest channel number not in `used`.
+pub fn next_number(used: &[u8]) -> Option<u8> {
+    (0..MAX_CHANNELS).find(|n| !used.contains(n))
+}
+
+/// Add an adapter as a new channel, returning its number.
+pub fn open(
+    connections: &Connections,
+    adapter: AdapterChoice,
+    connection: Box<dyn Connection>,
+) -> Result<u8> {
+    let mut channels = connections.lock().unwrap();
+    if channels.iter().any(|c| c.adapter == adapter) {
+        return Err(anyhow!("Adapter is already open."));
+    }
+    let used: Vec<u8> = channels.iter().map(|c| c.number).collect();
+    let number = next_number(&used).ok_or(anyhow!("Too many channels."))?;
+    channels.push(Channel {
+        number,
+        adapter,
+        connection: Some(connection.into()),
+        health: Arc::new(Mutex::new(Health::new(Instant::now()))),
+    });
+    channels.sort_by_key(|c| c.number);
+    Ok(number)
+}
+
+/// Close a channel. Its reader stops before the next packet.
+pub fn close(connections: &Connections, number: u8) {
+    connections.lock().unwrap().retain(|c| c.number != number);
+}
+
+/// Connection of an open channel.
+pub fn get(connections: &Connections, number: u8) -> Option<Arc<dyn Connection>> {
+    connections
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|c| c.number == number)
+        .and_then(|c| c.connection.clone())
+}
+
+/// Send on a channel, counting failures in its health.
+pub fn send(connections: &Connections, number: u8, packet: &J1939Packet) -> Result<()> {
+    let channel = connections
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|c| c.number == number)
+        .cloned()
+        .ok_or(anyhow!("Channel {number} is closed."))?;
+    let connection = channel
+        .connection
+        .ok_or(anyhow!("Channel {number} is reconnecting."))?;
+    connection
+        .send(packet)
+        .inspect_err(|_| channel.health.lock().unwrap().errors += 1)?;
+    Ok(())
+}
+
+/// The reader of `connection` stopped. If its channel is still open the adapter was lost,
+/// so it is closed and the channel waits to reopen it. Returns the channel.
+pub fn lost(connections: &Connections, connection: &Arc<dyn Connection>) -> Option<Channel> {
+    let mut channels = connections.lock().unwrap();
+    let channel = channels.iter_mut().find(|c| c.is(connection))?;
+    channel.connection = None;
+    let mut health = channel.health.lock().unwrap();
+    health.state = State::Reconnecting;
+    health.errors += 1;
+    health.lost = Some(Instant::now());
+    health.attempt = None;
+    drop(health);
+    Some(channel.clone())
+}
+
+/// Lost channels due for another attempt to reopen their adapter.
+pub fn retry_due(connections: &Connections) -> Vec<(u8, AdapterChoice)> {
+    let now = Instant::now();
+    connections
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|c| c.connection.is_none() && c.health.lock().unwrap().retry_due(now))
+        .map(|c| (c.number, c.adapter.clone()))
+        .collect()
+}
+
+/// Record an attempt to reopen a channel's adapter. When it was reopened, returns how long
+/// the channel was down and the packet time it came back, if it read frames before.
+pub fn reopened(
+    connections: &Connections,
+    number: u8,
+    result: Result<Box<dyn Connection>>,
+) -> Option<(Duration, Option<Duration>)> {
+    let mut channels = connections.lock().unwrap();
+    let channel = channels.iter_mut().find(|c| c.number == number)?;
+    let mut health = channel.health.lock().unwrap();
+    health.attempt = Some(Instant::now());
+    match result {
+        Ok(connection) => {
+            health.state = State::Connected;
+            health.reconnects += 1;
+            let gap = health.lost.take().map(|l| l.elapsed());
+            let time = health.packet_time(Instant::now());
+            drop(health);
+            channel.connection = Some(connection.into());
+            gap.map(|gap| (gap, time))
+        }
+        Err(err) => {
+            health.state = State::Error(format!("{err}"));
+            health.errors += 1;
+            None
+        }
+    }
+}
+
+/// One line with the health of every channel, for the status bar.
+pub fn status(connections: &Connections) -> String {
+    let channels = connections.lock().unwrap();
+    if channels.is_empty() {
+        return "No adapter".into();
+    }
+    let now = Instant::now();
+    channels
+        .iter()
+        .map(|c| {
+            let mut health = c.health.lock().unwrap();
+            health.tick(now);
+            format!("{} {health}", c.name())
+        })
+        .collect::<Vec<_>>()
+        .join("  |  ")
+}
+
+/// Parse a list of channels such as "0, 2". Empty means every channel.
+pub fn parse_channels(text: &str) -> Result<Option<HashSet<u8>>> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    let channels = text
+        .split(',')
+        .map(|c| {
+            let c = c.trim();
+            c.parse()
+                .ok()
+                .filter(|n| *n < MAX_CHANNELS)
+                .ok_or(anyhow!("Invalid channel {c}"))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Some(channels))
+}
+
+/// List the open channels, so one can be closed without stopping the others.
+pub fn channels_window(connections: Connections) {
+    let mut wind = Window::default().with_size(400, 200).with_label("Channels");
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+    let mut browser = HoldBrowser::default();
+    let buttons = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut close_button = Button::default().with_label("Close Channel");
+    buttons.end();
+    flex.fixed(&buttons, 30);
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    let mut update = {
+        let connections = connections.clone();
+        let mut browser = browser.clone();
+        move || {
+            browser.clear();
+            connections.lock().unwrap().iter().for_each(|c| {
+                browser.add(&format!(
+                    "{} ({})",
+                    c.name(),
+                    c.health.lock().unwrap().state
+                ))
+            });
+        }
+    };
+    update();
+    close_button.set_callback(move |_| {
+        let selected = browser.value();
+        let number = (selected as usize)
+            .checked_sub(1)
+            .and_then(|i| connections.lock().unwrap().get(i).map(|c| c.number));
+        if let Some(number) = number {
+            close(&connections, number);
+            update();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(next_number(&[]), Some(0));
+        assert_eq!(next_number(&[0, 2]), Some(1));
+        assert_eq!(next_number(&(0..MAX_CHANNELS).collect::<Vec<_>>()), None);
+    }
+
+    #[test]
+    fn health() {
+        let start = Instant::now();
+        let mut health = Health::new(start);
+        health.frames = 500;
+        health.tick(start + Duration::from_millis(500));
+        assert_eq!(health.rate, 0.0);
+        health.tick(start + Duration::from_secs(2));
+        assert_eq!(health.rate, 250.0);
+        assert_eq!(health.to_string(), "connected, 250 frames/s, 0 errors");
+
+        assert!(!health.retry_due(start));
+        health.state = State::Reconnecting;
+        assert!(health.retry_due(start));
+        health.attempt = Some(start);
+        health.state = State::Error("not found".into());
+        assert!(!health.retry_due(start + Duration::from_secs(1)));
+        assert!(health.retry_due(start + RETRY));
+        health.reconnects = 1;
+        assert_eq!(
+            health.to_string(),
+            "error: not found, 250 frames/s, 0 errors, 1 reconnects"
+        );
+    }
+
+    #[test]
+    fn packet_time() {
+        let mut health = Health::new(Instant::now());
+        assert_eq!(health.packet_time(Instant::now()), None);
+        health.frame(Duration::from_secs(10));
+        let (_, read) = health.last_frame.unwrap();
+        assert_eq!(
+            health.packet_time(read + Duration::from_millis(1500)),
+            Some(Duration::from_millis(11_500))
+        );
+        assert_eq!(health.frames, 1);
+    }
+
+    #[test]
+    fn channel_filter() {
+        assert_eq!(parse_channels(" ").unwrap(), None);
+        assert_eq!(parse_channels("0, 2").unwrap(), Some(HashSet::from([0, 2])));
+        assert!(parse_channels("1,x").is_err());
+        assert!(parse_channels("15").is_err());
+    }
+}
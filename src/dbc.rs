@@ -0,0 +1,793 @@
+use std::{collections::HashMap, fmt::Display, fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use canparse::pgn::{ParseMessage, PgnLibrary, SpnDefinition};
+use regex::Regex;
+
+use crate::packet_repo::dbc_key;
+
+/// A problem in a DBC file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// 1 based
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A `BO_` line and its signals.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message {
+    pub line: usize,
+    /// as written in the DBC, with bit 31 set for 29 bit ids
+    pub id: u32,
+    pub name: String,
+    pub dlc: usize,
+    pub transmitter: String,
+    pub signals: Vec<Signal>,
+}
+
+impl Message {
+    /// Check a message before it is saved.
+    pub fn check(&self) -> Result<()> {
+        check_name(&self.name)?;
+        if !self.transmitter.is_empty() {
+            check_name(&self.transmitter)?;
+        }
+        if self.dlc > 64 {
+            return Err(anyhow!("Message length {} is over 64 bytes.", self.dlc));
+        }
+        for (i, s) in self.signals.iter().enumerate() {
+            check_name(&s.name)?;
+            if self.signals[..i].iter().any(|o| o.name == s.name) {
+                return Err(anyhow!("Signal {} is defined twice.", s.name));
+            }
+            if s.length == 0 || s.length > 64 {
+                return Err(anyhow!("Signal {} must be 1 to 64 bits.", s.name));
+            }
+            if s.bits().iter().any(|b| *b >= self.dlc * 8) {
+                return Err(anyhow!(
+                    "Signal {} doesn't fit in {} bytes.",
+                    s.name,
+                    self.dlc
+                ));
+            }
+            if s.values.iter().any(|(_, text)| text.contains('"')) {
+                return Err(anyhow!(
+                    "Value descriptions of {} can't contain \".",
+                    s.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let transmitter = if self.transmitter.is_empty() {
+            "Vector__XXX"
+        } else {
+            &self.transmitter
+        };
+        write!(
+            f,
+            "BO_ {} {}: {} {transmitter}",
+            self.id, self.name, self.dlc
+        )
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid name \"{name}\". Use letters, digits and _."
+        ))
+    }
+}
+
+/// A `SG_` line, with its `VAL_` value descriptions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Signal {
+    pub line: usize,
+    pub name: String,
+    /// multiplexer indicator such as `M` or `m0`, empty for none
+    pub multiplexer: String,
+    pub start: usize,
+    pub length: usize,
+    /// `@1`, Intel byte order
+    pub little_endian: bool,
+    pub signed: bool,
+    pub scale: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub unit: String,
+    /// receiving nodes, as written
+    pub receivers: String,
+    pub values: Vec<(i64, String)>,
+}
+
+impl Signal {
+    /// Payload bits of the signal, least significant first. Bit `n` is bit `n % 8` of byte
+    /// `n / 8`, as DBC start bits are numbered.
+    pub fn bits(&self) -> Vec<usize> {
+        if self.little_endian {
+            return (self.start..self.start + self.length).collect();
+        }
+        // Motorola start bits are the most significant bit, counting down through a byte and
+        // then into the next byte.
+        let mut bits = Vec::with_capacity(self.length);
+        let mut bit = self.start;
+        for _ in 0..self.length {
+            bits.push(bit);
+            bit = if bit.is_multiple_of(8) {
+                bit + 15
+            } else {
+                bit - 1
+            };
+        }
+        bits.reverse();
+        bits
+    }
+
+    /// Scaled value of the signal in `payload`, None when the payload is too short.
+    pub fn value(&self, payload: &[u8]) -> Option<f64> {
+        if self.length == 0 || self.length > 64 {
+            return None;
+        }
+        let mut raw = 0u64;
+        for (i, bit) in self.bits().into_iter().enumerate() {
+            let byte = payload.get(bit / 8)?;
+            raw |= u64::from((byte >> (bit % 8)) & 1) << i;
+        }
+        let raw = if self.signed {
+            let shift = 64 - self.length as u32;
+            ((raw << shift) as i64 >> shift) as f64
+        } else {
+            raw as f64
+        };
+        Some(raw * self.scale + self.offset)
+    }
+
+    /// Set the minimum and maximum to the range the bits can hold.
+    pub fn fit_range(&mut self) {
+        let (low, high) = if self.signed {
+            let half = 2f64.powi(self.length as i32 - 1);
+            (-half, half - 1.0)
+        } else {
+            (0.0, 2f64.powi(self.length as i32) - 1.0)
+        };
+        let (a, b) = (
+            self.offset + self.scale * low,
+            self.offset + self.scale * high,
+        );
+        (self.min, self.max) = (a.min(b), a.max(b));
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let multiplexer = if self.multiplexer.is_empty() {
+            String::new()
+        } else {
+            format!("{} ", self.multiplexer)
+        };
+        let receivers = if self.receivers.is_empty() {
+            "Vector__XXX"
+        } else {
+            &self.receivers
+        };
+        write!(
+            f,
+            " SG_ {} {multiplexer}: {}|{}@{}{} ({},{}) [{}|{}] \"{}\" {receivers}",
+            self.name,
+            self.start,
+            self.length,
+            if self.little_endian { 1 } else { 0 },
+            if self.signed { "-" } else { "+" },
+            self.scale,
+            self.offset,
+            self.min,
+            self.max,
+            self.unit,
+        )
+    }
+}
+
+/// Value descriptions as "0=Off, 1=On".
+pub fn values_text(values: &[(i64, String)]) -> String {
+    values
+        .iter()
+        .map(|(v, text)| format!("{v}={text}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse `values_text`.
+pub fn parse_values(text: &str) -> Result<Vec<(i64, String)>> {
+    text.split(',')
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| {
+            let (value, description) = v
+                .split_once('=')
+                .ok_or(anyhow!("Expected VALUE=TEXT, not {v}"))?;
+            let value = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid value {value}"))?;
+            Ok((value, description.trim().to_string()))
+        })
+        .collect()
+}
+
+/// An editable DBC file. Lines other than messages, signals and value descriptions are kept
+/// as they were.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dbc {
+    /// lines before the first message
+    pub header: Vec<String>,
+    pub messages: Vec<Message>,
+    /// lines after the messages
+    pub footer: Vec<String>,
+}
+
+impl Dbc {
+    /// Read a file to edit. Files with problems are rejected, as their bad lines would be
+    /// lost when saved.
+    pub fn read(path: &Path) -> Result<Dbc> {
+        let bytes = fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        let (dbc, diagnostics) = parse(&String::from_utf8_lossy(&bytes));
+        if !diagnostics.is_empty() {
+            let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+            return Err(anyhow!(
+                "Fix {} first:\n{}",
+                path.display(),
+                lines.join("\n")
+            ));
+        }
+        Ok(dbc)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_string())
+            .with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    /// Add a signal to message `id`, ignoring priority. A missing message is added, named
+    /// `name` with `dlc` bytes.
+    pub fn add_signal(&mut self, id: u32, name: &str, dlc: usize, signal: Signal) {
+        let index = match self
+            .messages
+            .iter()
+            .position(|m| dbc_key(m.id) == dbc_key(id))
+        {
+            Some(i) => i,
+            None => {
+                self.messages.push(Message {
+                    id,
+                    name: name.into(),
+                    dlc,
+                    ..Default::default()
+                });
+                self.messages.len() - 1
+            }
+        };
+        self.messages[index].signals.push(signal);
+    }
+}
+
+impl Display for Dbc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.header {
+            writeln!(f, "{line}")?;
+        }
+        for m in &self.messages {
+            writeln!(f)?;
+            writeln!(f, "{m}")?;
+            for s in &m.signals {
+                writeln!(f, "{s}")?;
+            }
+        }
+        if !self.footer.is_empty() {
+            writeln!(f)?;
+        }
+        for line in &self.footer {
+            writeln!(f, "{line}")?;
+        }
+        let mut first = true;
+        for m in &self.messages {
+            for s in m.signals.iter().filter(|s| !s.values.is_empty()) {
+                if std::mem::take(&mut first) {
+                    writeln!(f)?;
+                }
+                let values: Vec<String> = s
+                    .values
+                    .iter()
+                    .map(|(v, text)| format!("{v} \"{text}\""))
+                    .collect();
+                writeln!(f, "VAL_ {} {} {} ;", m.id, s.name, values.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the `BO_` and `SG_` lines of DBC text, which canparse reports without line
+/// numbers.
+pub fn check(text: &str) -> Vec<Diagnostic> {
+    parse(text).1
+}
+
+/// Read the messages, signals and value descriptions of DBC text, with any problems found.
+/// Text inside quoted strings, such as multi-line comments, is skipped.
+pub fn parse(text: &str) -> (Dbc, Vec<Diagnostic>) {
+    let message = Regex::new(r"^BO_\s+(\d+)\s+(\w+)\s*:\s*(\d+)\s+(\w+)\s*$").unwrap();
+    let num = r"[-+0-9.eE]+";
+    let signal = Regex::new(&format!(
+        r#"^SG_\s+(\w+)(?:\s+([Mm]\d*M?))?\s*:\s*(\d+)\|(\d+)@([01])([+-])\s*\(\s*({num})\s*,\s*({num})\s*\)\s*\[\s*({num})\s*\|\s*({num})\s*\]\s*"([^"]*)"\s*(.*)$"#
+    ))
+    .unwrap();
+    let value_line = Regex::new(r#"^VAL_\s+(\d+)\s+(\w+)\s+((?:-?\d+\s+"[^"]*"\s*)*);$"#).unwrap();
+    let value = Regex::new(r#"(-?\d+)\s+"([^"]*)""#).unwrap();
+
+    let mut dbc = Dbc::default();
+    let mut diagnostics = Vec::new();
+    let mut error = |line: usize, message: String| diagnostics.push(Diagnostic { line, message });
+    // line of the open quote, while inside a string
+    let mut string_start: Option<usize> = None;
+    // in a message, and its index when the `BO_` line was valid
+    let mut in_message: Option<Option<usize>> = None;
+    let mut ids: HashMap<u32, usize> = HashMap::new();
+    // `VAL_` lines, by footer index, resolved once all the signals are read
+    let mut values = Vec::new();
+
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let continued = string_start.is_some();
+        for (j, _) in raw.match_indices('"') {
+            if raw[..j].ends_with('\\') {
+                continue;
+            }
+            string_start = match string_start {
+                Some(_) => None,
+                None => Some(line),
+            };
+        }
+        let in_footer = !dbc.messages.is_empty();
+        let other = if !in_footer {
+            &mut dbc.header
+        } else {
+            &mut dbc.footer
+        };
+        if continued {
+            other.push(raw.into());
+            continue;
+        }
+        let trimmed = raw.trim();
+        match trimmed.split_whitespace().next() {
+            Some("BO_") => {
+                in_message = Some(None);
+                let Some(c) = message.captures(trimmed) else {
+                    error(line, format!("malformed message: {trimmed}"));
+                    continue;
+                };
+                let Ok(id) = c[1].parse::<u32>() else {
+                    error(line, format!("invalid message id {}", &c[1]));
+                    continue;
+                };
+                if let Some(first) = ids.insert(id, line) {
+                    error(
+                        line,
+                        format!("duplicate message {id}, first on line {first}"),
+                    );
+                }
+                let dlc: usize = c[3].parse().unwrap_or(usize::MAX);
+                if dlc > 64 {
+                    error(line, format!("message length {dlc} is over 64 bytes"));
+                }
+                in_message = Some(Some(dbc.messages.len()));
+                dbc.messages.push(Message {
+                    line,
+                    id,
+                    name: c[2].to_string(),
+                    dlc,
+                    transmitter: c[4].to_string(),
+                    signals: Vec::new(),
+                });
+            }
+            Some("SG_") => {
+                if in_message.is_none() {
+                    error(line, "signal outside of a message".into());
+                }
+                let Some((c, numbers)) = signal.captures(trimmed).and_then(|c| {
+                    let numbers: Option<Vec<f64>> = (7..=10).map(|n| c[n].parse().ok()).collect();
+                    Some((c, numbers?))
+                }) else {
+                    error(line, format!("malformed signal: {trimmed}"));
+                    continue;
+                };
+                let start: usize = c[3].parse().unwrap_or(usize::MAX);
+                let length: usize = c[4].parse().unwrap_or_default();
+                if length == 0 || length > 64 * 8 {
+                    error(line, format!("signal length {length} is out of range"));
+                    continue;
+                }
+                if start >= 64 * 8 {
+                    error(line, format!("signal start bit {start} is out of range"));
+                    continue;
+                }
+                if let Some(Some(m)) = in_message {
+                    dbc.messages[m].signals.push(Signal {
+                        line,
+                        name: c[1].to_string(),
+                        multiplexer: c.get(2).map_or("", |m| m.as_str()).to_string(),
+                        start,
+                        length,
+                        little_endian: &c[5] == "1",
+                        signed: &c[6] == "-",
+                        scale: numbers[0],
+                        offset: numbers[1],
+                        min: numbers[2],
+                        max: numbers[3],
+                        unit: c[11].to_string(),
+                        receivers: c[12].trim().to_string(),
+                        values: Vec::new(),
+                    });
+                }
+            }
+            Some(keyword) => {
+                in_message = None;
+                if let Some(c) = value_line.captures(trimmed) {
+                    let pairs: Vec<(i64, String)> = value
+                        .captures_iter(&c[3])
+                        .filter_map(|v| Some((v[1].parse().ok()?, v[2].to_string())))
+                        .collect();
+                    match c[1].parse::<u32>() {
+                        Ok(id) if in_footer => {
+                            values.push((other.len(), id, c[2].to_string(), pairs))
+                        }
+                        _ => {}
+                    }
+                } else if keyword == "VAL_" {
+                    error(line, format!("malformed value descriptions: {trimmed}"));
+                }
+                other.push(raw.into());
+            }
+            None => {
+                // blank lines between messages are written again by `Dbc`
+                if in_message.is_none() {
+                    other.push(String::new());
+                }
+            }
+        }
+    }
+    if let Some(start) = string_start {
+        error(start, "string is not closed".into());
+    }
+
+    // move value descriptions to their signals
+    let mut moved = Vec::new();
+    for (index, id, name, pairs) in values {
+        let signal = dbc
+            .messages
+            .iter_mut()
+            .filter(|m| m.id == id)
+            .flat_map(|m| m.signals.iter_mut())
+            .find(|s| s.name == name);
+        if let Some(signal) = signal {
+            signal.values = pairs;
+            moved.push(index);
+        }
+    }
+    dbc.footer = std::mem::take(&mut dbc.footer)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !moved.contains(i))
+        .map(|(_, line)| line)
+        .collect();
+    let blank = |l: &String| l.trim().is_empty();
+    while dbc.header.last().is_some_and(blank) {
+        dbc.header.pop();
+    }
+    while dbc.footer.last().is_some_and(blank) {
+        dbc.footer.pop();
+    }
+    let leading = dbc.footer.iter().take_while(|l| blank(l)).count();
+    dbc.footer.drain(..leading);
+    (dbc, diagnostics)
+}
+
+/// Decoded value of a signal. canparse only reads the first 8 bytes, so longer CAN FD
+/// payloads are decoded from the signal's bits.
+pub fn decode(spn: &SpnDefinition, payload: &[u8]) -> Option<f64> {
+    if payload.len() <= 8 {
+        return spn.parse_message(payload).map(|v| v as f64);
+    }
+    Signal {
+        start: spn.start_bit,
+        length: spn.bit_len,
+        little_endian: spn.little_endian,
+        signed: spn.signed,
+        scale: spn.scale as f64,
+        offset: spn.offset as f64,
+        ..Default::default()
+    }
+    .value(payload)
+}
+
+/// Load a DBC file. When canparse rejects it, the error lists what `check` finds.
+pub fn load(path: &Path) -> Result<PgnLibrary> {
+    let text = fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    PgnLibrary::from_dbc_file(path).map_err(|err| {
+        let diagnostics: Vec<String> = check(&String::from_utf8_lossy(&text))
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        anyhow!(
+            "Unable to parse {}: {err:?}\n{}",
+            path.display(),
+            diagnostics.join("\n")
+        )
+    })
+}
+
+/// EEC1 with engine speed from SA FE, for tests.
+#[cfg(test)]
+pub const TEST_EEC1: &str = "BO_ 2364540158 EEC1: 8 ECU\n SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] \"rpm\" Vector__XXX\n";
+
+/// Definitions of the DBC `messages` as `load` reads them from a file, for tests.
+#[cfg(test)]
+pub fn load_test_messages(messages: &str) -> Vec<canparse::pgn::PgnDefinition> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    // tests run in parallel
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "messages-test-{}-{}.dbc",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&path, format!("VERSION \"\"\n\nBU_: ECU\n\n{messages}")).unwrap();
+    let library = load(&path);
+    fs::remove_file(&path).unwrap();
+    library.unwrap().pgns.values().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"VERSION ""
+
+BU_: ECU
+
+BO_ 2364539904 EEC1: 8 ECU
+ SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+ SG_ Mode m0 : 0|4@1+ (1,0) [0|15] "" Vector__XXX
+
+CM_ BO_ 2364539904 "Electronic Engine Controller 1
+SG_ this is not a signal";
+BA_ "GenMsgCycleTime" BO_ 2364539904 100;
+"#;
+
+    /// Messages without line numbers.
+    fn definitions(dbc: &Dbc) -> Vec<Message> {
+        let mut messages = dbc.messages.clone();
+        for m in &mut messages {
+            m.line = 0;
+            m.signals.iter_mut().for_each(|s| s.line = 0);
+        }
+        messages
+    }
+
+    /// Check that canparse, which decodes the log, reads the definitions of `dbc` from its
+    /// written text.
+    fn assert_canparse_reads(dbc: &Dbc) {
+        let path = std::env::temp_dir().join(format!("dbc-test-{}.dbc", std::process::id()));
+        fs::write(&path, dbc.to_string()).unwrap();
+        let library = PgnLibrary::from_dbc_file(&path);
+        fs::remove_file(&path).unwrap();
+        let library = library.unwrap();
+        assert_eq!(library.pgns.len(), dbc.messages.len());
+        for m in &dbc.messages {
+            let pgn = library
+                .pgns
+                .values()
+                .find(|p| dbc_key(p.id) == dbc_key(m.id))
+                .unwrap_or_else(|| panic!("{} is missing", m.name));
+            assert_eq!(pgn.spns.len(), m.signals.len(), "{}", m.name);
+            for s in &m.signals {
+                let spn = pgn
+                    .spns
+                    .values()
+                    .find(|spn| spn.name == s.name)
+                    .unwrap_or_else(|| panic!("{} is missing", s.name));
+                assert_eq!(
+                    (spn.start_bit, spn.bit_len, spn.little_endian, spn.signed),
+                    (s.start, s.length, s.little_endian, s.signed),
+                    "{}",
+                    s.name
+                );
+                assert_eq!(
+                    [spn.scale, spn.offset, spn.min_value, spn.max_value].map(f64::from),
+                    [s.scale, s.offset, s.min, s.max],
+                    "{}",
+                    s.name
+                );
+                assert_eq!(spn.units, s.unit);
+            }
+        }
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(check(DBC), vec![]);
+    }
+
+    #[test]
+    fn line_numbers() {
+        let text = DBC.replace("24|16@1+", "24|16@1").replace(
+            "BA_ \"GenMsgCycleTime\"",
+            "BO_ 2364539904 EEC1: 80 ECU\nBA_",
+        );
+        assert_eq!(
+            check(&text),
+            vec![
+                Diagnostic {
+                    line: 6,
+                    message: "malformed signal: SG_ EngineSpeed : 24|16@1 (0.125,0) [0|8031.875] \"rpm\" Vector__XXX".into()
+                },
+                Diagnostic {
+                    line: 11,
+                    message: "duplicate message 2364539904, first on line 5".into()
+                },
+                Diagnostic {
+                    line: 11,
+                    message: "message length 80 is over 64 bytes".into()
+                },
+            ]
+        );
+        assert_eq!(
+            check("BO_ 1 A: 8 ECU\nBU_: X\n SG_ B : 0|0@1+ (1,0) [0|1] \"\" X\nCM_ \"open")
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "line 3: signal outside of a message",
+                "line 3: signal length 0 is out of range",
+                "line 4: string is not closed",
+            ]
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let messages = parse(DBC).0.messages;
+        assert_eq!(messages.len(), 1);
+        let m = &messages[0];
+        assert_eq!(
+            (m.line, m.id, m.name.as_str(), m.dlc),
+            (5, 0x8CF00400, "EEC1", 8)
+        );
+        assert_eq!(m.signals[0].bits(), (24..40).collect::<Vec<_>>());
+        assert_eq!(m.signals[1].multiplexer, "m0");
+        // Motorola, most significant bit 7 of byte 0 through bit 4 of byte 1
+        let signal = Signal {
+            start: 7,
+            length: 12,
+            little_endian: false,
+            ..Default::default()
+        };
+        assert_eq!(signal.bits(), vec![12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn fd_value() {
+        let mut payload = vec![0; 64];
+        payload[12] = 0x10;
+        payload[13] = 0x82;
+        let mut signal = Signal {
+            start: 96,
+            length: 16,
+            little_endian: true,
+            scale: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(signal.value(&payload), Some(0x8210 as f64 * 0.5));
+        signal.signed = true;
+        assert_eq!(
+            signal.value(&payload),
+            Some((0x8210 - 0x10000) as f64 * 0.5)
+        );
+        // Motorola, most significant byte first
+        signal.start = 103;
+        signal.little_endian = false;
+        signal.signed = false;
+        assert_eq!(signal.value(&payload), Some(0x1082 as f64 * 0.5));
+        assert_eq!(signal.value(&payload[..13]), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = DBC.to_string()
+            + r#"
+BO_ 2566849024 PropB_00: 8 ECU
+ SG_ Torque : 7|12@0- (0.5,-100) [-1124|923.5] "Nm" ECU,Tool
+ SG_ State : 16|2@1+ (1,0) [0|3] "" Vector__XXX
+VAL_ 2566849024 State 0 "Off" 1 "On" 3 "Not Available" ;
+"#;
+        let (dbc, diagnostics) = parse(&text);
+        assert_eq!(diagnostics, vec![]);
+        let state = &dbc.messages[1].signals[1];
+        assert_eq!(state.values[2], (3, "Not Available".into()));
+        let torque = &dbc.messages[1].signals[0];
+        assert!(torque.signed && !torque.little_endian);
+        assert_eq!((torque.scale, torque.offset), (0.5, -100.0));
+        assert_eq!(torque.receivers, "ECU,Tool");
+        assert!(!dbc.footer.iter().any(|l| l.starts_with("VAL_")));
+
+        let written = dbc.to_string();
+        let (again, diagnostics) = parse(&written);
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(definitions(&again), definitions(&dbc));
+        assert_eq!((&again.header, &again.footer), (&dbc.header, &dbc.footer));
+        assert_eq!(again.to_string(), written);
+        assert_canparse_reads(&dbc);
+    }
+
+    #[test]
+    fn edit() {
+        let mut dbc = parse(DBC).0;
+        let mut counter = Signal {
+            name: "Counter".into(),
+            start: 56,
+            length: 4,
+            little_endian: true,
+            scale: 1.0,
+            ..Default::default()
+        };
+        counter.fit_range();
+        assert_eq!(
+            counter.to_string(),
+            " SG_ Counter : 56|4@1+ (1,0) [0|15] \"\" Vector__XXX"
+        );
+        // any priority
+        dbc.add_signal(0x98F00400, "EEC1", 8, counter.clone());
+        dbc.add_signal(0x98FF1200, "PropB_12", 8, counter.clone());
+        let (again, diagnostics) = parse(&dbc.to_string());
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(again.messages[0].signals.len(), 3);
+        assert_eq!(again.messages[1].name, "PropB_12");
+        assert_eq!(again.messages[1].transmitter, "Vector__XXX");
+        // comments and attributes stay after the messages
+        assert!(again.footer[0].starts_with("CM_"));
+
+        let mut m = again.messages[1].clone();
+        assert!(m.check().is_ok());
+        m.signals.push(counter.clone());
+        assert!(m.check().is_err());
+        m.signals[1].name = "Counter2".into();
+        m.signals[1].start = 62;
+        assert!(m.check().is_err());
+
+        assert_eq!(
+            parse_values("0=Off, 1 = On").unwrap(),
+            vec![(0, "Off".into()), (1, "On".into())]
+        );
+        assert_eq!(
+            values_text(&[(0, "Off".into()), (1, "On".into())]),
+            "0=Off, 1=On"
+        );
+        assert!(parse_values("x=1").is_err());
+    }
+}
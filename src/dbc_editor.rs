@@ -0,0 +1,440 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use fltk::{
+    browser::HoldBrowser,
+    button::{Button, CheckButton},
+    dialog::{FileDialog, FileDialogType},
+    frame::Frame,
+    group::{Flex, PackType},
+    input::{FloatInput, Input, IntInput},
+    menu::Choice,
+    prelude::{
+        BrowserExt, ButtonExt, GroupExt, InputExt, MenuExt, WidgetBase, WidgetExt, WindowExt,
+    },
+    window::Window,
+};
+use simple_table::joe_table::JoeTable;
+
+use crate::{
+    dbc::{self, parse_values, values_text, Dbc, Message, Signal},
+    dbc_table::DbcModel,
+    packet_repo::{dbc_key, frame_id, ide_id, pgn_text},
+    report::{report, report_err},
+};
+
+/// Message form.
+#[derive(Clone)]
+struct MessageForm {
+    id: Input,
+    name: Input,
+    dlc: IntInput,
+    transmitter: Input,
+}
+
+impl MessageForm {
+    fn show(&mut self, m: &Message) {
+        self.id.set_value(&format!("{:X}", m.id & 0x1FFF_FFFF));
+        self.name.set_value(&m.name);
+        self.dlc.set_value(&m.dlc.to_string());
+        self.transmitter.set_value(&m.transmitter);
+    }
+    /// The message in the form, with the signals of `m`.
+    fn read(&self, m: &Message) -> Result<Message> {
+        let id = u32::from_str_radix(self.id.value().trim(), 16)
+            .map_err(|_| anyhow!("Invalid id {}", self.id.value()))?;
+        if id > 0x1FFF_FFFF {
+            return Err(anyhow!("Id {id:X} is over 29 bits."));
+        }
+        Ok(Message {
+            line: m.line,
+            // ids over 7FF are 29 bit
+            id: ide_id(frame_id(id)),
+            name: self.name.value().trim().into(),
+            dlc: self.dlc.value().parse()?,
+            transmitter: self.transmitter.value().trim().into(),
+            signals: m.signals.clone(),
+        })
+    }
+}
+
+/// Signal form.
+#[derive(Clone)]
+struct SignalForm {
+    name: Input,
+    start: IntInput,
+    length: IntInput,
+    order: Choice,
+    signed: CheckButton,
+    scale: FloatInput,
+    offset: FloatInput,
+    min: FloatInput,
+    max: FloatInput,
+    unit: Input,
+    values: Input,
+}
+
+impl SignalForm {
+    fn show(&mut self, s: &Signal) {
+        self.name.set_value(&s.name);
+        self.start.set_value(&s.start.to_string());
+        self.length.set_value(&s.length.to_string());
+        self.order.set_value(if s.little_endian { 0 } else { 1 });
+        self.signed.set_checked(s.signed);
+        self.scale.set_value(&s.scale.to_string());
+        self.offset.set_value(&s.offset.to_string());
+        self.min.set_value(&s.min.to_string());
+        self.max.set_value(&s.max.to_string());
+        self.unit.set_value(&s.unit);
+        self.values.set_value(&values_text(&s.values));
+    }
+    /// The signal in the form, keeping the multiplexer and receivers of `s`.
+    fn read(&self, s: &Signal) -> Result<Signal> {
+        let number = |input: &FloatInput, name: &str| -> Result<f64> {
+            input
+                .value()
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid {name} {}", input.value()))
+        };
+        Ok(Signal {
+            name: self.name.value().trim().into(),
+            start: self.start.value().parse()?,
+            length: self.length.value().parse()?,
+            little_endian: self.order.value() == 0,
+            signed: self.signed.is_checked(),
+            scale: number(&self.scale, "scale")?,
+            offset: number(&self.offset, "offset")?,
+            min: number(&self.min, "minimum")?,
+            max: number(&self.max, "maximum")?,
+            unit: self.unit.value(),
+            values: parse_values(&self.values.value())?,
+            ..s.clone()
+        })
+    }
+}
+
+/// Selected (message, signal), 0 based.
+fn selection(messages: &HoldBrowser, signals: &HoldBrowser) -> (Option<usize>, Option<usize>) {
+    let index = |b: &HoldBrowser| (b.value() as usize).checked_sub(1);
+    (index(messages), index(signals))
+}
+
+/// Create and edit the messages and signals of a DBC file. Saving reloads the DBC window.
+pub fn dbc_editor_window(path: PathBuf, table: Arc<Mutex<JoeTable<DbcModel>>>) {
+    let Some(dbc) = report_err("Unable to edit DBC", Dbc::read(&path)) else {
+        return;
+    };
+    let dbc = Arc::new(Mutex::new(dbc));
+
+    let mut wind = Window::default().with_size(800, 500).with_label(&format!(
+        "Edit {}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let mut flex = Flex::default_fill().with_type(PackType::Horizontal);
+
+    let mut column = Flex::default_fill().with_type(PackType::Vertical);
+    Frame::default().with_label("Messages");
+    let mut messages = HoldBrowser::default();
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut delete_message = Button::default().with_label("Delete");
+    row.end();
+    column.fixed(&row, 25);
+    column.end();
+    flex.fixed(&column, 220);
+
+    let mut column = Flex::default_fill().with_type(PackType::Vertical);
+    Frame::default().with_label("Signals");
+    let mut signals = HoldBrowser::default();
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut delete_signal = Button::default().with_label("Delete");
+    row.end();
+    column.fixed(&row, 25);
+    column.end();
+    flex.fixed(&column, 200);
+
+    let form = Flex::default_fill().with_type(PackType::Vertical);
+    let field = |label: &str| {
+        let row = Flex::default_fill().with_type(PackType::Horizontal);
+        Frame::default().with_label(label);
+        let input = Input::default();
+        row.end();
+        input
+    };
+    let int_field = |label: &str| {
+        let row = Flex::default_fill().with_type(PackType::Horizontal);
+        Frame::default().with_label(label);
+        let input = IntInput::default();
+        row.end();
+        input
+    };
+    let float_field = |label: &str| {
+        let row = Flex::default_fill().with_type(PackType::Horizontal);
+        Frame::default().with_label(label);
+        let input = FloatInput::default();
+        row.end();
+        input
+    };
+    let message_form = MessageForm {
+        id: field("Id (hex)"),
+        name: field("Name"),
+        dlc: int_field("Length"),
+        transmitter: field("Transmitter"),
+    };
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut apply_message = Button::default().with_label("Apply");
+    let mut add_message = Button::default().with_label("Add Message");
+    row.end();
+
+    let name = field("Signal");
+    let start = int_field("Start bit");
+    let length = int_field("Length");
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Byte order");
+    let mut order = Choice::default();
+    order.add_choice("Intel|Motorola");
+    order.set_value(0);
+    row.end();
+    let signed = CheckButton::default().with_label("Signed");
+    let mut signal_form = SignalForm {
+        name,
+        start,
+        length,
+        order,
+        signed,
+        scale: float_field("Scale"),
+        offset: float_field("Offset"),
+        min: float_field("Minimum"),
+        max: float_field("Maximum"),
+        unit: field("Unit"),
+        values: field("Values"),
+    };
+    signal_form
+        .values
+        .set_tooltip("Value descriptions, such as 0=Off, 1=On");
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut apply_signal = Button::default().with_label("Apply");
+    let mut add_signal = Button::default().with_label("Add Signal");
+    row.end();
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut save = Button::default().with_label("Save");
+    let mut save_as = Button::default().with_label("Save As...");
+    row.end();
+    form.end();
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    // fill the lists, keeping the selection
+    let mut update = {
+        let dbc = dbc.clone();
+        let mut messages = messages.clone();
+        let mut signals = signals.clone();
+        move || {
+            let (m, s) = selection(&messages, &signals);
+            let dbc = dbc.lock().unwrap();
+            messages.clear();
+            for message in &dbc.messages {
+                messages.add(&format!(
+                    "{} ({})",
+                    message.name,
+                    pgn_text(dbc_key(message.id))
+                ));
+            }
+            signals.clear();
+            let selected = m.and_then(|m| Some((m, dbc.messages.get(m)?)));
+            if let Some((m, message)) = selected {
+                messages.select(m as i32 + 1);
+                for signal in &message.signals {
+                    signals.add(&signal.name);
+                }
+                if let Some(s) = s.filter(|s| *s < message.signals.len()) {
+                    signals.select(s as i32 + 1);
+                }
+            }
+        }
+    };
+    update();
+    {
+        let dbc = dbc.clone();
+        let mut update = update.clone();
+        let mut message_form = message_form.clone();
+        let mut signals = signals.clone();
+        messages.set_callback(move |b| {
+            signals.deselect();
+            let (Some(m), _) = selection(b, &signals) else {
+                return;
+            };
+            if let Some(message) = dbc.lock().unwrap().messages.get(m) {
+                message_form.show(message);
+            }
+            update();
+        });
+    }
+    {
+        let dbc = dbc.clone();
+        let messages = messages.clone();
+        let mut signal_form = signal_form.clone();
+        signals.set_callback(move |b| {
+            if let (Some(m), Some(s)) = selection(&messages, b) {
+                if let Some(signal) = dbc.lock().unwrap().messages[m].signals.get(s) {
+                    signal_form.show(signal);
+                }
+            }
+        });
+    }
+
+    // Apply replaces the selected message or signal, Add appends a new one.
+    let edit_message = {
+        let dbc = dbc.clone();
+        let messages = messages.clone();
+        let signals = signals.clone();
+        let message_form = message_form.clone();
+        let mut update = update.clone();
+        move |replace: bool| {
+            let mut dbc = dbc.lock().unwrap();
+            let selected = selection(&messages, &signals).0.filter(|_| replace);
+            let old = selected
+                .map(|m| dbc.messages[m].clone())
+                .unwrap_or_default();
+            let result = message_form.read(&old).and_then(|m| {
+                m.check()?;
+                let duplicate = dbc
+                    .messages
+                    .iter()
+                    .enumerate()
+                    .any(|(i, o)| Some(i) != selected && o.id == m.id);
+                if duplicate {
+                    return Err(anyhow!("Message {:X} already exists.", m.id));
+                }
+                Ok(m)
+            });
+            match result {
+                Ok(m) => match selected {
+                    Some(i) => dbc.messages[i] = m,
+                    None => dbc.messages.push(m),
+                },
+                Err(err) => return report("Invalid message", &err),
+            }
+            drop(dbc);
+            update();
+        }
+    };
+    {
+        let mut edit_message = edit_message.clone();
+        let messages = messages.clone();
+        apply_message.set_callback(move |_| {
+            if messages.value() > 0 {
+                edit_message(true)
+            }
+        });
+    }
+    {
+        let mut edit_message = edit_message.clone();
+        add_message.set_callback(move |_| edit_message(false));
+    }
+    let edit_signal = {
+        let dbc = dbc.clone();
+        let messages = messages.clone();
+        let signals = signals.clone();
+        let signal_form = signal_form.clone();
+        let mut update = update.clone();
+        move |replace: bool| {
+            let (Some(m), s) = selection(&messages, &signals) else {
+                return report("Invalid signal", &anyhow!("Select a message."));
+            };
+            let mut dbc = dbc.lock().unwrap();
+            let selected = s.filter(|_| replace);
+            let mut message = dbc.messages[m].clone();
+            let old = selected
+                .map(|s| message.signals[s].clone())
+                .unwrap_or_default();
+            let result = signal_form.read(&old).and_then(|signal| {
+                match selected {
+                    Some(s) => message.signals[s] = signal,
+                    None => message.signals.push(signal),
+                }
+                message.check()
+            });
+            if let Err(err) = result {
+                return report("Invalid signal", &err);
+            }
+            dbc.messages[m] = message;
+            drop(dbc);
+            update();
+        }
+    };
+    {
+        let mut edit_signal = edit_signal.clone();
+        let signals = signals.clone();
+        apply_signal.set_callback(move |_| {
+            if signals.value() > 0 {
+                edit_signal(true)
+            }
+        });
+    }
+    {
+        let mut edit_signal = edit_signal.clone();
+        add_signal.set_callback(move |_| edit_signal(false));
+    }
+    {
+        let dbc = dbc.clone();
+        let messages = messages.clone();
+        let mut signals = signals.clone();
+        let mut update = update.clone();
+        delete_message.set_callback(move |_| {
+            if let (Some(m), _) = selection(&messages, &signals) {
+                dbc.lock().unwrap().messages.remove(m);
+                signals.deselect();
+                update();
+            }
+        });
+    }
+    {
+        let dbc = dbc.clone();
+        let messages = messages.clone();
+        let signals = signals.clone();
+        let mut update = update.clone();
+        delete_signal.set_callback(move |_| {
+            if let (Some(m), Some(s)) = selection(&messages, &signals) {
+                dbc.lock().unwrap().messages[m].signals.remove(s);
+                update();
+            }
+        });
+    }
+    {
+        let dbc = dbc.clone();
+        save.set_callback(move |_| {
+            let saved = dbc
+                .lock()
+                .unwrap()
+                .save(&path)
+                .and_then(|_| dbc::load(&path));
+            if let Some(pgns) = report_err("Unable to save DBC", saved) {
+                let mut table = table.lock().unwrap();
+                table
+                    .model
+                    .lock()
+                    .unwrap()
+                    .set_pgns(pgns.pgns.values().cloned().collect());
+                table.redraw();
+            }
+        });
+    }
+    save_as.set_callback(move |_| {
+        let mut fc = FileDialog::new(FileDialogType::BrowseSaveFile);
+        fc.set_filter("*.dbc");
+        fc.show();
+        if !fc.filenames().is_empty() {
+            report_err(
+                "Unable to save DBC",
+                dbc.lock().unwrap().save(&fc.filename()),
+            );
+        }
+    });
+}
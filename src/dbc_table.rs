@@ -1,21 +1,68 @@
 use core::f64;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
     hash::Hash,
-    sync::{Arc, RwLock},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
     time::Duration,
 };
 
-use can_adapter::packet::Packet;
-use canparse::pgn::{ParseMessage, PgnDefinition, SpnDefinition};
-use simple_table::{simple_model::{DrawDelegate, Order, SimpleModel}, spark_line::SparkLine};
+use anyhow::Result;
+use can_adapter::{j1939::j1939_packet::J1939Packet, packet::Packet};
+use canparse::pgn::{PgnDefinition, SpnDefinition};
+use chrono::Local;
+use fltk::{draw, enums::Color};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{DrawDelegate, Order, SimpleModel},
+    spark_line::SparkLine,
+};
+
+use crate::{
+    address_map::{self, AddressMapping, Discovered},
+    alarm::{self, AlarmCell, AlarmEvent, AlarmEvents, AlarmLimits, Violation},
+    dbc,
+    packet_repo::{
+        channel_key, dbc_key, frame_id, packet_string, pgn_text, sa_text, MarkerKind, PacketRepo,
+    },
+};
+
+/// Chart with the log markers in its time range.
+struct Chart {
+    line: SparkLine,
+    /// marker positions, as fractions of the chart width
+    marks: Vec<f64>,
+}
+
+impl DrawDelegate for Chart {
+    fn draw(&self, row: i32, col: i32, x: i32, y: i32, w: i32, h: i32, selected: bool) {
+        self.line.draw(row, col, x, y, w, h, selected);
+        draw::push_clip(x, y, w, h);
+        draw::set_draw_color(Color::Red);
+        for f in &self.marks {
+            let mx = x + (f * (w - 1) as f64) as i32;
+            draw::draw_line(mx, y, mx, y + h - 1);
+        }
+        draw::pop_clip();
+    }
+}
+
+/// All open DBC windows.
+pub type DbcTables = Arc<Mutex<Vec<DbcTable>>>;
 
-use crate::packet_repo::PacketRepo;
+/// An open DBC window.
+#[derive(Clone)]
+pub struct DbcTable {
+    pub path: PathBuf,
+    pub table: Arc<Mutex<JoeTable<DbcModel>>>,
+}
 
 /// SimpleModel representing a DBC file with a Connection.
 pub struct DbcModel {
-    /// PGN Definitions in row order.
+    /// PGN Definitions as read from the DBC.
+    dbc_pgns: Vec<PgnDefinition>,
+    /// PGN Definitions after address mapping, in row order.
     pgns: Vec<PgnDefinition>,
     /// pgn -> packets in chronological order
     packets: Arc<RwLock<PacketRepo>>,
@@ -26,22 +73,80 @@ pub struct DbcModel {
     time: Duration,
     // how long should the spark line be
     line_length: Duration,
+    /// address mappings, first match wins.
+    mappings: Vec<AddressMapping>,
+    /// show messages for every source address sending them, after the mappings.
+    auto_match: bool,
+    hide_missing: bool,
+    /// last sort column and descending
+    sort: Option<(usize, bool)>,
+    /// row index -> (`PacketRepo::generation`, packet index, packet time, decoded value),
+    /// cleared when the rows change
+    values: Mutex<HashMap<usize, (usize, usize, Duration, Option<f64>)>>,
+    /// alarm key -> limits
+    alarms: HashMap<String, AlarmLimits>,
+    /// where alarms are saved
+    alarms_path: Option<PathBuf>,
+    /// alarm commands the user allowed to run this session
+    allowed_commands: HashSet<String>,
+    /// rows with a raised alarm, by (id, signal)
+    active_alarms: HashSet<(u32, String)>,
+    events: AlarmEvents,
+    /// only packets from this channel, any when None
+    channel: Option<u8>,
 }
 impl DbcModel {
     pub fn new(pgns: Vec<PgnDefinition>, packets: Arc<RwLock<PacketRepo>>) -> DbcModel {
         let mut m = DbcModel {
+            dbc_pgns: pgns.clone(),
             pgns,
             rows: Vec::new(),
             packets,
             time: Duration::MAX,
             line_length: Duration::from_secs(10),
+            mappings: Vec::new(),
+            auto_match: false,
+            hide_missing: false,
+            sort: None,
+            values: Default::default(),
+            alarms: HashMap::new(),
+            alarms_path: None,
+            allowed_commands: HashSet::new(),
+            active_alarms: HashSet::new(),
+            events: Default::default(),
+            channel: None,
         };
         m.restore_missing();
         m
     }
+    /// PGN definitions, including any address mapping.
+    pub fn pgns(&self) -> &[PgnDefinition] {
+        &self.pgns
+    }
+    /// PGN definitions as read from the DBC.
+    pub fn dbc_pgns(&self) -> &[PgnDefinition] {
+        &self.dbc_pgns
+    }
     pub fn set_time(&mut self, t: Duration) {
         self.time = t;
     }
+    /// Time decoded, `Duration::MAX` when live.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+    /// Decode only packets from `channel`, or from any channel.
+    pub fn set_channel(&mut self, channel: Option<u8>) {
+        self.channel = channel;
+        self.pgns = self.mapped_pgns();
+        self.rebuild_rows();
+    }
+    pub fn channel(&self) -> Option<u8> {
+        self.channel
+    }
+    /// `PacketRepo` key for a DBC id on the bound channel.
+    fn key(&self, id: u32) -> u32 {
+        channel_key(self.channel, dbc_key(id))
+    }
     pub fn remove_missing(&mut self) {
         let new_rows = self
             .rows
@@ -50,71 +155,341 @@ impl DbcModel {
                 self.packets
                     .read()
                     .unwrap()
-                    .get_for(row.pgn.id & 0x3FFFFFF)
+                    .get_for(self.key(row.pgn.id))
                     .is_some()
             })
             .cloned()
             .collect();
-        self.rows = new_rows;
+        self.set_rows(new_rows);
     }
     pub fn restore_missing(&mut self) {
-        self.rows = calc_rows(&self.pgns);
+        self.set_rows(calc_rows(&self.pgns));
+    }
+    fn set_rows(&mut self, rows: Vec<Row>) {
+        self.rows = rows;
+        self.values.get_mut().unwrap().clear();
     }
 
-    fn spn_value(&self, row: &Row) -> String {
+    fn spn_value(&self, index: usize) -> String {
+        let row = &self.rows[index];
         // ignore pritority?
-        self.last_packet(row.pgn.id & 0x3FFFFFF)
-            .map_or("no packet".to_string(), |packet| {
-                row.decode(&packet)
-                    .map_or("unable to parse".to_string(), |value| {
-                        format!("{:0.3} {}", value, row.spn.units)
-                    })
+        self.decoded(index)
+            .map_or("no packet".to_string(), |value| {
+                value.map_or("unable to parse".to_string(), |value| {
+                    format!("{:0.3} {}", value, row.spn.units)
+                })
             })
     }
 
+    /// Decoded value of the most recent packet for a row, None if there is no packet.
+    /// Values are cached until a newer packet is selected.
+    fn decoded(&self, row: usize) -> Option<Option<f64>> {
+        let r = &self.rows[row];
+        let repo = self.packets.read().unwrap();
+        let (index, packet) = repo.last_before(self.key(r.pgn.id), self.time)?;
+        let cached = (repo.generation(), index, packet.time().unwrap_or_default());
+        let mut cache = self.values.lock().unwrap();
+        if let Some((generation, i, t, value)) = cache.get(&row) {
+            if (*generation, *i, *t) == cached {
+                return Some(*value);
+            }
+        }
+        let value = r.decode(packet);
+        cache.insert(row, (cached.0, cached.1, cached.2, value));
+        Some(value)
+    }
+
     fn packet_string(&self, pgn: &PgnDefinition) -> String {
         // ignore priority?
-        self.last_packet(pgn.id & 0x3FFFFFF)
-            .map_or("no packet".to_string(), |p| p.to_string())
-    }
-
-    fn last_packet(&self, id: u32) -> Option<Packet> {
-        return self.packets.read().unwrap().get_for(id).and_then(|v| {
-            // FIXME replace with partition.  It will do a binary search.
-            v.iter()
-                .rev()
-                .find(|p| p.time().unwrap_or_default() <= self.time)
-                .map(|p| p.into())
-        });
-    }
-    pub fn map_address(&mut self, from: u8, to: u8) {
-        let f = from as u32;
-        let t = to as u32;
-        self.pgns = self
-            .pgns
+        self.last_packet(self.key(pgn.id))
+            .map_or("no packet".to_string(), |p| packet_string(&p, 0))
+    }
+
+    fn last_packet(&self, id: u32) -> Option<J1939Packet> {
+        self.packets
+            .read()
+            .unwrap()
+            .last_before(id, self.time)
+            .map(|(_, p)| p.clone())
+    }
+    /// Replace the address mappings. An empty list restores the DBC addresses.
+    pub fn set_mappings(&mut self, mappings: Vec<AddressMapping>) {
+        self.mappings = mappings;
+        self.pgns = self.mapped_pgns();
+        self.rebuild_rows();
+    }
+
+    /// Replace the DBC definitions, such as after the file was edited.
+    pub fn set_pgns(&mut self, pgns: Vec<PgnDefinition>) {
+        self.dbc_pgns = pgns;
+        self.pgns = self.mapped_pgns();
+        self.rebuild_rows();
+    }
+
+    fn mapped_pgns(&self) -> Vec<PgnDefinition> {
+        self.apply_mappings(&self.dbc_pgns)
+    }
+
+    fn apply_mappings(&self, pgns: &[PgnDefinition]) -> Vec<PgnDefinition> {
+        let repo = self.packets.read().unwrap();
+        let mut mappings = self.mappings.clone();
+        if self.auto_match {
+            // duplicate everything else for each SA seen
+            mappings.push(AddressMapping::default());
+        }
+        address_map::apply(&mappings, pgns, |id| repo.source_addresses(self.key(id)))
+    }
+
+    /// Ids of a DBC message after address mapping, one for each address it is mapped to.
+    pub fn mapped_ids(&self, dbc_id: u32) -> Vec<u32> {
+        let Some(pgn) = self
+            .dbc_pgns
             .iter()
-            .map(|pgn_def| {
-                let k = pgn_def.id;
-                let mut pgn_definition = pgn_def.clone();
-                if (0xFF & k) == f {
-                    pgn_definition.id = 0xFFFFFF00 & k | t;
-                }
-                pgn_definition
+            .find(|p| dbc_key(p.id) == dbc_key(dbc_id))
+        else {
+            return Vec::new();
+        };
+        self.apply_mappings(std::slice::from_ref(pgn))
+            .iter()
+            .map(|p| p.id)
+            .collect()
+    }
+
+    /// Payloads of a DBC message, separately for each address it is mapped to.
+    pub fn payloads(&self, dbc_id: u32) -> Vec<Vec<Vec<u8>>> {
+        let mapped = self.mapped_ids(dbc_id);
+        let repo = self.packets.read().unwrap();
+        mapped
+            .iter()
+            .filter_map(|id| repo.get_for(self.key(*id)))
+            .map(|packets| packets.iter().map(|p| p.payload.clone()).collect())
+            .collect()
+    }
+
+    /// Message id and name of each signal, for choosing a signal in other windows.
+    pub fn signal_names(&self) -> Vec<(u32, String)> {
+        self.rows
+            .iter()
+            .map(|r| (r.pgn.id, r.spn.name.clone()))
+            .collect()
+    }
+
+    /// Decoded values of a signal over time.
+    pub fn series(&self, id: u32, name: &str) -> Vec<(Duration, f64)> {
+        let Some(row) = self
+            .rows
+            .iter()
+            .find(|r| r.pgn.id == id && r.spn.name == name)
+        else {
+            return Vec::new();
+        };
+        let repo = self.packets.read().unwrap();
+        repo.get_for(self.key(id))
+            .map(|packets| {
+                packets
+                    .iter()
+                    .filter_map(|p| Some((p.time().unwrap_or_default(), row.decode(p)?)))
+                    .collect()
             })
-            .collect();
+            .unwrap_or_default()
+    }
+
+    /// DBC messages sent from source addresses other than the DBC's.
+    pub fn discover(&self) -> Vec<Discovered> {
+        let repo = self.packets.read().unwrap();
+        address_map::discover(&self.dbc_pgns, |id| repo.source_addresses(self.key(id)))
+    }
+
+    pub fn set_auto_match(&mut self, auto_match: bool) {
+        self.auto_match = auto_match;
+        self.pgns = self.mapped_pgns();
+        self.rebuild_rows();
+    }
+    pub fn auto_match(&self) -> bool {
+        self.auto_match
+    }
+
+    /// Add rows for newly seen source addresses of mappings that duplicate messages.
+    pub fn update_mapped_addresses(&mut self) {
+        if !self.auto_match && self.mappings.iter().all(|m| m.to.is_some()) {
+            return;
+        }
+        let pgns = self.mapped_pgns();
+        if pgns.iter().map(|p| p.id).ne(self.pgns.iter().map(|p| p.id)) {
+            self.pgns = pgns;
+            self.rebuild_rows();
+        }
+    }
+
+    /// Recalculate rows, keeping Hide Inactive and sort.
+    fn rebuild_rows(&mut self) {
+        self.set_rows(calc_rows(&self.pgns));
+        if self.hide_missing {
+            self.remove_missing();
+        }
+        if let Some((col, descending)) = self.sort {
+            self.sort(
+                col,
+                if descending {
+                    Order::Descending
+                } else {
+                    Order::Ascending
+                },
+            );
+        }
     }
 
     pub(crate) fn toggle_missing(&mut self) {
-        if calc_rows(&self.pgns).len() == self.rows.len() {
+        self.hide_missing = !self.hide_missing;
+        if self.hide_missing {
             self.remove_missing();
         } else {
-            self.rows = calc_rows(&self.pgns);
+            self.set_rows(calc_rows(&self.pgns));
+        }
+    }
+    pub fn hide_missing(&self) -> bool {
+        self.hide_missing
+    }
+
+    /// Load alarm limits, which are saved to the same file when changed. Their commands
+    /// don't run until `allow_commands`, as the file may come with someone else's DBC.
+    pub fn load_alarms(&mut self, path: PathBuf) -> Result<()> {
+        self.alarms = alarm::load_alarms(&path)?;
+        self.alarms_path = Some(path);
+        Ok(())
+    }
+
+    /// Alarm commands that won't run until the user allows them.
+    pub fn blocked_commands(&self) -> Vec<String> {
+        let mut commands: Vec<String> = self
+            .alarms
+            .values()
+            .filter_map(|a| a.command.clone())
+            .filter(|c| !self.allowed_commands.contains(c))
+            .collect();
+        commands.sort();
+        commands.dedup();
+        commands
+    }
+
+    /// Let the loaded alarm commands run, until the application exits.
+    pub fn allow_commands(&mut self) {
+        self.allowed_commands.extend(self.blocked_commands());
+    }
+
+    /// Alarm key, signal name and alarm limits for a row. Rows may be rebuilt while the alarm
+    /// is edited, so it is saved by key.
+    pub fn alarm(&self, row: usize) -> Option<(String, String, AlarmLimits)> {
+        self.rows.get(row).map(|row| {
+            (
+                row.alarm_key(),
+                row.spn.name.clone(),
+                self.alarms
+                    .get(&row.alarm_key())
+                    .cloned()
+                    .unwrap_or_default(),
+            )
+        })
+    }
+
+    /// Set or remove the alarm with a key from `alarm`, on all source addresses.
+    pub fn set_alarm(&mut self, key: String, limits: Option<AlarmLimits>) -> Result<()> {
+        match limits.filter(|l| !l.is_empty()) {
+            Some(limits) => {
+                // entered by the user
+                self.allowed_commands.extend(limits.command.clone());
+                self.alarms.insert(key, limits)
+            }
+            None => self.alarms.remove(&key),
+        };
+        match &self.alarms_path {
+            Some(path) => alarm::save_alarms(path, &self.alarms),
+            None => Ok(()),
+        }
+    }
+
+    pub fn alarm_events(&self) -> AlarmEvents {
+        self.events.clone()
+    }
+
+    /// Alarm violation for a row at `time`.
+    fn violation(&self, row: &Row, time: Duration) -> Option<Violation> {
+        let limits = self.alarms.get(&row.alarm_key())?;
+        let repo = self.packets.read().unwrap();
+        let id = self.key(row.pgn.id);
+        let value_at = |index: usize| {
+            let packet = &repo.get_for(id)?[index];
+            row.decode(packet)
+                .map(|v| (packet.time().unwrap_or_default(), v))
+        };
+        let (index, _) = repo.last_before(id, time)?;
+        let previous = index.checked_sub(1).and_then(value_at);
+        limits.check(
+            value_at(index),
+            previous,
+            Duration::min(repo.last_time(), time),
+        )
+    }
+
+    /// Check alarms against the latest packets, raising events for new violations.
+    pub fn check_alarms(&mut self) {
+        if self.alarms.is_empty() {
+            return;
+        }
+        let mut raised = Vec::new();
+        let mut active = HashSet::new();
+        for row in &self.rows {
+            if let Some(violation) = self.violation(row, Duration::MAX) {
+                let key = (row.pgn.id, row.spn.name.clone());
+                if !self.active_alarms.contains(&key) {
+                    raised.push((row.alarm_key(), row.spn.name.clone(), violation));
+                }
+                active.insert(key);
+            }
+        }
+        self.active_alarms = active;
+        if raised.is_empty() {
+            return;
+        }
+        let time = self.packets.read().unwrap().last_time();
+        for (key, signal, violation) in raised {
+            let event = AlarmEvent {
+                wall: Local::now(),
+                time,
+                signal,
+                violation,
+            };
+            let limits = &self.alarms[&key];
+            match &limits.command {
+                Some(command) if self.allowed_commands.contains(command) => {
+                    alarm::run_command(command, &event)
+                }
+                Some(command) => eprintln!("Alarm command not allowed: {command}"),
+                None => {}
+            }
+            if limits.marker {
+                self.packets.write().unwrap().add_marker(
+                    time,
+                    MarkerKind::Alarm,
+                    format!("ALARM {}: {}", event.signal, event.violation),
+                );
+            }
+            self.events.lock().unwrap().push(event);
         }
     }
 
     pub fn set_line_length(&mut self, line_length: Duration) {
         self.line_length = line_length;
     }
+    pub fn line_length(&self) -> Duration {
+        self.line_length
+    }
+    pub fn mappings(&self) -> &[AddressMapping] {
+        &self.mappings
+    }
+    pub fn sort_order(&self) -> Option<(usize, bool)> {
+        self.sort
+    }
 }
 
 fn calc_rows(pgns: &[PgnDefinition]) -> Vec<Row> {
@@ -147,8 +522,14 @@ impl SimpleModel for DbcModel {
         COLUMNS.len()
     }
 
+    /// The sorted column is marked, including a sort restored from a session.
     fn header(&mut self, col: usize) -> String {
-        COLUMNS[col].into()
+        match self.sort {
+            Some((c, descending)) if c == col => {
+                format!("{} {}", COLUMNS[col], if descending { "▼" } else { "▲" })
+            }
+            _ => COLUMNS[col].into(),
+        }
     }
 
     fn column_width(&mut self, col: usize) -> u32 {
@@ -166,14 +547,15 @@ impl SimpleModel for DbcModel {
     }
 
     fn cell(&mut self, row: i32, col: i32) -> Option<String> {
-        let row = self.rows.get(row as usize).expect("Unknown row requested");
+        let index = row as usize;
+        let row = self.rows.get(index).expect("Unknown row requested");
 
         match col {
             0 => Some(format!("{:08X}", row.pgn.id)),
-            1 => Some(format!("{:04X}", row.pgn.pgn())),
-            2 => Some(format!("{:02X}", row.pgn.sa())),
+            1 => Some(pgn_text(frame_id(row.pgn.id))),
+            2 => Some(sa_text(frame_id(row.pgn.id))),
             3 => Some(row.spn.name.clone()),
-            4 => Some(self.spn_value(row)),
+            4 => Some(self.spn_value(index)),
             6 => Some(self.packet_string(&row.pgn)),
             7 => Some(row.spn.description.clone()),
             _ => None,
@@ -182,9 +564,18 @@ impl SimpleModel for DbcModel {
 
     fn cell_delegate(&mut self, row: i32, col: i32) -> Option<Box<dyn DrawDelegate>> {
         match col {
+            4 => {
+                let index = row as usize;
+                let row = self.rows.get(index).expect("Unknown row requested");
+                self.violation(row, self.time).map(|_| {
+                    Box::new(AlarmCell {
+                        text: self.spn_value(index),
+                    }) as Box<dyn DrawDelegate>
+                })
+            }
             5 => {
                 let row = self.rows.get(row as usize).expect("Unknown row requested");
-                let id = row.pgn.id & 0x3FFFFFF;
+                let id = self.key(row.pgn.id);
                 let repo = self.packets.read().unwrap();
                 let packets = repo.get_for(id);
                 if packets.is_none() || packets.unwrap().is_empty() {
@@ -206,7 +597,17 @@ impl SimpleModel for DbcModel {
                     .iter()
                     .filter_map(|p| row.decode(p))
                     .collect();
-                Some(Box::new(SparkLine::new(data)) as Box<dyn DrawDelegate>)
+                let span = (end - start).as_secs_f64();
+                let marks = repo
+                    .markers()
+                    .iter()
+                    .filter(|m| span > 0.0 && (start..=end).contains(&m.time))
+                    .map(|m| (m.time - start).as_secs_f64() / span)
+                    .collect();
+                Some(Box::new(Chart {
+                    line: SparkLine::new(data),
+                    marks,
+                }) as Box<dyn DrawDelegate>)
             }
             _ => None,
         }
@@ -214,31 +615,38 @@ impl SimpleModel for DbcModel {
 
     fn sort<'a>(&mut self, column: usize, order: Order) {
         if let Order::None = order {
+            self.sort = None;
             return;
         }
+        self.sort = Some((column, matches!(order, Order::Descending)));
 
-        self.rows = match column {
-            0 => sort_with(&self.rows, |row: &Row| row.pgn.id),
-            1 => sort_with(&self.rows, |row: &Row| row.pgn.pgn()),
-            2 => sort_with(&self.rows, |row: &Row| row.pgn.sa()),
-            3 => sort_with(&self.rows, |row: &Row| row.spn.name.clone()),
-            4 | 5 => sort_with(&self.rows, |row| self.spn_value(row)),
-            6 => sort_with(&self.rows, |row: &Row| self.packet_string(&row.pgn)),
-            7 => sort_with(&self.rows, |row: &Row| row.spn.description.clone()),
+        let mut rows = match column {
+            0 => sort_with(&self.rows, |_, row| row.pgn.id),
+            1 => sort_with(&self.rows, |_, row| row.pgn.pgn()),
+            2 => sort_with(&self.rows, |_, row| row.pgn.sa()),
+            3 => sort_with(&self.rows, |_, row| row.spn.name.clone()),
+            4 | 5 => sort_with(&self.rows, |index, _| self.spn_value(index)),
+            6 => sort_with(&self.rows, |_, row| self.packet_string(&row.pgn)),
+            7 => sort_with(&self.rows, |_, row| row.spn.description.clone()),
             _ => panic!("unknown column"),
         };
         match order {
-            Order::Descending => self.rows.reverse(),
+            Order::Descending => rows.reverse(),
             Order::Ascending | Order::None => (),
         }
+        self.set_rows(rows);
     }
 }
 
-fn sort_with<T: Ord>(the_rows: &[Row], extract_fn: impl Fn(&Row) -> T) -> Vec<Row> {
-    let values: HashMap<&Row, T> = the_rows.iter().map(|row| (row, extract_fn(row))).collect();
-    let mut rows: Vec<Row> = the_rows.into();
-    rows.sort_by(|a: &Row, b: &Row| values.get(a).cmp(&values.get(b)));
-    rows
+/// Rows sorted by a value of each row, from its index and the row.
+fn sort_with<T: Ord>(the_rows: &[Row], extract_fn: impl Fn(usize, &Row) -> T) -> Vec<Row> {
+    let mut keyed: Vec<(T, &Row)> = the_rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| (extract_fn(index, row), row))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, row)| row.clone()).collect()
 }
 
 #[derive(Clone, Debug)]
@@ -247,10 +655,12 @@ struct Row {
     pgn: PgnDefinition,
 }
 impl Row {
+    /// Alarms apply to every source address sending the signal.
+    fn alarm_key(&self) -> String {
+        format!("{} {}", pgn_text(frame_id(self.pgn.id)), self.spn.name)
+    }
     fn decode(&self, packet: &Packet) -> Option<f64> {
-        self.spn
-            .parse_message(packet.payload.as_slice())
-            .map(|v| v as f64)
+        dbc::decode(&self.spn, &packet.payload)
     }
 }
 impl Hash for Row {
@@ -276,4 +686,67 @@ mod tests {
         assert_eq!(v.partition_point(|&x| x <= 3.0), 3);
         assert_eq!(v.partition_point(|&x| x <= 3.5), 3);
     }
+
+    /// A signal past the first 8 bytes of a CAN FD payload.
+    #[test]
+    fn fd_signal() {
+        use super::*;
+        use crate::fd::FD;
+        let pgns = dbc::load_test_messages(
+            "BO_ 2566853120 PropB_FD: 64 ECU\n SG_ Late : 96|16@1+ (0.5,0) [0|32767.5] \"\" Vector__XXX\n",
+        );
+        let packets: Arc<RwLock<PacketRepo>> = Default::default();
+        let mut model = DbcModel::new(pgns, packets.clone());
+        let mut payload = vec![0; 16];
+        payload[12] = 0x10;
+        payload[13] = 0x02;
+        packets.write().unwrap().push_from(
+            0,
+            J1939Packet::new(Some(Duration::from_secs(1)), 0, 0x18FF1200, &payload),
+            FD,
+        );
+        assert_eq!(model.cell(0, 4), Some("264.000 ".into()));
+    }
+
+    /// A million packets 1ms apart, scrubbed like the time slider does on every redraw. Slow
+    /// in debug builds, time it with `cargo test --release -- --ignored scrub`.
+    #[test]
+    #[ignore]
+    fn scrub() {
+        use super::*;
+        let pgns = dbc::load_test_messages(dbc::TEST_EEC1);
+        let packets: Arc<RwLock<PacketRepo>> = Default::default();
+        {
+            let mut repo = packets.write().unwrap();
+            for i in 0..1_000_000u64 {
+                let raw = ((i % 8000) as u16 * 8).to_le_bytes();
+                let payload = [0xFF, 0xFF, 0xFF, raw[0], raw[1], 0xFF, 0xFF, 0xFF];
+                let time = Some(Duration::from_millis(i));
+                repo.push(J1939Packet::new(time, 0, 0x0CF004FE, &payload));
+            }
+        }
+        let mut model = DbcModel::new(pgns, packets.clone());
+        for i in 0..100_000u64 {
+            let ms = i * 9_973 % 1_000_000;
+            model.set_time(Duration::from_millis(ms));
+            let rpm = (ms % 8000) as f64;
+            assert_eq!(model.cell(0, 4), Some(format!("{rpm:0.3} rpm")));
+        }
+    }
+
+    #[test]
+    fn alarm_commands() {
+        use super::*;
+        let mut model = DbcModel::new(Vec::new(), Default::default());
+        model.alarms.insert(
+            "F004 Engine Speed".into(),
+            AlarmLimits {
+                command: Some("echo $ALARM_SIGNAL".into()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(model.blocked_commands(), ["echo $ALARM_SIGNAL"]);
+        model.allow_commands();
+        assert!(model.blocked_commands().is_empty());
+    }
 }
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, time::Duration};
+
+use can_adapter::j1939::j1939_packet::J1939Packet;
+
+use crate::packet_repo::{da, pgn_of, sa};
+
+/// ETP.CM, connection management
+pub const ETP_CM: u32 = 0xC800;
+/// ETP.DT, data transfer
+pub const ETP_DT: u32 = 0xC700;
+
+/// ETP.CM and ETP.DT frames.
+pub fn is_etp(id: u32) -> bool {
+    matches!(pgn_of(id), ETP_CM | ETP_DT)
+}
+
+const RTS: u8 = 20;
+const DPO: u8 = 22;
+const ABORT: u8 = 255;
+
+/// Largest ETP message, 2^24 - 1 packets of 7 bytes
+const MAX_SIZE: usize = 16_777_215 * 7;
+
+/// J1939-21 T2/T3, time waiting for data or a CTS
+const TIMEOUT: Duration = Duration::from_millis(1250);
+
+/// A message reassembled from an ETP session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// time of the last data packet
+    pub time: Duration,
+    pub id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn packet(&self) -> J1939Packet {
+        J1939Packet::new(Some(self.time), 0, self.id, &self.payload)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Session {
+    id: u32,
+    /// message size from the RTS
+    size: usize,
+    /// grown as packets arrive
+    data: Vec<u8>,
+    /// packets arrived, by packet number
+    arrived: Vec<bool>,
+    /// count of packets arrived
+    received: usize,
+    /// packet offset from the last DPO
+    offset: u32,
+    last: Duration,
+}
+
+/// Reassembles Extended Transport Protocol (RTS/CTS, over 1785 bytes) sessions between any
+/// two addresses from raw frames.
+#[derive(Clone, Debug, Default)]
+pub struct EtpReceiver {
+    /// (source, destination) -> session
+    sessions: HashMap<(u8, u8), Session>,
+}
+
+impl EtpReceiver {
+    /// Check one frame, returning the message when all of its packets have arrived.
+    pub fn receive(&mut self, id: u32, time: Duration, data: &[u8]) -> Option<Message> {
+        self.sessions
+            .retain(|_, s| time.saturating_sub(s.last) <= TIMEOUT);
+        let (source, destination) = (sa(id), da(id));
+        match pgn_of(id) {
+            ETP_CM if data.len() >= 8 => match data[0] {
+                RTS => {
+                    let size = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize;
+                    if size == 0 || size > MAX_SIZE {
+                        return None;
+                    }
+                    let pgn = u32::from_le_bytes([data[5], data[6], data[7], 0]);
+                    let pgn = if pgn & 0xFF00 < 0xF000 {
+                        pgn | destination as u32
+                    } else {
+                        pgn
+                    };
+                    self.sessions.insert(
+                        (source, destination),
+                        Session {
+                            id: id & 0x1C000000 | pgn << 8 | source as u32,
+                            size,
+                            data: Vec::new(),
+                            arrived: Vec::new(),
+                            received: 0,
+                            offset: 0,
+                            last: time,
+                        },
+                    );
+                    None
+                }
+                DPO => {
+                    let s = self.sessions.get_mut(&(source, destination))?;
+                    s.offset = u32::from_le_bytes([data[2], data[3], data[4], 0]);
+                    s.last = time;
+                    None
+                }
+                // either side may abort
+                ABORT => {
+                    self.sessions.remove(&(source, destination));
+                    self.sessions.remove(&(destination, source));
+                    None
+                }
+                _ => None,
+            },
+            ETP_DT if data.len() >= 2 && data[0] > 0 => {
+                let s = self.sessions.get_mut(&(source, destination))?;
+                s.last = time;
+                let packet = (s.offset + data[0] as u32 - 1) as usize;
+                let start = packet * 7;
+                let end = (start + 7).min(s.size);
+                if start >= end {
+                    return None;
+                }
+                if s.data.len() < end {
+                    s.data.resize(end, 0xFF);
+                    s.arrived.resize(packet + 1, false);
+                }
+                let len = (end - start).min(data.len() - 1);
+                s.data[start..start + len].copy_from_slice(&data[1..=len]);
+                if !s.arrived[packet] {
+                    s.arrived[packet] = true;
+                    s.received += 1;
+                }
+                if s.received < s.size.div_ceil(7) {
+                    return None;
+                }
+                let s = self.sessions.remove(&(source, destination))?;
+                Some(Message {
+                    time,
+                    id: s.id,
+                    payload: s.data,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTS: u8 = 21;
+    const EOMA: u8 = 23;
+
+    /// Frames for an ETP transfer of `payload` from 0x00 to 0xF9, with CTS windows of 255 packets.
+    fn session(pgn: u32, payload: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let p = pgn.to_le_bytes();
+        let size = (payload.len() as u32).to_le_bytes();
+        let mut frames = vec![(
+            0x18C8F900,
+            vec![RTS, size[0], size[1], size[2], size[3], p[0], p[1], p[2]],
+        )];
+        let packets: Vec<&[u8]> = payload.chunks(7).collect();
+        for (window, chunk) in packets.chunks(255).enumerate() {
+            let offset = (window * 255) as u32;
+            let o = offset.to_le_bytes();
+            let next = (offset + 1).to_le_bytes();
+            frames.push((
+                0x18C800F9,
+                vec![
+                    CTS,
+                    chunk.len() as u8,
+                    next[0],
+                    next[1],
+                    next[2],
+                    p[0],
+                    p[1],
+                    p[2],
+                ],
+            ));
+            frames.push((
+                0x18C8F900,
+                vec![DPO, chunk.len() as u8, o[0], o[1], o[2], p[0], p[1], p[2]],
+            ));
+            for (seq, data) in chunk.iter().enumerate() {
+                let mut frame = vec![seq as u8 + 1];
+                frame.extend_from_slice(data);
+                frame.resize(8, 0xFF);
+                frames.push((0x1CC7F900, frame));
+            }
+        }
+        frames.push((
+            0x18C800F9,
+            vec![EOMA, size[0], size[1], size[2], size[3], p[0], p[1], p[2]],
+        ));
+        frames
+    }
+
+    fn run(etp: &mut EtpReceiver, frames: &[(u32, Vec<u8>)]) -> Vec<Message> {
+        frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (id, data))| etp.receive(*id, Duration::from_millis(i as u64), data))
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_with_dpo() {
+        // 3 CTS windows, last packet partly filled
+        let payload: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+        let mut etp = EtpReceiver::default();
+        let messages = run(&mut etp, &session(0xD700, &payload));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, payload);
+        // PDU1 PGN gets the destination, priority from the RTS
+        assert_eq!(messages[0].id, 0x18D7F900);
+        assert!(etp.sessions.is_empty());
+
+        let global: Vec<u8> = vec![7; 1786];
+        let messages = run(&mut etp, &session(0xFECA, &global));
+        assert_eq!(messages[0].id, 0x18FECA00);
+        assert_eq!(messages[0].payload, global);
+    }
+
+    #[test]
+    fn abort_and_timeout() {
+        let payload = vec![1; 2000];
+        let mut frames = session(0xD700, &payload);
+        frames.insert(
+            10,
+            (0x18C800F9, vec![ABORT, 3, 0xFF, 0xFF, 0xFF, 0, 0xD7, 0]),
+        );
+        let mut etp = EtpReceiver::default();
+        assert!(run(&mut etp, &frames).is_empty());
+
+        let frames = session(0xD700, &payload);
+        let mut etp = EtpReceiver::default();
+        let (first, rest) = frames.split_at(10);
+        run(&mut etp, first);
+        let late: Vec<Message> = rest
+            .iter()
+            .filter_map(|(id, data)| etp.receive(*id, Duration::from_secs(2), data))
+            .collect();
+        assert!(late.is_empty());
+    }
+
+    #[test]
+    fn missing_packets() {
+        let payload = vec![1; 2000];
+        // a data packet from the middle is lost, the last one arrives
+        let mut frames = session(0xD700, &payload);
+        frames.remove(20);
+        let mut etp = EtpReceiver::default();
+        assert!(run(&mut etp, &frames).is_empty());
+        // or arrives late, at the end of its CTS window
+        let mut frames = session(0xD700, &payload);
+        let late = frames.remove(20);
+        frames.insert(257, late);
+        assert_eq!(frames[258].1[0], CTS);
+        let mut etp = EtpReceiver::default();
+        assert_eq!(run(&mut etp, &frames)[0].payload, payload);
+
+        // over 2^24 - 1 packets is not ETP
+        let mut etp = EtpReceiver::default();
+        etp.receive(
+            0x18C8F900,
+            Duration::ZERO,
+            &[RTS, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0xD7, 0],
+        );
+        assert!(etp.sessions.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::packet_repo::is_standard;
+
+/// Frame flags kept next to each packet in `PacketRepo`. The adapters only report the data
+/// length of a frame, so FD is set for frames over 8 bytes.
+pub const FD: u8 = 0x01;
+/// A PG unpacked from a multi-PG frame after the first one, which shares that frame on the
+/// bus.
+pub const CONTAINED: u8 = 0x08;
+
+/// A raw transport protocol frame, logged to show a session. Its reassembled message is logged
+/// too.
+pub const RAW: u8 = 0x10;
+
+/// J1939-22 Multi-PG message, carrying several contained PGs in one FD frame.
+pub const MULTI_PG: u32 = 0x2500;
+
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Data length for a DLC code.
+pub fn dlc_to_len(dlc: u8) -> usize {
+    FD_LENGTHS[(dlc & 0xF) as usize]
+}
+
+/// Smallest DLC code that holds `len` bytes.
+pub fn len_to_dlc(len: usize) -> u8 {
+    FD_LENGTHS.iter().position(|l| *l >= len).unwrap_or(15) as u8
+}
+
+/// Flags as shown after a log line.
+pub fn flags_text(flags: u8) -> String {
+    if flags & FD != 0 {
+        " FD".into()
+    } else {
+        String::new()
+    }
+}
+
+/// Flags for the PGs unpacked from a multi-PG frame, so the frame is counted once.
+pub fn contained_flags(flags: u8, count: usize) -> impl Iterator<Item = u8> {
+    (0..count).map(move |i| if i == 0 { flags } else { flags | CONTAINED })
+}
+
+/// Split a multi-PG payload into (id, payload) for each contained PG. The contained ids
+/// use the priority and source address of the container. Destination specific (PDU1) PGs are
+/// sent to the container's destination.
+///
+/// Each C-PG starts with a 4 byte little endian header: TOS (3 bits), trailer format
+/// (3 bits), contained PGN (18 bits) and payload length (8 bits), from the most significant
+/// bit down. A TOS of 0 ends the list, the rest of the frame is padding.
+pub fn unpack_multi_pg(id: u32, payload: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut contained = Vec::new();
+    let mut rest = payload;
+    while let [a, b, c, d, tail @ ..] = rest {
+        let header = u32::from_le_bytes([*a, *b, *c, *d]);
+        let tos = header >> 29;
+        if tos == 0 {
+            break;
+        }
+        let pgn = (header >> 8) & 0x3FFFF;
+        let len = (header & 0xFF) as usize;
+        let Some(data) = tail.get(..len) else {
+            break;
+        };
+        // data page and PDU format of the contained PGN
+        let pf = pgn & 0x3FF00;
+        let ps = if pgn & 0xFF00 < 0xF000 {
+            (id >> 8) & 0xFF
+        } else {
+            pgn & 0xFF
+        };
+        contained.push((id & 0x1C0000FF | (pf | ps) << 8, data.to_vec()));
+        rest = &tail[len..];
+    }
+    contained
+}
+
+/// Bits for an FD frame with a `PacketRepo` id at the nominal bit rate, with an estimate of
+/// stuffing.
+pub fn fd_frame_bits(id: u32, len: usize) -> f64 {
+    let len = dlc_to_len(len_to_dlc(len));
+    // SOF, 11 bit id, RRS, IDE, FDF, res, BRS, ESI, DLC
+    let mut header = 1 + 11 + 1 + 1 + 1 + 1 + 1 + 1 + 4;
+    if !is_standard(id) {
+        // SRR and the rest of the 29 bit id
+        header += 1 + 18;
+    }
+    // stuff count and CRC
+    let crc = 4 + if len <= 16 { 17 } else { 21 };
+    // CRC delimiter, ACK, EOF, IFS
+    let fixed = 1 + 2 + 7 + 3;
+    let stuffed = header + 8 * len;
+    (stuffed + crc + fixed) as f64 + (stuffed - 1) as f64 / 4.0 / 2.0
+}
+
+/// Bits a contained PG adds to the multi-PG frame carrying it: its header and data, with an
+/// estimate of stuffing. The frame itself is counted with the first PG.
+pub fn contained_bits(len: usize) -> f64 {
+    let bits = (8 * (4 + len)) as f64;
+    bits + bits / 4.0 / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(tos: u32, pgn: u32, len: u8) -> [u8; 4] {
+        (tos << 29 | pgn << 8 | len as u32).to_le_bytes()
+    }
+
+    #[test]
+    fn dlc() {
+        assert_eq!(len_to_dlc(8), 8);
+        assert_eq!(len_to_dlc(9), 9);
+        assert_eq!(len_to_dlc(64), 15);
+        assert_eq!(dlc_to_len(13), 32);
+        assert_eq!(flags_text(FD), " FD");
+        assert_eq!(flags_text(FD | CONTAINED), " FD");
+        assert_eq!(flags_text(0), "");
+        assert_eq!(
+            contained_flags(FD, 3).collect::<Vec<_>>(),
+            [FD, FD | CONTAINED, FD | CONTAINED]
+        );
+    }
+
+    #[test]
+    fn multi_pg() {
+        let mut payload = Vec::new();
+        payload.extend(header(2, 0xF004, 8));
+        payload.extend([1; 8]);
+        // PDU1, to the container's destination
+        payload.extend(header(2, 0xEF00, 3));
+        payload.extend([2; 3]);
+        payload.extend(header(0, 0, 0));
+        payload.resize(24, 0xAA);
+
+        let pgs = unpack_multi_pg(0x0C2517FE, &payload);
+        assert_eq!(pgs, [(0x0CF004FE, vec![1; 8]), (0x0CEF17FE, vec![2; 3]),]);
+        // truncated
+        assert_eq!(unpack_multi_pg(0x0C2517FE, &payload[..10]), []);
+    }
+}
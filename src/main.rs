@@ -1,23 +1,49 @@
 #![windows_subsystem = "windows"]
 
+mod address_map;
+mod alarm;
+mod anomaly;
+mod bridge;
+mod channel;
+mod dbc;
+mod dbc_editor;
 mod dbc_table;
+mod etp;
+mod fd;
+mod mdf4;
+mod monitor;
+mod obd;
 mod packet_model;
 mod packet_repo;
+mod report;
+mod reverse;
+mod session;
+mod stats;
+mod tp;
+mod trigger;
+mod uds;
+mod validate;
 
 #[derive(RustEmbed)]
 #[folder = "assets/"]
 struct Asset;
 
 use std::{
+    cell::RefCell,
+    collections::HashSet,
     fs::File,
-    io::{BufWriter, Write},
+    io::BufWriter,
     option::Option,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, RwLock},
-    thread,
+    thread::{self, JoinHandle},
     time::Duration,
 };
 
-use anyhow::Error;
+use address_map::{AddressMapping, Discovered};
+use anomaly::anomaly_window;
+use anyhow::{anyhow, Error};
+use bridge::{bridge_window, Bridge};
 #[cfg(windows)]
 use can_adapter::rp1210;
 use can_adapter::{
@@ -25,36 +51,63 @@ use can_adapter::{
     j1939::{j1939_packet::J1939Packet, J1939},
     ConnectionDescriptor,
 };
-use canparse::pgn::PgnLibrary;
-use clap::Parser;
-use dbc_table::DbcModel;
+use canparse::pgn::PgnDefinition;
+use channel::{channels_window, parse_channels, Channel, Connections};
+use clap::{CommandFactory, Parser};
+use dbc_editor::dbc_editor_window;
+use dbc_table::{DbcModel, DbcTable, DbcTables};
+use etp::{is_etp, EtpReceiver};
 use fltk::{
     app::{self, copy},
+    browser::{HoldBrowser, MultiBrowser},
     button::Button,
-    dialog::{message_default, message_icon_label, FileDialog, FileDialogType::BrowseMultiFile},
-    enums::{Font, Mode, Shortcut},
+    dialog::{
+        alert_default, choice2_default, input_default, FileDialog, FileDialogType::BrowseMultiFile,
+    },
+    draw,
+    enums::{Align, Color, Event, Font, Mode, Shortcut},
     frame::Frame,
     group::{Flex, Pack, PackType},
     image::PngImage,
     input::Input,
-    menu::{self, MenuFlag, SysMenuBar},
+    menu::{self, MenuButton, MenuButtonType, MenuFlag, SysMenuBar},
     output::Output,
-    prelude::{GroupExt, InputExt, MenuExt, ValuatorExt, WidgetBase, WidgetExt, WindowExt},
+    prelude::{
+        BrowserExt, GroupExt, InputExt, MenuExt, TableExt, ValuatorExt, WidgetBase, WidgetExt,
+        WindowExt,
+    },
     valuator::HorNiceSlider,
     window::Window,
 };
+use monitor::monitor_window;
+use obd::obd_window;
 use packet_model::PacketModel;
-use packet_repo::PacketRepo;
+use packet_repo::{frame_id, pgn_of, write_packets, MarkerKind, PacketRepo};
+use report::{report, report_err};
+use reverse::reverse_window;
 use rust_embed::RustEmbed;
-use simple_table::joe_table::JoeTable;
+use session::{AdapterChoice, DbcSession, Session};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+use stats::stats_window;
 use timer::Timer;
+use tp::{is_tp, tp_window, TpTracker};
+use trigger::{triggers_window, Triggers};
+use uds::{diag_window, Diagnostics};
+use validate::validation_window;
 
 #[derive(Parser, Debug)]
 #[command(name = "logger")]
 #[command(version,about = "CAN logger", long_about = None)]
 struct Cli {
     #[clap(subcommand)]
-    connection_descriptor: ConnectionDescriptor,
+    connection_descriptor: Option<ConnectionDescriptor>,
+
+    /// Restore a saved session (adapter, DBC windows and their settings).
+    #[clap(long)]
+    session: Option<PathBuf>,
 
     #[clap(short, long)]
     dbc: Vec<String>,
@@ -66,39 +119,138 @@ fn main() -> Result<(), anyhow::Error> {
     // repaint the table in a timer
     let timer = Arc::new(Timer::new());
     let packets = Arc::new(RwLock::new(PacketRepo::default()));
+    let dbc_tables: DbcTables = Default::default();
+    let connections: Connections = Default::default();
+    let triggers: Arc<Mutex<Triggers>> = Default::default();
+    let tp: Arc<Mutex<TpTracker>> = Default::default();
+    let diagnostics: Arc<Mutex<Diagnostics>> = Default::default();
+    let bridge: Arc<Mutex<Bridge>> = Default::default();
+
+    report::use_dialogs();
 
     // handle command line
     let cli = Cli::try_parse();
-    let connection = match cli {
+    let mut log_column_widths = Vec::new();
+    match cli {
         Ok(cli) => {
+            if let Some(path) = cli.session {
+                if let Some(session) = report_err("Unable to open session", Session::load(&path)) {
+                    log_column_widths = session.log_column_widths.clone();
+                    restore_session(
+                        &session,
+                        packets.clone(),
+                        timer.clone(),
+                        dbc_tables.clone(),
+                        &connections,
+                    );
+                }
+            }
             for file in cli.dbc {
-                load_dbc_window(packets.clone(), timer.clone(), file.into())?
+                report_err(
+                    "Unable to load DBC",
+                    load_dbc_window(
+                        packets.clone(),
+                        timer.clone(),
+                        dbc_tables.clone(),
+                        DbcSession::new(file.into()),
+                    ),
+                );
+            }
+            if let Some(connection_descriptor) = cli.connection_descriptor {
+                if let Ok(conn) = connection_descriptor.connect() {
+                    let args: Vec<String> = std::env::args().skip(1).collect();
+                    let choice = AdapterChoice::Args(adapter_args(&args));
+                    channel::open(&connections, choice, conn)?;
+                }
             }
-            cli.connection_descriptor.connect().ok()
         }
         Err(msg) => {
             eprintln!("{msg}");
-            None
         }
     };
 
-    let connection: Arc<Mutex<Option<Box<dyn Connection>>>> = Arc::new(Mutex::new(connection));
     {
-        let connection = connection.clone();
+        let connections = connections.clone();
         let packets = packets.clone();
+        let triggers = triggers.clone();
+        let tp = tp.clone();
+        let diagnostics = diagnostics.clone();
+        let bridge = bridge.clone();
         thread::Builder::new()
             .name("main:packet copy".to_owned())
             .spawn(move || {
+                // one reader per open channel
+                let mut readers: Vec<(Arc<dyn Connection>, JoinHandle<()>)> = Vec::new();
                 loop {
-                    // get iterator from connection if possible
-                    if let Some(connection) = (*connection.lock().unwrap()).as_deref() {
-                        let mut iter = connection.iter().flatten().map(|p| p.into());
-                        let addr = 0xF9;
-                        let iter = J1939::receive_tp(connection, addr, false, &mut iter);
-                        // make sure to unlock between writes.
-                        iter.for_each(|p| packets.write().unwrap().push(p));
+                    // readers finish when their channel is closed or the adapter stops
+                    let finished;
+                    (finished, readers) = readers
+                        .into_iter()
+                        .partition(|(_, reader)| reader.is_finished());
+                    for (connection, _) in finished {
+                        if let Some(channel) = channel::lost(&connections, &connection) {
+                            let lost = {
+                                let health = channel.health.lock().unwrap();
+                                health.lost.and_then(|at| health.packet_time(at))
+                            };
+                            let mut repo = packets.write().unwrap();
+                            let time = lost.unwrap_or_else(|| repo.last_time());
+                            repo.add_marker(
+                                time,
+                                MarkerKind::Adapter,
+                                format!("Lost adapter {}", channel.name()),
+                            );
+                        }
+                    }
+                    // reopen lost adapters, such as after a USB unplug
+                    for (number, adapter) in channel::retry_due(&connections) {
+                        let result = open_adapter(&adapter);
+                        if let Some((gap, time)) = channel::reopened(&connections, number, result) {
+                            let mut repo = packets.write().unwrap();
+                            let time = time.unwrap_or_else(|| repo.last_time());
+                            repo.add_marker(
+                                time,
+                                MarkerKind::Adapter,
+                                format!(
+                                    "Reconnected channel {number} after {:.1} s",
+                                    gap.as_secs_f64()
+                                ),
+                            );
+                        }
                     }
-                    // either no connection or connection closed.
+                    let open = connections.lock().unwrap().clone();
+                    for channel in open {
+                        let Some(connection) = channel.connection.clone() else {
+                            continue;
+                        };
+                        if readers.iter().any(|(c, _)| channel.is(c)) {
+                            continue;
+                        }
+                        let connections = connections.clone();
+                        let packets = packets.clone();
+                        let triggers = triggers.clone();
+                        let tp = tp.clone();
+                        let diagnostics = diagnostics.clone();
+                        let bridge = bridge.clone();
+                        match thread::Builder::new()
+                            .name(format!("main:channel {}", channel.number))
+                            .spawn(move || {
+                                read_channel(
+                                    channel,
+                                    connections,
+                                    packets,
+                                    triggers,
+                                    tp,
+                                    diagnostics,
+                                    bridge,
+                                )
+                            }) {
+                            Ok(reader) => readers.push((connection, reader)),
+                            Err(err) => eprintln!("{err}"),
+                        }
+                    }
+                    // finish captures when the bus goes quiet
+                    triggers.lock().unwrap().save_idle(&packets.read().unwrap());
                     thread::sleep(Duration::from_millis(200));
                 }
             })?;
@@ -114,15 +266,28 @@ fn main() -> Result<(), anyhow::Error> {
     let pack = Pack::default_fill();
 
     let mut menu = SysMenuBar::default().with_size(100, 35);
+    let mut table = JoeTable::new(PacketModel::new(packets.clone()));
     {
         let timer = timer.clone();
         let packets = packets.clone();
+        let dbc_tables = dbc_tables.clone();
+        let connections = connections.clone();
         menu.add(
             "&Action/@fileopen Load DBC...\t",
             Shortcut::None,
             menu::MenuFlag::Normal,
-            move |_b| {
-                dbc_window(packets.clone(), timer.clone()).expect("Canceled");
+            move |m| {
+                report_err(
+                    "Unable to load DBC",
+                    dbc_window(packets.clone(), timer.clone(), dbc_tables.clone()),
+                );
+                let open = file_opener(
+                    packets.clone(),
+                    timer.clone(),
+                    dbc_tables.clone(),
+                    connections.clone(),
+                );
+                update_recent_menu(m, open);
             },
         );
     }
@@ -133,12 +298,45 @@ fn main() -> Result<(), anyhow::Error> {
             Shortcut::None,
             menu::MenuFlag::Normal,
             move |_| {
-                save_log(list.read().unwrap().packets()).expect("Unable to save packet log.");
+                report_err("Unable to save packet log", save_log(&list.read().unwrap()));
+            },
+        );
+    }
+    {
+        let list = packets.clone();
+        let dbc_tables = dbc_tables.clone();
+        menu.add(
+            "&Action/Export MDF4...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                let pgns: Vec<(Option<u8>, PgnDefinition)> = dbc_tables
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .flat_map(|t| {
+                        let table = t.table.lock().unwrap();
+                        let model = table.model.lock().unwrap();
+                        let channel = model.channel();
+                        model
+                            .pgns()
+                            .iter()
+                            .map(|pgn| (channel, pgn.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                report_err(
+                    "Unable to export MDF4",
+                    export_mdf4(&list.read().unwrap(), &pgns),
+                );
             },
         );
     }
     {
         let packets = packets.clone();
+        let diagnostics = diagnostics.clone();
+        let tp = tp.clone();
+        let triggers = triggers.clone();
         menu.add(
             "&Action/@refresh Clear\t",
             Shortcut::None,
@@ -148,13 +346,137 @@ fn main() -> Result<(), anyhow::Error> {
                     .write()
                     .expect("Unable to lock model for clear.")
                     .clear();
+                diagnostics.lock().unwrap().clear();
+                tp.lock().unwrap().clear();
+                triggers.lock().unwrap().clear();
             },
         );
     }
 
-    let mut table = JoeTable::new(PacketModel::new(packets.clone()));
+    {
+        let triggers = triggers.clone();
+        let dbc_tables = dbc_tables.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Triggers...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                triggers_window(triggers.clone(), dbc_tables.clone(), timer.clone());
+            },
+        );
+    }
+    {
+        let connections = connections.clone();
+        let packets = packets.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/OBD-II...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                obd_window(connections.clone(), packets.clone(), &timer);
+            },
+        );
+    }
+    {
+        let diagnostics = diagnostics.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Diagnostics (UDS)...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                diag_window(diagnostics.clone(), &timer);
+            },
+        );
+    }
+    {
+        let tp = tp.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Transport Sessions...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                tp_window(tp.clone(), &timer);
+            },
+        );
+    }
+    {
+        let tp = tp.clone();
+        menu.add(
+            "&Action/Show TP Frames\t",
+            Shortcut::None,
+            menu::MenuFlag::Toggle,
+            move |m| {
+                tp.lock().unwrap().show_raw = m.mvalue().is_some_and(|i| i.value());
+            },
+        );
+    }
+    {
+        let packets = packets.clone();
+        let dbc_tables = dbc_tables.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Bus Statistics...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                stats_window(packets.clone(), dbc_tables.clone(), &timer);
+            },
+        );
+    }
+    {
+        let packets = packets.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Monitor...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| monitor_window(packets.clone(), &timer),
+        );
+    }
+    {
+        let packets = packets.clone();
+        let dbc_tables = dbc_tables.clone();
+        let timer = timer.clone();
+        menu.add(
+            "&Action/Timing Anomalies...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                anomaly_window(packets.clone(), dbc_tables.clone(), &timer);
+            },
+        );
+    }
+    {
+        let packets = packets.clone();
+        let dbc_tables = dbc_tables.clone();
+        menu.add(
+            "&Action/Reverse Engineering...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| reverse_window(packets.clone(), dbc_tables.clone()),
+        );
+    }
+    add_session_menu(
+        &mut menu,
+        packets.clone(),
+        timer.clone(),
+        dbc_tables.clone(),
+        connections.clone(),
+        table.clone(),
+    );
+
     table.set_font(Font::Courier, 8);
     table.init();
+    // sessions from before the channel column have one width
+    if log_column_widths.len() == table.cols() as usize {
+        for (col, width) in log_column_widths.iter().enumerate() {
+            table.set_col_width(col as i32, *width as i32);
+        }
+    }
     {
         let mut table = table.clone();
         menu.add(
@@ -174,28 +496,113 @@ fn main() -> Result<(), anyhow::Error> {
             Shortcut::Ctrl | 'c',
             menu::MenuFlag::Normal,
             move |_| {
+                let model = table.model.lock().unwrap();
                 let read = list.read().expect("Unable to lock model for copy.");
-                let collect: Vec<String> = read.packets()[table.get_selection()]
-                    .iter()
+                let collect: Vec<String> = table
+                    .get_selection()
+                    .filter_map(|row| model.index(&read, row))
+                    .filter_map(|i| read.packets().get(i))
                     .map(|p| format!("{p}"))
                     .collect();
                 copy(collect.join("\n").as_str());
             },
         );
     }
+    {
+        let mut table = table.clone();
+        menu.add(
+            "&Edit/Channel Filter...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                let current = table.model.lock().unwrap().channels().map(|c| {
+                    let mut c: Vec<u8> = c.iter().copied().collect();
+                    c.sort();
+                    c.iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                });
+                let Some(text) = input_default(
+                    "Channels to show, such as 0,2. Empty for all.",
+                    &current.unwrap_or_default(),
+                ) else {
+                    return;
+                };
+                match parse_channels(&text) {
+                    Ok(channels) => {
+                        table.model.lock().unwrap().set_channels(channels);
+                        table.redraw();
+                    }
+                    Err(err) => alert_default(&format!("{err}")),
+                }
+            },
+        );
+    }
+    {
+        let mut table = table.clone();
+        menu.add(
+            "&Edit/Changes Only\t",
+            Shortcut::None,
+            menu::MenuFlag::Toggle,
+            move |m| {
+                table
+                    .model
+                    .lock()
+                    .unwrap()
+                    .set_changes_only(m.mvalue().is_some_and(|i| i.value()));
+                table.redraw();
+            },
+        );
+    }
+    {
+        let packets = packets.clone();
+        menu.add(
+            "&Edit/Add Annotation...\t",
+            Shortcut::Ctrl | 'm',
+            menu::MenuFlag::Normal,
+            move |_| {
+                // the time the key was pressed, not when the note is finished
+                let time = packets.read().unwrap().last_time();
+                add_annotation(&packets, time)
+            },
+        );
+    }
+    for (label, shortcut, forward) in [
+        ("&Edit/Next Annotation\t", Shortcut::Ctrl | 'n', true),
+        ("&Edit/Previous Annotation\t", Shortcut::Ctrl | 'p', false),
+    ] {
+        let mut table = table.clone();
+        menu.add(label, shortcut, menu::MenuFlag::Normal, move |_| {
+            jump_to_annotation(&mut table, forward)
+        });
+    }
 
-    add_rp1210_menu(&mut menu, connection.clone())?;
+    add_rp1210_menu(
+        &mut menu,
+        connections.clone(),
+        bridge.clone(),
+        timer.clone(),
+    )?;
 
     menu.add(
         "&Action/How to...\t",
         Shortcut::None,
         menu::MenuFlag::Normal,
         move |_b| {
-            webbrowser::open("https://github.com/SolidDesignNet/j1939logger/blob/main/README.md")
-                .expect("Unable to open web browser.");
+            if let Err(err) = webbrowser::open(
+                "https://github.com/SolidDesignNet/j1939logger/blob/main/README.md",
+            ) {
+                report("Unable to open web browser", &Error::from(err));
+            }
         },
     );
 
+    // adapter state, frame rate and errors
+    let mut status = Frame::default()
+        .with_size(100, 20)
+        .with_align(Align::Left | Align::Inside);
+
     pack.resizable(&*table);
     pack.end();
 
@@ -206,9 +613,43 @@ fn main() -> Result<(), anyhow::Error> {
             .expect("Unable to read icon png.")
             .data,
     )?));
+    {
+        // save the session on exit
+        let table = table.clone();
+        let dbc_tables = dbc_tables.clone();
+        let connections = connections.clone();
+        wind.set_callback(move |_| {
+            // Escape closes other windows, not the logger
+            if app::event() != Event::Close {
+                return;
+            }
+            let others = app::windows().map_or(0, |w| w.len()) > 1;
+            if others
+                && choice2_default(
+                    "Quit the logger? The other windows will close too.",
+                    "Cancel",
+                    "Quit",
+                    "",
+                ) != Some(1)
+            {
+                return;
+            }
+            let session = current_session(&connections, &table, &dbc_tables);
+            if let Err(err) = session.save(&session::last_session_path()) {
+                eprintln!("{err:#}");
+            }
+            app::quit();
+        });
+    }
     wind.show();
 
     table.redraw_on(&timer, chrono::Duration::milliseconds(200));
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(500), move || {
+            status.set_label(&channel::status(&connections));
+            status.redraw();
+        })
+        .ignore();
 
     // run the app
     app.run()?;
@@ -216,7 +657,117 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn dbc_window(packets: Arc<RwLock<PacketRepo>>, timer: Arc<Timer>) -> Result<(), anyhow::Error> {
+/// Copy packets from one channel into the log until the channel is closed or the adapter
+/// stops.
+fn read_channel(
+    channel: Channel,
+    connections: Connections,
+    packets: Arc<RwLock<PacketRepo>>,
+    triggers: Arc<Mutex<Triggers>>,
+    tp: Arc<Mutex<TpTracker>>,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    bridge: Arc<Mutex<Bridge>>,
+) {
+    let number = channel.number;
+    let Some(current) = channel.connection.clone() else {
+        return;
+    };
+    // ETP is not handled by receive_tp
+    let mut etp = EtpReceiver::default();
+    // ETP messages reassembled in `inspect`, logged before the frame that completed them
+    let reassembled: RefCell<Vec<J1939Packet>> = Default::default();
+    // raw frames over 8 bytes, which are FD, as (id, time)
+    let fd_frames: RefCell<HashSet<(u32, Duration)>> = Default::default();
+    // stop reading when the channel is closed
+    let open = || connections.lock().unwrap().iter().any(|c| c.is(&current));
+    let mut iter = current
+        .iter()
+        .take_while(|_| open())
+        .flatten()
+        .map(|p| {
+            let p: J1939Packet = p.into();
+            // the frame size is only known from the adapter's id
+            J1939Packet::new(p.time(), 0, frame_id(p.id()), &p.payload)
+        })
+        .inspect(|p: &J1939Packet| {
+            let time = p.time().unwrap_or_default();
+            channel.health.lock().unwrap().frame(time);
+        })
+        // frames the bridge sent were logged when sent
+        .filter(|p: &J1939Packet| !bridge.lock().unwrap().is_echo(number, p.id(), &p.payload))
+        .inspect(|p: &J1939Packet| {
+            bridge::forward(&bridge, &connections, &packets, number, p);
+            // TP frames are consumed by receive_tp
+            let time = p.time().unwrap_or_default();
+            let mut tp = tp.lock().unwrap();
+            tp.observe(p.id(), time, &p.payload);
+            if tp.show_raw && is_tp(p.id()) {
+                packets
+                    .write()
+                    .unwrap()
+                    .push_from(number, p.clone(), fd::RAW);
+            }
+            if let Some(m) = etp.receive(p.id(), time, &p.payload) {
+                reassembled.borrow_mut().push(m.packet());
+            }
+            if p.payload.len() > 8 {
+                fd_frames.borrow_mut().insert((p.id(), time));
+            }
+        });
+    let addr = 0xF9;
+    let iter = J1939::receive_tp(current.as_ref(), addr, false, &mut iter);
+    // make sure to unlock between writes.
+    let log = |p: J1939Packet, flags: u8| {
+        let time = p.time().unwrap_or_default();
+        // J1939-22 containers are logged as the PGs they carry
+        let contained: Vec<J1939Packet> = if pgn_of(p.id()) == fd::MULTI_PG {
+            fd::unpack_multi_pg(p.id(), &p.payload)
+                .into_iter()
+                .map(|(id, data)| J1939Packet::new(p.time(), 0, id, &data))
+                .collect()
+        } else {
+            vec![p]
+        };
+        let flags = fd::contained_flags(flags, contained.len());
+        let mut triggers = triggers.lock().unwrap();
+        for (p, flags) in contained.into_iter().zip(flags) {
+            triggers.check(p.id(), time, &p.payload);
+            diagnostics
+                .lock()
+                .unwrap()
+                .receive(p.id(), time, &p.payload);
+            packets.write().unwrap().push_from(number, p, flags);
+        }
+        triggers.save_due(&packets.read().unwrap());
+    };
+    iter.for_each(|p| {
+        for m in reassembled.borrow_mut().drain(..) {
+            log(m, 0);
+        }
+        let time = p.time().unwrap_or_default();
+        let flags = if fd_frames.borrow_mut().remove(&(p.id(), time)) {
+            fd::FD
+        } else {
+            0
+        };
+        if is_etp(p.id()) {
+            if tp.lock().unwrap().show_raw {
+                packets
+                    .write()
+                    .unwrap()
+                    .push_from(number, p, flags | fd::RAW);
+            }
+            return;
+        }
+        log(p, flags);
+    });
+}
+
+fn dbc_window(
+    packets: Arc<RwLock<PacketRepo>>,
+    timer: Arc<Timer>,
+    dbc_tables: DbcTables,
+) -> Result<(), anyhow::Error> {
     let mut fc = FileDialog::new(BrowseMultiFile);
     fc.set_filter("*.dbc");
     fc.show();
@@ -225,18 +776,55 @@ fn dbc_window(packets: Arc<RwLock<PacketRepo>>, timer: Arc<Timer>) -> Result<(),
         return Ok(());
     }
     let path = fc.filename();
-    load_dbc_window(packets, timer, path)
+    session::add_recent_file(&path).ok();
+    load_dbc_window(packets, timer, dbc_tables, DbcSession::new(path))
 }
 
 fn load_dbc_window(
     packets: Arc<RwLock<PacketRepo>>,
     timer: Arc<Timer>,
-    path: std::path::PathBuf,
+    dbc_tables: DbcTables,
+    settings: DbcSession,
 ) -> Result<(), Error> {
+    let path = settings.path.clone();
     let filename = path.to_str().unwrap_or_default();
-    let pgns = PgnLibrary::from_dbc_file(path.clone())
-        .unwrap_or_else(|_| panic!("Unable to read dbc file {filename}."));
-    let model = DbcModel::new(pgns.pgns.values().cloned().collect(), packets.clone());
+    let pgns = dbc::load(&path)?;
+    let mut model = DbcModel::new(pgns.pgns.values().cloned().collect(), packets.clone());
+    if let Err(err) = model.load_alarms(alarm::alarms_path(&path)) {
+        report("Unable to load alarms", &err);
+    }
+    let commands = model.blocked_commands();
+    if !commands.is_empty() {
+        let question = format!(
+            "The alarms of {} run these commands:\n\n{}\n\nAllow them to run?",
+            path.display(),
+            commands.join("\n")
+        );
+        if choice2_default(&question, "Don't Run", "Allow", "") == Some(1) {
+            model.allow_commands();
+        }
+    }
+    model.set_mappings(settings.mappings.clone());
+    if settings.auto_match {
+        model.set_auto_match(true);
+    }
+    if settings.hide_inactive {
+        model.toggle_missing();
+    }
+    if let Some((col, descending)) = settings.sort {
+        model.sort(
+            col,
+            if descending {
+                Order::Descending
+            } else {
+                Order::Ascending
+            },
+        );
+    }
+    model.set_line_length(settings.chart_duration);
+    if settings.channel.is_some() {
+        model.set_channel(settings.channel);
+    }
 
     let mut wind = Window::default().with_size(600, 300).with_label(filename);
     wind.set_icon(Some(PngImage::from_data(
@@ -266,9 +854,9 @@ fn load_dbc_window(
     time_slider.set_tooltip("How mch time should charts represent.");
     line_length_slider.set_maximum(10.0);
     line_length_slider.set_minimum(1.0);
-    line_length_slider.set_value(10.0);
+    line_length_slider.set_value(settings.chart_duration.as_secs_f64().log2());
     let mut line_length = Output::default().with_size(80, 20);
-    line_length.set_value("Max");
+    line_length.set_value(&format!("{:0.2}", settings.chart_duration.as_secs_f64()));
 
     // Why doesn't slider resize?
     hbox.resizable(&time_slider.as_base_widget());
@@ -277,6 +865,9 @@ fn load_dbc_window(
     let mut table = JoeTable::new(model);
     table.set_font(Font::Helvetica, 8);
     table.init();
+    for (col, width) in settings.column_widths.iter().enumerate() {
+        table.set_col_width(col as i32, *width as i32);
+    }
 
     pack.resizable(&*table);
     pack.end();
@@ -285,6 +876,79 @@ fn load_dbc_window(
     table.redraw_on(&timer, redraw_period);
 
     let table = Arc::new(Mutex::new(table));
+    {
+        // unregister on close
+        let table = table.clone();
+        dbc_tables.lock().unwrap().push(DbcTable {
+            path: path.clone(),
+            table: table.clone(),
+        });
+        wind.set_callback(move |w| {
+            dbc_tables
+                .lock()
+                .unwrap()
+                .retain(|t| !Arc::ptr_eq(&t.table, &table));
+            w.hide();
+        });
+    }
+    {
+        // annotations and other markers
+        let packets = packets.clone();
+        time_slider.draw(move |s| {
+            let (min, max) = (s.minimum(), s.maximum());
+            if max <= min {
+                return;
+            }
+            draw::set_draw_color(Color::Red);
+            for m in packets.read().unwrap().markers() {
+                let f = (m.time.as_secs_f64() - min) / (max - min);
+                let x = s.x() + (f * (s.w() - 1) as f64) as i32;
+                draw::draw_line(x, s.y(), x, s.y() + s.h() - 1);
+            }
+        });
+    }
+    {
+        let table = table.clone();
+        let packets = packets.clone();
+        menu.add(
+            "Action/Add Annotation...",
+            Shortcut::Ctrl | 'm',
+            MenuFlag::Normal,
+            move |_| {
+                // at the time shown, when scrolled back
+                let time = table.lock().unwrap().model.lock().unwrap().time();
+                let time = time.min(packets.read().unwrap().last_time());
+                add_annotation(&packets, time)
+            },
+        );
+    }
+    for (label, forward) in [
+        ("Action/Next Annotation", true),
+        ("Action/Previous Annotation", false),
+    ] {
+        let table = table.clone();
+        let packets = packets.clone();
+        let mut time_slider = time_slider.clone();
+        let mut time = time.clone();
+        menu.add(label, Shortcut::None, MenuFlag::Normal, move |_| {
+            let table = table.lock().unwrap();
+            let mut model = table.model.lock().unwrap();
+            let marker = {
+                let repo = packets.read().unwrap();
+                let now = model.time().min(repo.last_time());
+                if forward {
+                    repo.annotation_after(now).map(|m| m.time)
+                } else {
+                    repo.annotation_before(now).map(|m| m.time)
+                }
+            };
+            if let Some(t) = marker {
+                model.set_time(t);
+                time_slider.set_value(t.as_secs_f64());
+                time.set_value(&format!("{:0.2}", t.as_secs_f64()));
+            }
+        });
+    }
     {
         let table = table.clone();
         time_slider.set_callback(move |s| {
@@ -326,26 +990,98 @@ fn load_dbc_window(
                 .set_line_length(Duration::from_secs_f64(val));
         });
     }
-    timer
-        .schedule_repeating(redraw_period, move || {
-            let (min, max) = {
-                let packet_repo = packets.read().unwrap();
-                (packet_repo.first_time(), packet_repo.last_time())
-            };
-            time_slider.set_minimum(min.as_secs_f64());
-            time_slider.set_maximum(max.as_secs_f64());
-            time_slider.damage();
-        })
-        .ignore();
+    {
+        let table = table.clone();
+        timer
+            .schedule_repeating(redraw_period, move || {
+                let (min, max) = {
+                    let packet_repo = packets.read().unwrap();
+                    (packet_repo.first_time(), packet_repo.last_time())
+                };
+                table
+                    .lock()
+                    .unwrap()
+                    .model
+                    .lock()
+                    .unwrap()
+                    .update_mapped_addresses();
+                table.lock().unwrap().model.lock().unwrap().check_alarms();
+                time_slider.set_minimum(min.as_secs_f64());
+                time_slider.set_maximum(max.as_secs_f64());
+                time_slider.damage();
+            })
+            .ignore();
+    }
 
     {
         let table = table.clone();
         menu.add(
-            "Action/Map Address...",
+            "Action/Channel...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| {
+                let channel = table.lock().unwrap().model.lock().unwrap().channel();
+                let Some(text) = input_default(
+                    "Decode packets from channel. Empty for any channel.",
+                    &channel.map(|c| c.to_string()).unwrap_or_default(),
+                ) else {
+                    return;
+                };
+                match parse_channels(&text) {
+                    Ok(Some(channels)) if channels.len() > 1 => {
+                        alert_default("Only one channel may be selected.")
+                    }
+                    Ok(channels) => {
+                        let channel = channels.and_then(|c| c.into_iter().next());
+                        let mut table = table.lock().unwrap();
+                        table.model.lock().unwrap().set_channel(channel);
+                        table.redraw();
+                    }
+                    Err(err) => alert_default(&format!("{err}")),
+                }
+            },
+        );
+    }
+    {
+        let table = table.clone();
+        menu.add(
+            "Action/Address Mappings...",
             Shortcut::None,
             MenuFlag::Normal,
             move |_| {
-                map_address_wizard(table.clone());
+                address_map_editor(table.clone());
+            },
+        );
+    }
+    {
+        let table = table.clone();
+        menu.add(
+            "Action/Discover Addresses...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| {
+                discover_addresses_window(table.clone());
+            },
+        );
+    }
+    {
+        let table = table.clone();
+        menu.add(
+            "Action/Auto Match Addresses",
+            Shortcut::None,
+            if settings.auto_match {
+                MenuFlag::Toggle | MenuFlag::Value
+            } else {
+                MenuFlag::Toggle
+            },
+            move |m| {
+                let simple_table = &mut table.lock().expect("Unable to lock simple table.");
+                simple_table
+                    .model
+                    .lock()
+                    .expect("Unable to lock model.")
+                    .set_auto_match(m.mvalue().is_some_and(|i| i.value()));
+                simple_table.redraw();
             },
         );
     }
@@ -354,7 +1090,11 @@ fn load_dbc_window(
         menu.add(
             "Action/Hide Inactive",
             Shortcut::None,
-            MenuFlag::Toggle,
+            if settings.hide_inactive {
+                MenuFlag::Toggle | MenuFlag::Value
+            } else {
+                MenuFlag::Toggle
+            },
             move |_| {
                 let simple_table = &mut table.lock().expect("Unable to lock simple table.");
                 simple_table
@@ -400,6 +1140,62 @@ fn load_dbc_window(
             },
         );
     }
+    {
+        let table = table.clone();
+        menu.add(
+            "&Edit/Alarm Limits...\t",
+            Shortcut::Ctrl | 'l',
+            menu::MenuFlag::Normal,
+            move |_| edit_alarm(table.clone()),
+        );
+    }
+    {
+        let table = table.clone();
+        let timer = timer.clone();
+        let filename = filename.to_string();
+        menu.add(
+            "Action/Alarm Events...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| {
+                let events = table.lock().unwrap().model.lock().unwrap().alarm_events();
+                alarm::alarm_events_window(&filename, events, &timer);
+            },
+        );
+    }
+    {
+        let table = table.clone();
+        let path = path.clone();
+        menu.add(
+            "Action/Edit DBC...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| dbc_editor_window(path.clone(), table.clone()),
+        );
+    }
+    {
+        let table = table.clone();
+        menu.add(
+            "Action/Validate...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| validation_window(path.clone(), table.clone()),
+        );
+    }
+
+    // right click context menu
+    let mut popup = MenuButton::default()
+        .size_of(&wind)
+        .with_type(MenuButtonType::Popup3);
+    {
+        let table = table.clone();
+        popup.add(
+            "Alarm Limits...",
+            Shortcut::None,
+            MenuFlag::Normal,
+            move |_| edit_alarm(table.clone()),
+        );
+    }
 
     wind.end();
     wind.resizable(&wind);
@@ -407,24 +1203,73 @@ fn load_dbc_window(
     Ok(())
 }
 
-fn map_address_wizard(table: Arc<Mutex<JoeTable<DbcModel>>>) {
+/// Edit the alarm of the selected row.
+fn edit_alarm(table: Arc<Mutex<JoeTable<DbcModel>>>) {
+    let selection = table
+        .lock()
+        .expect("Unable to lock simple table.")
+        .selection
+        .lock()
+        .unwrap()
+        .clone();
+    if selection.is_empty() {
+        alert_default("Select a signal to edit its alarm.");
+        return;
+    }
+    let row = selection.start as usize;
+    let alarm = table
+        .lock()
+        .expect("Unable to lock simple table.")
+        .model
+        .lock()
+        .expect("Unable to lock model.")
+        .alarm(row);
+    if let Some((key, signal, limits)) = alarm {
+        alarm::alarm_editor(&signal, limits, move |limits| {
+            table
+                .lock()
+                .expect("Unable to lock simple table.")
+                .model
+                .lock()
+                .expect("Unable to lock model.")
+                .set_alarm(key.clone(), limits)
+        });
+    }
+}
+
+fn address_map_editor(table: Arc<Mutex<JoeTable<DbcModel>>>) {
     let mut wind = Window::default()
-        .with_size(100, 180)
-        .with_label("Map Address");
+        .with_size(320, 300)
+        .with_label("Address Mappings");
 
-    let pack = Flex::default_fill()
+    let mut pack = Flex::default_fill()
         .with_type(PackType::Vertical)
         .size_of(&wind);
 
-    Frame::default().with_label("From (hex)");
-    let mut from = Input::default().with_size(35, 35);
+    let help = Frame::default().with_label("FROM:TO@PGN in hex, * for any. First match wins.");
+    pack.fixed(&help, 20);
+    let mut browser = HoldBrowser::default_fill();
+    browser.set_tooltip("TO of * duplicates the message for every source address on the bus.");
+
+    let inputs = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("From");
+    let mut from = Input::default();
     from.set_value("FE");
-    Frame::default().with_label("To (hex)");
-    let mut to = Input::default_fill().with_size(35, 35).with_label("To");
+    Frame::default().with_label("To");
+    let mut to = Input::default();
     to.set_value("00");
-    let mut go = Button::default_fill()
-        .with_size(35, 35)
-        .with_label("Update");
+    Frame::default().with_label("PGN");
+    let mut pgn = Input::default();
+    pgn.set_value("*");
+    inputs.end();
+    pack.fixed(&inputs, 30);
+
+    let buttons = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut add = Button::default().with_label("Add");
+    let mut remove = Button::default().with_label("Remove");
+    let mut reset = Button::default().with_label("Reset");
+    buttons.end();
+    pack.fixed(&buttons, 30);
 
     pack.end();
 
@@ -432,38 +1277,182 @@ fn map_address_wizard(table: Arc<Mutex<JoeTable<DbcModel>>>) {
     wind.resizable(&pack);
     wind.show();
 
-    go.set_callback(move |_| {
-        if let (Ok(from), Ok(to)) = (
-            u8::from_str_radix(&from.value(), 16),
-            u8::from_str_radix(&to.value(), 16),
-        ) {
+    let mappings = table
+        .lock()
+        .expect("Unable to lock simple table")
+        .model
+        .lock()
+        .expect("Unable to lock model.")
+        .mappings()
+        .to_vec();
+    mappings.iter().for_each(|m| browser.add(&m.to_string()));
+    let mappings = Arc::new(Mutex::new(mappings));
+
+    // update the model and list
+    let update = {
+        let browser = browser.clone();
+        move |mappings: &[AddressMapping]| {
+            let mut browser = browser.clone();
+            browser.clear();
+            mappings.iter().for_each(|m| browser.add(&m.to_string()));
+            let mut table = table.lock().expect("Unable to lock simple table");
             table
+                .model
+                .lock()
+                .expect("Unable to lock model.")
+                .set_mappings(mappings.to_vec());
+            table.redraw();
+        }
+    };
+    {
+        let mappings = mappings.clone();
+        let update = update.clone();
+        add.set_callback(move |_| {
+            match format!("{}:{}@{}", from.value(), to.value(), pgn.value()).parse() {
+                Ok(mapping) => {
+                    let mut mappings = mappings.lock().unwrap();
+                    mappings.push(mapping);
+                    update(&mappings);
+                }
+                Err(err) => alert_default(&format!("Invalid mapping: {err}")),
+            }
+        });
+    }
+    {
+        let mappings = mappings.clone();
+        let update = update.clone();
+        remove.set_callback(move |_| {
+            let selected = browser.value();
+            let mut mappings = mappings.lock().unwrap();
+            if selected > 0 && selected as usize <= mappings.len() {
+                mappings.remove(selected as usize - 1);
+                update(&mappings);
+            }
+        });
+    }
+    reset.set_callback(move |_| {
+        let mut mappings = mappings.lock().unwrap();
+        mappings.clear();
+        update(&mappings);
+    });
+}
+
+/// Offer mappings for DBC messages sent from other source addresses than the DBC's.
+fn discover_addresses_window(table: Arc<Mutex<JoeTable<DbcModel>>>) {
+    let mut wind = Window::default()
+        .with_size(400, 300)
+        .with_label("Discover Addresses");
+
+    let mut pack = Flex::default_fill()
+        .with_type(PackType::Vertical)
+        .size_of(&wind);
+    let mut browser = MultiBrowser::default_fill();
+    browser.set_tooltip("Messages from the DBC seen on the bus with a different source address.");
+    let buttons = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut refresh = Button::default().with_label("Refresh");
+    let mut add = Button::default().with_label("Add Selected");
+    buttons.end();
+    pack.fixed(&buttons, 30);
+    pack.end();
+
+    wind.end();
+    wind.resizable(&pack);
+    wind.show();
+
+    let discovered: Arc<Mutex<Vec<Discovered>>> = Default::default();
+    let mut update = {
+        let table = table.clone();
+        let discovered = discovered.clone();
+        let mut browser = browser.clone();
+        move || {
+            let found = table
                 .lock()
                 .expect("Unable to lock simple table")
                 .model
                 .lock()
                 .expect("Unable to lock model.")
-                .map_address(from, to);
-            wind.hide();
+                .discover();
+            browser.clear();
+            found.iter().for_each(|d| browser.add(&d.to_string()));
+            *discovered.lock().unwrap() = found;
+        }
+    };
+    update();
+    refresh.set_callback(move |_| update());
+    add.set_callback(move |_| {
+        let discovered = discovered.lock().unwrap();
+        let selected: Vec<AddressMapping> = (1..=browser.size())
+            .filter(|line| browser.selected(*line))
+            .filter_map(|line| discovered.get(line as usize - 1))
+            .map(|d| d.mapping())
+            .collect();
+        let mut table = table.lock().expect("Unable to lock simple table");
+        {
+            let mut model = table.model.lock().expect("Unable to lock model.");
+            let mut mappings = model.mappings().to_vec();
+            mappings.extend(selected);
+            model.set_mappings(mappings);
         }
+        table.redraw();
     });
 }
 
-fn save_log(list: &[J1939Packet]) -> Result<(), Error> {
+/// Ask for a note, such as "customer says it hesitated here", and mark the log with it at
+/// `time`.
+fn add_annotation(packets: &RwLock<PacketRepo>, time: Duration) {
+    let Some(text) = input_default(&format!("Annotation at {:.3} s", time.as_secs_f64()), "")
+    else {
+        return;
+    };
+    if !text.trim().is_empty() {
+        packets
+            .write()
+            .unwrap()
+            .add_marker(time, MarkerKind::Annotation, text.trim().to_string());
+    }
+}
+
+/// Select the first annotation row of the log after, or the last one before, the selected row.
+fn jump_to_annotation(table: &mut JoeTable<PacketModel>, forward: bool) {
+    let rows = table.model.lock().unwrap().annotation_rows();
+    let current = table.get_selection().next();
+    let row = if forward {
+        rows.into_iter().find(|r| current.is_none_or(|c| *r > c))
+    } else {
+        rows.into_iter()
+            .rev()
+            .find(|r| current.is_none_or(|c| *r < c))
+    };
+    if let Some(row) = row {
+        table.select_rows(row..row + 1);
+        table.set_row_position(row as i32);
+        table.redraw();
+    }
+}
+
+fn save_log(repo: &PacketRepo) -> Result<(), Error> {
     let mut fc = FileDialog::new(fltk::dialog::FileDialogType::BrowseSaveFile);
     fc.show();
     if !fc.filenames().is_empty() {
         let mut out = BufWriter::new(File::create(fc.filename())?);
-        for p in list.iter() {
-            out.write_all(p.to_string().as_bytes())?;
-            out.write_all(b"\r\n")?;
-        }
+        write_packets(&mut out, repo, 0..repo.packets().len())?;
+    }
+    Ok(())
+}
+fn export_mdf4(repo: &PacketRepo, pgns: &[(Option<u8>, PgnDefinition)]) -> Result<(), Error> {
+    let mut fc = FileDialog::new(fltk::dialog::FileDialogType::BrowseSaveFile);
+    fc.set_filter("*.mf4");
+    fc.show();
+    if !fc.filenames().is_empty() {
+        mdf4::save_mdf4(&fc.filename(), repo, pgns)?;
     }
     Ok(())
 }
 fn add_rp1210_menu(
     menu: &mut SysMenuBar,
-    connection: Arc<Mutex<Option<Box<dyn Connection>>>>,
+    connections: Connections,
+    bridge: Arc<Mutex<Bridge>>,
+    timer: Arc<Timer>,
 ) -> Result<(), Error> {
     #[cfg(windows)]
     menu.add(
@@ -489,56 +1478,71 @@ fn add_rp1210_menu(
         },
     );
 
-    add_adapters(menu, &connection)?;
+    add_adapters(menu, &connections)?;
 
     {
-        let connection = connection.clone();
+        let connections = connections.clone();
+        let bridge = bridge.clone();
+        let timer = timer.clone();
         menu.add(
-            "&Connection/@|| Stop",
+            "&Connection/Bridge...",
             Shortcut::None,
             menu::MenuFlag::Normal,
             move |_b| {
-                *connection.lock().unwrap() = None;
+                bridge_window(bridge.clone(), connections.clone(), &timer);
             },
         );
     }
+    {
+        let connections = connections.clone();
+        menu.add(
+            "&Connection/Channels...",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_b| {
+                channels_window(connections.clone());
+            },
+        );
+    }
+    menu.add(
+        "&Connection/@|| Stop",
+        Shortcut::None,
+        menu::MenuFlag::Normal,
+        move |_b| {
+            connections.lock().unwrap().clear();
+        },
+    );
     Ok(())
 }
 
-fn add_adapters(
-    menu: &mut SysMenuBar,
-    connection: &Arc<Mutex<Option<Box<dyn Connection>>>>,
-) -> Result<(), Error> {
+/// Menu path of an adapter, also used to find it again when restoring a session.
+fn adapter_name(product: &str, device: &str, factory: &str) -> String {
+    format!("{}/{}/{}", product, device.replace("/", "\\/"), factory)
+}
+
+/// Each adapter opens as a new channel, alongside those already open.
+fn add_adapters(menu: &mut SysMenuBar, connections: &Connections) -> Result<(), Error> {
     for product in connection::enumerate_connections()? {
         for device in product.devices {
             for factory in device.connections {
-                let connection = connection.clone();
-                let name = format!(
-                    "Connection/{}/{}/{}\t",
-                    &product.name,
-                    &device.name.replace("/", "\\/"),
-                    factory.name()
-                );
+                let connections = connections.clone();
+                let adapter_name = adapter_name(&product.name, &device.name, &factory.name());
+                let name = format!("Connection/{adapter_name}\t");
 
                 menu.add(
                     &name.clone(),
                     Shortcut::None,
                     menu::MenuFlag::Normal,
                     move |_b| {
-                        // unload old DLL
-                        *connection.lock().unwrap() = None;
                         eprintln!("LOADING: {name}");
 
                         // load new DLL
-                        match factory.create() {
-                            Ok(conn) => {
-                                *connection.lock().unwrap() = Some(conn);
-                            }
-                            Err(err) => {
-                                message_icon_label("Fail");
-                                message_default(&format!("Failed to open adapter: {err}"));
-                                *connection.lock().unwrap() = None;
-                            }
+                        let choice = AdapterChoice::Menu(adapter_name.clone());
+                        if let Err(err) = factory
+                            .create()
+                            .and_then(|conn| channel::open(&connections, choice, conn))
+                        {
+                            report("Failed to open adapter", &err);
                         }
                     },
                 );
@@ -547,3 +1551,240 @@ fn add_adapters(
     }
     Ok(())
 }
+
+/// The adapter subcommand and its arguments, without the `Cli` options before it such as
+/// `--session` and `--dbc`.
+fn adapter_args(args: &[String]) -> Vec<String> {
+    let command = Cli::command();
+    let takes_value = |arg: &str| {
+        command
+            .get_arguments()
+            .filter(|a| a.get_action().takes_values())
+            .any(|a| {
+                arg.strip_prefix("--")
+                    .is_some_and(|l| a.get_long() == Some(l))
+                    || arg
+                        .strip_prefix('-')
+                        .and_then(|s| s.parse::<char>().ok())
+                        .is_some_and(|c| a.get_short() == Some(c))
+            })
+    };
+    let mut i = 0;
+    while let Some(arg) = args.get(i).filter(|a| a.starts_with('-')) {
+        i += if takes_value(arg) { 2 } else { 1 };
+    }
+    args.get(i..).unwrap_or_default().to_vec()
+}
+
+/// Open the adapter recorded in a session.
+fn open_adapter(choice: &AdapterChoice) -> Result<Box<dyn Connection>, Error> {
+    match choice {
+        AdapterChoice::Menu(name) => {
+            for product in connection::enumerate_connections()? {
+                for device in product.devices {
+                    for factory in device.connections {
+                        if adapter_name(&product.name, &device.name, &factory.name()) == *name {
+                            return factory.create();
+                        }
+                    }
+                }
+            }
+            Err(anyhow!("Adapter {name} not found."))
+        }
+        AdapterChoice::Args(args) => {
+            Cli::try_parse_from(std::iter::once("logger".into()).chain(args.iter().cloned()))?
+                .connection_descriptor
+                .ok_or(anyhow!("No adapter in {args:?}"))?
+                .connect()
+        }
+    }
+}
+
+fn restore_session(
+    session: &Session,
+    packets: Arc<RwLock<PacketRepo>>,
+    timer: Arc<Timer>,
+    dbc_tables: DbcTables,
+    connections: &Connections,
+) {
+    // report each failure and restore the rest
+    for dbc in &session.dbcs {
+        report_err(
+            "Unable to load DBC",
+            load_dbc_window(
+                packets.clone(),
+                timer.clone(),
+                dbc_tables.clone(),
+                dbc.clone(),
+            ),
+        );
+    }
+    if !session.adapters.is_empty() {
+        connections.lock().unwrap().clear();
+    }
+    for choice in &session.adapters {
+        if let Err(err) =
+            open_adapter(choice).and_then(|conn| channel::open(connections, choice.clone(), conn))
+        {
+            report("Failed to open adapter", &err);
+        }
+    }
+}
+
+fn current_session(
+    connections: &Connections,
+    log_table: &JoeTable<PacketModel>,
+    dbc_tables: &DbcTables,
+) -> Session {
+    Session {
+        adapters: connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.adapter.clone())
+            .collect(),
+        log_column_widths: (0..log_table.cols())
+            .map(|c| log_table.col_width(c) as u32)
+            .collect(),
+        dbcs: dbc_tables
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|t| {
+                let table = t.table.lock().unwrap();
+                let model = table.model.lock().unwrap();
+                DbcSession {
+                    path: t.path.clone(),
+                    mappings: model.mappings().to_vec(),
+                    auto_match: model.auto_match(),
+                    hide_inactive: model.hide_missing(),
+                    column_widths: (0..table.cols())
+                        .map(|c| table.col_width(c) as u32)
+                        .collect(),
+                    sort: model.sort_order(),
+                    chart_duration: model.line_length(),
+                    channel: model.channel(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Restore a session or DBC file.
+fn file_opener(
+    packets: Arc<RwLock<PacketRepo>>,
+    timer: Arc<Timer>,
+    dbc_tables: DbcTables,
+    connections: Connections,
+) -> impl Fn(&Path) -> Result<(), Error> + Clone + 'static {
+    move |path: &Path| -> Result<(), Error> {
+        if path.extension().is_some_and(|e| e == "dbc") {
+            load_dbc_window(
+                packets.clone(),
+                timer.clone(),
+                dbc_tables.clone(),
+                DbcSession::new(path.into()),
+            )
+        } else {
+            restore_session(
+                &Session::load(path)?,
+                packets.clone(),
+                timer.clone(),
+                dbc_tables.clone(),
+                &connections,
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Replace the Action/Recent items with the recent files list.
+fn update_recent_menu(
+    menu: &mut SysMenuBar,
+    open: impl Fn(&Path) -> Result<(), Error> + Clone + 'static,
+) {
+    let index = menu.find_index("&Action/Recent");
+    if index >= 0 {
+        menu.clear_submenu(index).ok();
+    }
+    for path in session::recent_files() {
+        let open = open.clone();
+        menu.add(
+            &format!(
+                "&Action/Recent/{}\t",
+                path.display().to_string().replace("/", "\\/")
+            ),
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                report_err("Unable to open recent file", open(&path));
+            },
+        );
+    }
+}
+
+fn add_session_menu(
+    menu: &mut SysMenuBar,
+    packets: Arc<RwLock<PacketRepo>>,
+    timer: Arc<Timer>,
+    dbc_tables: DbcTables,
+    connections: Connections,
+    log_table: JoeTable<PacketModel>,
+) {
+    let open = file_opener(packets, timer, dbc_tables.clone(), connections.clone());
+    {
+        let open = open.clone();
+        menu.add(
+            "&Action/Session/Open Session...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |m| {
+                let mut fc = FileDialog::new(fltk::dialog::FileDialogType::BrowseFile);
+                fc.set_filter("*.session");
+                fc.show();
+                if !fc.filenames().is_empty() {
+                    let path = fc.filename();
+                    session::add_recent_file(&path).ok();
+                    update_recent_menu(m, open.clone());
+                    report_err("Unable to open session", open(&path));
+                }
+            },
+        );
+    }
+    {
+        let open = open.clone();
+        menu.add(
+            "&Action/Session/Restore Last Session\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |_| {
+                report_err(
+                    "Unable to restore last session",
+                    open(&session::last_session_path()),
+                );
+            },
+        );
+    }
+    {
+        let open = open.clone();
+        menu.add(
+            "&Action/Session/Save Session...\t",
+            Shortcut::None,
+            menu::MenuFlag::Normal,
+            move |m| {
+                let mut fc = FileDialog::new(fltk::dialog::FileDialogType::BrowseSaveFile);
+                fc.set_filter("*.session");
+                fc.show();
+                if !fc.filenames().is_empty() {
+                    let path = fc.filename();
+                    let session = current_session(&connections, &log_table, &dbc_tables);
+                    if report_err("Unable to save session", session.save(&path)).is_some() {
+                        session::add_recent_file(&path).ok();
+                        update_recent_menu(m, open.clone());
+                    }
+                }
+            },
+        );
+    }
+    update_recent_menu(menu, open);
+}
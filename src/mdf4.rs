@@ -0,0 +1,644 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use canparse::pgn::PgnDefinition;
+
+use crate::{
+    dbc,
+    fd::{len_to_dlc, FD},
+    packet_repo::{channel_key, dbc_key, ide_id, PacketRepo},
+};
+
+/// Minimal ASAM MDF 4.10 writer.
+///
+/// Raw frames are written as a CAN bus logging channel group (CAN_DataFrame.*) and each
+/// DBC message is written as its own channel group with one channel per signal, using a
+/// linear conversion and the signal units.
+pub struct Mdf4Writer {
+    buf: Vec<u8>,
+    /// last DG written, so the next one can be chained.
+    last_dg: Option<u64>,
+}
+
+/// A raw frame as stored in the bus logging channel group.
+pub struct Frame<'a> {
+    pub time: f64,
+    /// bit 31 set for 29 bit ids
+    pub id: u32,
+    /// `fd` flags
+    pub flags: u8,
+    /// adapter channel the frame was read from
+    pub channel: u8,
+    pub data: &'a [u8],
+}
+
+/// A decoded signal channel.  `raw` values are converted to physical values by readers using
+/// `physical = offset + factor * raw`.
+pub struct Signal {
+    pub name: String,
+    pub unit: String,
+    pub factor: f64,
+    pub offset: f64,
+    pub raw: Vec<f64>,
+}
+
+const HD_OFFSET: u64 = 64;
+
+// data types
+const DT_UINT_LE: u8 = 0;
+const DT_FLOAT_LE: u8 = 4;
+const DT_BYTE_ARRAY: u8 = 10;
+
+// channel types
+const CN_FIXED: u8 = 0;
+const CN_VLSD: u8 = 1;
+const CN_MASTER: u8 = 2;
+
+impl Default for Mdf4Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mdf4Writer {
+    pub fn new() -> Mdf4Writer {
+        let mut w = Mdf4Writer {
+            buf: Vec::new(),
+            last_dg: None,
+        };
+        // ID block
+        w.buf.extend_from_slice(b"MDF     ");
+        w.buf.extend_from_slice(b"4.10    ");
+        w.buf.extend_from_slice(b"j1939log");
+        w.buf.extend_from_slice(&[0; 4]);
+        w.buf.extend_from_slice(&410u16.to_le_bytes());
+        w.buf.extend_from_slice(&[0; 30]);
+        w.buf.extend_from_slice(&[0; 4]);
+
+        // HD block
+        let start_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut data = Vec::new();
+        data.extend_from_slice(&start_ns.to_le_bytes());
+        data.extend_from_slice(&[0; 4]); // tz, dst
+        data.extend_from_slice(&[0; 4]); // time flags, time class, flags, reserved
+        data.extend_from_slice(&0f64.to_le_bytes());
+        data.extend_from_slice(&0f64.to_le_bytes());
+        let hd = w.block(b"##HD", &[0; 6], &data);
+        debug_assert_eq!(hd, HD_OFFSET);
+
+        // FH block, required by MDF 4.1
+        let comment = w.metadata(&format!(
+            "<FHcomment><TX>created</TX><tool_id>{}</tool_id><tool_vendor>SolidDesignNet</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        let mut data = Vec::new();
+        data.extend_from_slice(&start_ns.to_le_bytes());
+        data.extend_from_slice(&[0; 4]); // tz, dst
+        data.extend_from_slice(&[0; 4]); // time flags, reserved
+        let fh = w.block(b"##FH", &[0, comment], &data);
+        w.set_link(HD_OFFSET, 1, fh);
+        w
+    }
+
+    /// Append a block, 8 byte aligned, returning the file offset.
+    fn block(&mut self, id: &[u8; 4], links: &[u64], data: &[u8]) -> u64 {
+        self.buf.resize(self.buf.len().next_multiple_of(8), 0);
+        let offset = self.buf.len() as u64;
+        let len = 24 + 8 * links.len() + data.len();
+        self.buf.extend_from_slice(id);
+        self.buf.extend_from_slice(&[0; 4]);
+        self.buf.extend_from_slice(&(len as u64).to_le_bytes());
+        self.buf
+            .extend_from_slice(&(links.len() as u64).to_le_bytes());
+        for l in links {
+            self.buf.extend_from_slice(&l.to_le_bytes());
+        }
+        self.buf.extend_from_slice(data);
+        offset
+    }
+
+    fn set_link(&mut self, block: u64, index: usize, target: u64) {
+        let at = block as usize + 24 + 8 * index;
+        self.buf[at..at + 8].copy_from_slice(&target.to_le_bytes());
+    }
+
+    fn text(&mut self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        data.resize(data.len().next_multiple_of(8), 0);
+        self.block(b"##TX", &[], &data)
+    }
+
+    fn metadata(&mut self, xml: &str) -> u64 {
+        let mut data = xml.as_bytes().to_vec();
+        data.push(0);
+        data.resize(data.len().next_multiple_of(8), 0);
+        self.block(b"##MD", &[], &data)
+    }
+
+    fn linear_conversion(&mut self, offset: f64, factor: f64) -> u64 {
+        let mut data = vec![1, 0]; // linear, precision
+        data.extend_from_slice(&0u16.to_le_bytes()); // flags
+        data.extend_from_slice(&0u16.to_le_bytes()); // ref count
+        data.extend_from_slice(&2u16.to_le_bytes()); // val count
+        data.extend_from_slice(&0f64.to_le_bytes());
+        data.extend_from_slice(&0f64.to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&factor.to_le_bytes());
+        self.block(b"##CC", &[0; 4], &data)
+    }
+
+    /// Write one channel. Returns the CN offset.
+    #[allow(clippy::too_many_arguments)]
+    fn channel(
+        &mut self,
+        name: &str,
+        unit: &str,
+        cn_type: u8,
+        data_type: u8,
+        byte_offset: u32,
+        bit_count: u32,
+        conversion: u64,
+    ) -> u64 {
+        let name = self.text(name);
+        let unit = self.text(unit);
+        let mut data = vec![cn_type, if cn_type == CN_MASTER { 1 } else { 0 }];
+        data.push(data_type);
+        data.push(0); // bit offset
+        data.extend_from_slice(&byte_offset.to_le_bytes());
+        data.extend_from_slice(&bit_count.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes()); // flags
+        data.extend_from_slice(&0u32.to_le_bytes()); // inval bit pos
+        data.extend_from_slice(&[0, 0, 0, 0]); // precision, reserved, attachment count
+        data.extend_from_slice(&[0; 48]); // ranges and limits
+        self.block(b"##CN", &[0, 0, name, 0, conversion, 0, unit, 0], &data)
+    }
+
+    /// Write a DG/CG pair containing `channels` (already written) and `records`.
+    fn group(&mut self, name: &str, channels: &[u64], record_size: u32, records: &[u8], bus: bool) {
+        for pair in channels.windows(2) {
+            self.set_link(pair[0], 0, pair[1]);
+        }
+        let cycle_count = if record_size == 0 {
+            0
+        } else {
+            records.len() as u64 / record_size as u64
+        };
+        let acq_name = self.text(name);
+        let source = if bus {
+            let bus_name = self.text("CAN");
+            // BUS, CAN
+            self.block(b"##SI", &[bus_name, 0, 0], &[2, 2, 0, 0, 0, 0, 0, 0])
+        } else {
+            0
+        };
+        let dt = self.block(b"##DT", &[], records);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0u64.to_le_bytes()); // record id
+        data.extend_from_slice(&cycle_count.to_le_bytes());
+        data.extend_from_slice(&(if bus { 0x0002u16 } else { 0 }).to_le_bytes()); // bus event
+        data.extend_from_slice(&(b'.' as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&record_size.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        let cg = self.block(
+            b"##CG",
+            &[0, *channels.first().unwrap_or(&0), acq_name, source, 0, 0],
+            &data,
+        );
+        let dg = self.block(b"##DG", &[0, cg, dt, 0], &[0; 8]);
+        match self.last_dg {
+            Some(prev) => self.set_link(prev, 0, dg),
+            None => self.set_link(HD_OFFSET, 0, dg),
+        }
+        self.last_dg = Some(dg);
+    }
+
+    /// Add the raw frames as a CAN bus logging channel group.
+    pub fn add_frames<'a>(&mut self, frames: impl Iterator<Item = Frame<'a>>) {
+        // record: time f64, id u32, dlc u8, data length u32, data bytes VLSD offset u64,
+        // channel u8
+        const RECORD_SIZE: u32 = 8 + 4 + 1 + 4 + 8 + 1;
+        let mut records = Vec::new();
+        let mut sd = Vec::new();
+        for f in frames {
+            records.extend_from_slice(&f.time.to_le_bytes());
+            records.extend_from_slice(&f.id.to_le_bytes());
+            records.push(if f.flags & FD != 0 {
+                len_to_dlc(f.data.len())
+            } else {
+                f.data.len().min(8) as u8
+            });
+            records.extend_from_slice(&(f.data.len() as u32).to_le_bytes());
+            records.extend_from_slice(&(sd.len() as u64).to_le_bytes());
+            records.push(f.channel);
+            sd.extend_from_slice(&(f.data.len() as u32).to_le_bytes());
+            sd.extend_from_slice(f.data);
+        }
+        let sd = self.block(b"##SD", &[], &sd);
+
+        let channels = [
+            self.channel("t", "s", CN_MASTER, DT_FLOAT_LE, 0, 64, 0),
+            self.channel("CAN_DataFrame.ID", "", CN_FIXED, DT_UINT_LE, 8, 32, 0),
+            self.channel("CAN_DataFrame.DLC", "", CN_FIXED, DT_UINT_LE, 12, 8, 0),
+            self.channel(
+                "CAN_DataFrame.DataLength",
+                "",
+                CN_FIXED,
+                DT_UINT_LE,
+                13,
+                32,
+                0,
+            ),
+            self.channel(
+                "CAN_DataFrame.DataBytes",
+                "",
+                CN_VLSD,
+                DT_BYTE_ARRAY,
+                17,
+                64,
+                0,
+            ),
+            self.channel(
+                "CAN_DataFrame.BusChannel",
+                "",
+                CN_FIXED,
+                DT_UINT_LE,
+                25,
+                8,
+                0,
+            ),
+        ];
+        // VLSD data link
+        self.set_link(channels[4], 5, sd);
+        self.group("CAN_DataFrame", &channels, RECORD_SIZE, &records, true);
+    }
+
+    /// Add a channel group of decoded signals sharing the `times` master channel.
+    pub fn add_signals(&mut self, name: &str, times: &[f64], signals: &[Signal]) {
+        let mut channels = vec![self.channel("t", "s", CN_MASTER, DT_FLOAT_LE, 0, 64, 0)];
+        for (i, s) in signals.iter().enumerate() {
+            let cc = self.linear_conversion(s.offset, s.factor);
+            channels.push(self.channel(
+                &s.name,
+                &s.unit,
+                CN_FIXED,
+                DT_FLOAT_LE,
+                8 * (i as u32 + 1),
+                64,
+                cc,
+            ));
+        }
+        let record_size = 8 * (signals.len() as u32 + 1);
+        let mut records = Vec::with_capacity(record_size as usize * times.len());
+        for (r, t) in times.iter().enumerate() {
+            records.extend_from_slice(&t.to_le_bytes());
+            for s in signals {
+                records.extend_from_slice(&s.raw.get(r).copied().unwrap_or(f64::NAN).to_le_bytes());
+            }
+        }
+        self.group(name, &channels, record_size, &records, false);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Write the contents of the repo as MDF4, adding decoded channel groups for `pgns`, each
+/// decoded from its channel, or from any channel when `None`.
+pub fn write_mdf4(
+    out: &mut impl Write,
+    repo: &PacketRepo,
+    pgns: &[(Option<u8>, PgnDefinition)],
+) -> Result<()> {
+    let mut w = Mdf4Writer::new();
+    w.add_frames(
+        repo.packets()
+            .iter()
+            .zip(repo.flags())
+            .zip(repo.channels())
+            .map(|((p, flags), channel)| Frame {
+                time: p.time().unwrap_or_default().as_secs_f64(),
+                id: ide_id(p.id()),
+                flags: *flags,
+                channel: *channel,
+                data: &p.payload,
+            }),
+    );
+    for (channel, pgn) in pgns {
+        // ignore priority
+        let Some(packets) = repo.get_for(channel_key(*channel, dbc_key(pgn.id))) else {
+            continue;
+        };
+        let times: Vec<f64> = packets
+            .iter()
+            .map(|p| p.time().unwrap_or_default().as_secs_f64())
+            .collect();
+        let mut spns: Vec<_> = pgn.spns.values().collect();
+        spns.sort_by(|a, b| a.name.cmp(&b.name));
+        let signals: Vec<Signal> = spns
+            .iter()
+            .map(|spn| {
+                let factor = if spn.scale == 0.0 {
+                    1.0
+                } else {
+                    spn.scale as f64
+                };
+                let offset = spn.offset as f64;
+                Signal {
+                    name: spn.name.clone(),
+                    unit: spn.units.clone(),
+                    factor,
+                    offset,
+                    raw: packets
+                        .iter()
+                        .map(|p| {
+                            dbc::decode(spn, &p.payload).map_or(f64::NAN, |v| (v - offset) / factor)
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        let name = match channel {
+            Some(c) => format!("{:08X} Ch {c}", pgn.id),
+            None => format!("{:08X}", pgn.id),
+        };
+        w.add_signals(&name, &times, &signals);
+    }
+    out.write_all(&w.into_bytes())?;
+    Ok(())
+}
+
+pub fn save_mdf4(
+    path: &Path,
+    repo: &PacketRepo,
+    pgns: &[(Option<u8>, PgnDefinition)],
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_mdf4(&mut out, repo, pgns)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just enough of an MDF4 reader to verify the writer.
+    struct Reader<'a>(&'a [u8]);
+
+    struct Channel {
+        name: String,
+        unit: String,
+        cn_type: u8,
+        byte_offset: usize,
+        bit_count: u32,
+        data_type: u8,
+        conversion: Option<(f64, f64)>,
+        sd: u64,
+    }
+
+    struct Group {
+        name: String,
+        flags: u16,
+        channels: Vec<Channel>,
+        record_size: usize,
+        records: Vec<u8>,
+    }
+
+    impl Reader<'_> {
+        fn u64(&self, at: u64) -> u64 {
+            u64::from_le_bytes(self.0[at as usize..at as usize + 8].try_into().unwrap())
+        }
+        fn u32(&self, at: u64) -> u32 {
+            u32::from_le_bytes(self.0[at as usize..at as usize + 4].try_into().unwrap())
+        }
+        fn f64(&self, at: u64) -> f64 {
+            f64::from_bits(self.u64(at))
+        }
+        fn id(&self, at: u64) -> &[u8] {
+            &self.0[at as usize..at as usize + 4]
+        }
+        fn link(&self, block: u64, index: u64) -> u64 {
+            self.u64(block + 24 + 8 * index)
+        }
+        fn data(&self, block: u64) -> u64 {
+            block + 24 + 8 * self.u64(block + 16)
+        }
+        fn body(&self, block: u64) -> &[u8] {
+            &self.0[self.data(block) as usize..(block + self.u64(block + 8)) as usize]
+        }
+        fn text(&self, block: u64) -> String {
+            if block == 0 {
+                return String::new();
+            }
+            assert_eq!(self.id(block), b"##TX");
+            let body = self.body(block);
+            let end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+            String::from_utf8(body[..end].to_vec()).unwrap()
+        }
+        fn groups(&self) -> Vec<Group> {
+            assert_eq!(&self.0[0..8], b"MDF     ");
+            assert_eq!(self.id(HD_OFFSET), b"##HD");
+            let fh = self.link(HD_OFFSET, 1);
+            assert_eq!(self.id(fh), b"##FH");
+            let comment = self.link(fh, 1);
+            assert_eq!(self.id(comment), b"##MD");
+            assert!(String::from_utf8_lossy(self.body(comment)).starts_with("<FHcomment>"));
+            let mut result = Vec::new();
+            let mut dg = self.link(HD_OFFSET, 0);
+            while dg != 0 {
+                assert_eq!(self.id(dg), b"##DG");
+                let cg = self.link(dg, 1);
+                assert_eq!(self.id(cg), b"##CG");
+                let cg_data = self.data(cg);
+                let mut channels = Vec::new();
+                let mut cn = self.link(cg, 1);
+                while cn != 0 {
+                    assert_eq!(self.id(cn), b"##CN");
+                    let d = self.data(cn);
+                    let cc = self.link(cn, 4);
+                    let conversion = (cc != 0).then(|| {
+                        let cc_data = self.data(cc);
+                        (self.f64(cc_data + 24), self.f64(cc_data + 32))
+                    });
+                    channels.push(Channel {
+                        name: self.text(self.link(cn, 2)),
+                        unit: self.text(self.link(cn, 6)),
+                        cn_type: self.0[d as usize],
+                        data_type: self.0[d as usize + 2],
+                        byte_offset: self.u32(d + 4) as usize,
+                        bit_count: self.u32(d + 8),
+                        conversion,
+                        sd: self.link(cn, 5),
+                    });
+                    cn = self.link(cn, 0);
+                }
+                result.push(Group {
+                    name: self.text(self.link(cg, 2)),
+                    flags: u16::from_le_bytes(
+                        self.0[cg_data as usize + 16..cg_data as usize + 18]
+                            .try_into()
+                            .unwrap(),
+                    ),
+                    channels,
+                    record_size: self.u32(cg_data + 24) as usize,
+                    records: self.body(self.link(dg, 2)).to_vec(),
+                });
+                dg = self.link(dg, 0);
+            }
+            result
+        }
+        fn value(&self, group: &Group, channel: &Channel, record: usize) -> f64 {
+            let at = record * group.record_size + channel.byte_offset;
+            let bytes = &group.records[at..at + channel.bit_count as usize / 8];
+            let mut raw = [0u8; 8];
+            raw[..bytes.len()].copy_from_slice(bytes);
+            let raw = u64::from_le_bytes(raw);
+            let v = if channel.data_type == DT_FLOAT_LE {
+                f64::from_bits(raw)
+            } else {
+                raw as f64
+            };
+            channel.conversion.map_or(v, |(a, b)| a + b * v)
+        }
+        fn vlsd(&self, group: &Group, channel: &Channel, record: usize) -> Vec<u8> {
+            let offset = self.value(group, channel, record) as u64;
+            let at = self.data(channel.sd) + offset;
+            let len = self.u32(at) as usize;
+            self.0[at as usize + 4..at as usize + 4 + len].to_vec()
+        }
+    }
+
+    #[test]
+    fn round_trip_frames() {
+        let payloads: [&[u8]; 3] = [&[1, 2, 3, 4, 5, 6, 7, 8], &[0xFF; 3], &[9; 20]];
+        let mut w = Mdf4Writer::new();
+        w.add_frames(payloads.iter().enumerate().map(|(i, d)| Frame {
+            time: i as f64 * 0.5,
+            id: 0x18FEF100 + i as u32,
+            flags: if d.len() > 8 { FD } else { 0 },
+            channel: i as u8,
+            data: d,
+        }));
+        let bytes = w.into_bytes();
+        let r = Reader(&bytes);
+        let groups = r.groups();
+        assert_eq!(groups.len(), 1);
+        let g = &groups[0];
+        assert_eq!(g.name, "CAN_DataFrame");
+        assert_eq!(g.flags & 0x2, 0x2);
+        let names: Vec<&str> = g.channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "t",
+                "CAN_DataFrame.ID",
+                "CAN_DataFrame.DLC",
+                "CAN_DataFrame.DataLength",
+                "CAN_DataFrame.DataBytes",
+                "CAN_DataFrame.BusChannel"
+            ]
+        );
+        assert_eq!(g.channels[0].cn_type, CN_MASTER);
+        assert_eq!(g.records.len(), 3 * g.record_size);
+        for (i, d) in payloads.iter().enumerate() {
+            assert_eq!(r.value(g, &g.channels[0], i), i as f64 * 0.5);
+            assert_eq!(r.value(g, &g.channels[1], i), (0x18FEF100 + i) as f64);
+            assert_eq!(r.value(g, &g.channels[2], i), len_to_dlc(d.len()) as f64);
+            assert_eq!(r.value(g, &g.channels[3], i), d.len() as f64);
+            assert_eq!(r.vlsd(g, &g.channels[4], i), d.to_vec());
+            assert_eq!(r.value(g, &g.channels[5], i), i as f64);
+        }
+    }
+
+    #[test]
+    fn round_trip_signals() {
+        let mut w = Mdf4Writer::new();
+        w.add_frames(std::iter::empty());
+        w.add_signals(
+            "18FEF100",
+            &[0.0, 0.1],
+            &[Signal {
+                name: "Wheel-Based Vehicle Speed".into(),
+                unit: "km/h".into(),
+                factor: 1.0 / 256.0,
+                offset: 0.0,
+                raw: vec![256.0, 512.0],
+            }],
+        );
+        let bytes = w.into_bytes();
+        let r = Reader(&bytes);
+        let groups = r.groups();
+        assert_eq!(groups.len(), 2);
+        let g = &groups[1];
+        assert_eq!(g.name, "18FEF100");
+        let c = &g.channels[1];
+        assert_eq!(c.name, "Wheel-Based Vehicle Speed");
+        assert_eq!(c.unit, "km/h");
+        assert_eq!(r.value(g, c, 0), 1.0);
+        assert_eq!(r.value(g, c, 1), 2.0);
+        assert_eq!(r.value(g, &g.channels[0], 1), 0.1);
+    }
+
+    #[test]
+    fn write_dbc_signals() {
+        use can_adapter::j1939::j1939_packet::J1939Packet;
+        use std::time::Duration;
+
+        let eec1 = dbc::load_test_messages(dbc::TEST_EEC1).remove(0);
+        let pgns = [(None, eec1.clone()), (Some(1), eec1)];
+
+        let mut repo = PacketRepo::default();
+        for (i, rpm) in [1000u16, 1500].iter().enumerate() {
+            let raw = (rpm * 8).to_le_bytes();
+            let payload = [0xFF, 0xFF, 0xFF, raw[0], raw[1], 0xFF, 0xFF, 0xFF];
+            let time = Some(Duration::from_millis(100 * i as u64));
+            repo.push_from(i as u8, J1939Packet::new(time, 0, 0x0CF004FE, &payload), 0);
+        }
+        repo.push(J1939Packet::new(None, 0, 0x18FEF100, &[0; 8]));
+
+        let mut bytes = Vec::new();
+        write_mdf4(&mut bytes, &repo, &pgns).unwrap();
+        let r = Reader(&bytes);
+        let groups = r.groups();
+        assert_eq!(groups.len(), 3);
+        let frames = &groups[0];
+        assert_eq!(frames.records.len(), 3 * frames.record_size);
+        assert_eq!(
+            r.value(frames, &frames.channels[1], 0),
+            0x8CF004FE_u32 as f64
+        );
+        assert_eq!(r.value(frames, &frames.channels[5], 1), 1.0);
+
+        let g = &groups[1];
+        assert_eq!(g.name, "8CF004FE");
+        assert_eq!(g.records.len(), 2 * g.record_size);
+        let c = &g.channels[1];
+        assert_eq!(c.name, "EngineSpeed");
+        assert_eq!(c.unit, "rpm");
+        assert_eq!(r.value(g, c, 0), 1000.0);
+        assert_eq!(r.value(g, c, 1), 1500.0);
+        assert_eq!(r.value(g, &g.channels[0], 1), 0.1);
+
+        // bound to channel 1
+        let g = &groups[2];
+        assert_eq!(g.name, "8CF004FE Ch 1");
+        assert_eq!(g.records.len(), g.record_size);
+        assert_eq!(r.value(g, &g.channels[1], 0), 1500.0);
+    }
+}
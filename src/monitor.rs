@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use fltk::{
+    enums::Font,
+    group::Pack,
+    prelude::{GroupExt, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+use timer::Timer;
+
+use crate::packet_repo::{is_standard, pgn_of, pgn_text, repo_key, sa, sa_text, PacketRepo};
+
+/// Latest state of one id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entry {
+    pub count: usize,
+    /// time between the last two packets
+    pub period: Duration,
+    pub time: Duration,
+    /// time the payload last differed from the packet before it, or the first packet
+    pub last_change: Duration,
+    pub payload: Vec<u8>,
+}
+
+impl Entry {
+    pub fn add(&mut self, time: Duration, payload: &[u8]) {
+        if self.count == 0 || payload != self.payload {
+            self.last_change = time;
+            self.payload = payload.to_vec();
+        }
+        if self.count > 0 {
+            self.period = time.saturating_sub(self.time);
+        }
+        self.time = time;
+        self.count += 1;
+    }
+}
+
+/// Row key, channel and id without priority.
+type Key = (u8, u32);
+
+/// One row per channel and id, priority masked, updated in place as packets arrive.
+#[derive(Default)]
+pub struct MonitorModel {
+    packets: Arc<RwLock<PacketRepo>>,
+    entries: HashMap<Key, Entry>,
+    /// keys in display order
+    rows: Vec<Key>,
+    sort: Option<(usize, Order)>,
+    /// packets added so far
+    scanned: usize,
+    /// `PacketRepo::generation` when last scanned
+    generation: usize,
+}
+
+impl MonitorModel {
+    pub fn new(packets: Arc<RwLock<PacketRepo>>) -> MonitorModel {
+        MonitorModel {
+            packets,
+            ..Default::default()
+        }
+    }
+
+    /// Add the packets read since the last update.
+    pub fn update(&mut self) {
+        let repo = self.packets.read().unwrap();
+        if repo.generation() != self.generation || repo.packets().len() < self.scanned {
+            // cleared
+            self.entries.clear();
+            self.rows.clear();
+            self.scanned = 0;
+            self.generation = repo.generation();
+        }
+        let mut added = false;
+        let new = repo.packets()[self.scanned..]
+            .iter()
+            .zip(&repo.channels()[self.scanned..]);
+        for (p, channel) in new {
+            let entry = self
+                .entries
+                .entry((*channel, repo_key(p.id())))
+                .or_insert_with(|| {
+                    added = true;
+                    Entry::default()
+                });
+            entry.add(p.time().unwrap_or_default(), &p.payload);
+        }
+        self.scanned = repo.packets().len();
+        drop(repo);
+        if added {
+            self.rows = self.entries.keys().copied().collect();
+            self.rows.sort();
+        }
+        if let Some((col, order)) = self.sort.take() {
+            self.sort(col, order);
+        }
+    }
+}
+
+const COLUMNS: [&str; 8] = [
+    "Ch",
+    "Id",
+    "PGN",
+    "SA",
+    "Count",
+    "Period (ms)",
+    "Last Change (s)",
+    "Data",
+];
+
+fn id_text(id: u32) -> String {
+    if is_standard(id) {
+        format!("{:03X}", id & 0x7FF)
+    } else {
+        format!("{id:08X}")
+    }
+}
+
+impl SimpleModel for MonitorModel {
+    fn row_count(&mut self) -> usize {
+        self.rows.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 30,
+            1 => 70,
+            2 | 3 => 50,
+            7 => 400,
+            _ => 90,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let (channel, id) = *self.rows.get(row as usize)?;
+        let e = self.entries.get(&(channel, id))?;
+        match col {
+            0 => Some(channel.to_string()),
+            1 => Some(id_text(id)),
+            2 => Some(pgn_text(id)),
+            3 => Some(sa_text(id)),
+            4 => Some(e.count.to_string()),
+            5 => Some(format!("{:0.1}", e.period.as_secs_f64() * 1000.0)),
+            6 => Some(format!("{:0.3}", e.last_change.as_secs_f64())),
+            7 => {
+                let data: Vec<String> = e.payload.iter().map(|b| format!("{b:02X}")).collect();
+                Some(format!("[{}] {}", e.payload.len(), data.join(" ")))
+            }
+            _ => None,
+        }
+    }
+
+    fn sort(&mut self, col: usize, order: Order) {
+        let entries = &self.entries;
+        let key = |k: &Key| -> (f64, Key) {
+            let (channel, id) = *k;
+            let e = &entries[k];
+            match col {
+                0 => (channel as f64, *k),
+                2 => (pgn_of(id) as f64, *k),
+                3 => (sa(id) as f64, *k),
+                4 => (e.count as f64, *k),
+                5 => (e.period.as_secs_f64(), *k),
+                6 => (e.last_change.as_secs_f64(), *k),
+                _ => (id as f64, *k),
+            }
+        };
+        match order {
+            Order::None => {
+                self.rows.sort();
+                self.sort = None;
+                return;
+            }
+            Order::Ascending => self
+                .rows
+                .sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap()),
+            Order::Descending => self
+                .rows
+                .sort_by(|a, b| key(b).partial_cmp(&key(a)).unwrap()),
+        }
+        self.sort = Some((col, order));
+    }
+}
+
+/// Latest packet of each id, like a CAN monitor.
+pub fn monitor_window(packets: Arc<RwLock<PacketRepo>>, timer: &Timer) {
+    let mut wind = Window::default().with_size(800, 500).with_label("Monitor");
+    let pack = Pack::default_fill();
+
+    let mut table = JoeTable::new(MonitorModel::new(packets));
+    table.set_font(Font::Courier, 8);
+    table.init();
+
+    pack.resizable(&*table);
+    pack.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    table.redraw_on(timer, chrono::Duration::milliseconds(200));
+
+    let model = table.model.clone();
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(200), move || {
+            if wind.shown() {
+                model.lock().unwrap().update();
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use can_adapter::j1939::j1939_packet::J1939Packet;
+
+    use super::*;
+
+    #[test]
+    fn entry() {
+        let ms = Duration::from_millis;
+        let mut e = Entry::default();
+        e.add(ms(100), &[1, 2]);
+        assert_eq!(
+            (e.count, e.period, e.last_change),
+            (1, Duration::ZERO, ms(100))
+        );
+        e.add(ms(200), &[1, 2]);
+        e.add(ms(350), &[1, 2]);
+        assert_eq!((e.count, e.period, e.last_change), (3, ms(150), ms(100)));
+        e.add(ms(400), &[1, 3]);
+        assert_eq!(e.last_change, ms(400));
+        assert_eq!(e.payload, vec![1, 3]);
+        assert_eq!(e.time, ms(400));
+    }
+
+    #[test]
+    fn update() {
+        let repo = Arc::new(RwLock::new(PacketRepo::default()));
+        let mut model = MonitorModel::new(repo.clone());
+        let packet = |ms| J1939Packet::new(Some(Duration::from_millis(ms)), 0, 0x18FEF100, &[1]);
+        {
+            let mut repo = repo.write().unwrap();
+            repo.push_from(0, packet(10), 0);
+            repo.push_from(1, packet(20), 0);
+            repo.push_from(0, packet(30), 0);
+        }
+        model.update();
+        assert_eq!(model.rows, [(0, 0xFEF100), (1, 0xFEF100)]);
+        assert_eq!(model.entries[&(0, 0xFEF100)].count, 2);
+
+        // cleared and refilled between updates
+        {
+            let mut repo = repo.write().unwrap();
+            repo.clear();
+            for ms in [40, 50, 60] {
+                repo.push_from(1, packet(ms), 0);
+            }
+        }
+        model.update();
+        assert_eq!(model.rows, [(1, 0xFEF100)]);
+        assert_eq!(model.entries[&(1, 0xFEF100)].count, 3);
+    }
+}
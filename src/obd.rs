@@ -0,0 +1,451 @@
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use can_adapter::{connection::Connection, j1939::j1939_packet::J1939Packet};
+use fltk::{
+    browser::HoldBrowser,
+    button::Button,
+    dialog::alert_default,
+    group::{Flex, PackType},
+    menu::Choice,
+    prelude::{BrowserExt, GroupExt, MenuExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use timer::Timer;
+
+use crate::{
+    channel::{self, Connections},
+    packet_repo::{da, frame_id, ide_id, is_standard, pgn_of, sa, MarkerKind, PacketRepo},
+    uds::{IsoTpReceiver, DIAG_PHYSICAL},
+};
+
+/// Tester address for 29 bit requests.
+const TESTER: u8 = 0xF1;
+/// Time to wait for responses, P2 plus time for multi frame responses.
+const RESPONSE_TIME: Duration = Duration::from_millis(1000);
+
+/// SAE J1979 CAN identifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Addressing {
+    /// 11 bit, requests on 7DF and responses on 7E8-7EF
+    Standard,
+    /// 29 bit, requests on 18DB33F1 and responses on 18DAF1xx
+    Extended,
+}
+
+impl Addressing {
+    pub fn request_id(&self) -> u32 {
+        match self {
+            Addressing::Standard => 0x7DF,
+            Addressing::Extended => 0x18DB33F1,
+        }
+    }
+
+    /// The responding ECU for a response id from the log.
+    pub fn responder(&self, id: u32) -> Option<u8> {
+        match self {
+            Addressing::Standard => (is_standard(id) && (0x7E8..=0x7EF).contains(&(id & 0x7FF)))
+                .then(|| ((id & 0x7FF) - 0x7E8) as u8),
+            Addressing::Extended => {
+                (!is_standard(id) && pgn_of(id) == DIAG_PHYSICAL && da(id) == TESTER)
+                    .then(|| sa(id))
+            }
+        }
+    }
+
+    /// Id to send flow control to `ecu` on.
+    pub fn flow_control_id(&self, ecu: u8) -> u32 {
+        match self {
+            Addressing::Standard => 0x7E0 + ecu as u32,
+            Addressing::Extended => 0x18DA00F1 | (ecu as u32) << 8,
+        }
+    }
+}
+
+/// Single frame request, padded to 8 bytes.
+pub fn request(mode: u8, pid: Option<u8>) -> Vec<u8> {
+    let mut data = vec![1, mode];
+    if let Some(pid) = pid {
+        data[0] = 2;
+        data.push(pid);
+    }
+    data.resize(8, 0x55);
+    data
+}
+
+struct Pid {
+    pid: u8,
+    name: &'static str,
+    units: &'static str,
+    /// data bytes
+    len: usize,
+    value: fn(&[u8]) -> f64,
+}
+
+fn a(d: &[u8]) -> f64 {
+    d[0] as f64
+}
+
+fn ab(d: &[u8]) -> f64 {
+    (256 * d[0] as usize + d[1] as usize) as f64
+}
+
+/// Mode 01 PIDs read by the panel.
+const PIDS: &[Pid] = &[
+    Pid {
+        pid: 0x04,
+        name: "Calculated engine load",
+        units: "%",
+        len: 1,
+        value: |d| a(d) * 100.0 / 255.0,
+    },
+    Pid {
+        pid: 0x05,
+        name: "Engine coolant temperature",
+        units: "°C",
+        len: 1,
+        value: |d| a(d) - 40.0,
+    },
+    Pid {
+        pid: 0x0B,
+        name: "Intake manifold pressure",
+        units: "kPa",
+        len: 1,
+        value: a,
+    },
+    Pid {
+        pid: 0x0C,
+        name: "Engine speed",
+        units: "rpm",
+        len: 2,
+        value: |d| ab(d) / 4.0,
+    },
+    Pid {
+        pid: 0x0D,
+        name: "Vehicle speed",
+        units: "km/h",
+        len: 1,
+        value: a,
+    },
+    Pid {
+        pid: 0x0F,
+        name: "Intake air temperature",
+        units: "°C",
+        len: 1,
+        value: |d| a(d) - 40.0,
+    },
+    Pid {
+        pid: 0x10,
+        name: "Mass air flow",
+        units: "g/s",
+        len: 2,
+        value: |d| ab(d) / 100.0,
+    },
+    Pid {
+        pid: 0x11,
+        name: "Throttle position",
+        units: "%",
+        len: 1,
+        value: |d| a(d) * 100.0 / 255.0,
+    },
+    Pid {
+        pid: 0x1F,
+        name: "Run time since engine start",
+        units: "s",
+        len: 2,
+        value: ab,
+    },
+    Pid {
+        pid: 0x2F,
+        name: "Fuel level",
+        units: "%",
+        len: 1,
+        value: |d| a(d) * 100.0 / 255.0,
+    },
+    Pid {
+        pid: 0x42,
+        name: "Control module voltage",
+        units: "V",
+        len: 2,
+        value: |d| ab(d) / 1000.0,
+    },
+    Pid {
+        pid: 0x46,
+        name: "Ambient air temperature",
+        units: "°C",
+        len: 1,
+        value: |d| a(d) - 40.0,
+    },
+    Pid {
+        pid: 0x5C,
+        name: "Engine oil temperature",
+        units: "°C",
+        len: 1,
+        value: |d| a(d) - 40.0,
+    },
+];
+
+/// "P0301" style code from the two DTC bytes.
+pub fn dtc(hi: u8, lo: u8) -> String {
+    let system = ['P', 'C', 'B', 'U'][(hi >> 6) as usize];
+    format!("{system}{:01X}{:01X}{lo:02X}", (hi >> 4) & 0x3, hi & 0xF)
+}
+
+/// Describe a response.
+pub fn decode(payload: &[u8]) -> String {
+    match payload {
+        [0x41, pid, data @ ..] => match PIDS.iter().find(|p| p.pid == *pid) {
+            Some(p) if data.len() >= p.len => {
+                format!("{} = {:0.1} {}", p.name, (p.value)(data), p.units)
+            }
+            _ => format!("PID {pid:02X} = {data:02X?}"),
+        },
+        [0x43, count, codes @ ..] => {
+            let codes: Vec<String> = codes
+                .chunks_exact(2)
+                .take(*count as usize)
+                .map(|c| dtc(c[0], c[1]))
+                .collect();
+            if codes.is_empty() {
+                "No DTCs".into()
+            } else {
+                format!("DTCs: {}", codes.join(", "))
+            }
+        }
+        [0x49, 0x02, _, vin @ ..] => format!(
+            "VIN {}",
+            String::from_utf8_lossy(vin).trim_matches(char::from(0))
+        ),
+        [0x7F, mode, nrc, ..] => format!("Mode {mode:02X} rejected: {nrc:02X}"),
+        _ => format!("{payload:02X?}"),
+    }
+}
+
+/// Send a frame, with the IDE flag for 29 bit ids.
+fn send(connection: &dyn Connection, id: u32, data: &[u8]) -> Result<()> {
+    connection.send(&J1939Packet::new(None, 0, ide_id(frame_id(id)), data))?;
+    Ok(())
+}
+
+/// Responses logged from `channel` since packet `next`, as (ECU, time, payload). `next` is
+/// advanced past them.
+fn new_responses(
+    repo: &PacketRepo,
+    next: &mut usize,
+    channel: u8,
+    addressing: Addressing,
+) -> Vec<(u8, Duration, Vec<u8>)> {
+    // the log may have been cleared
+    let new = repo.packets().get(*next..).unwrap_or_default();
+    let channels = repo.channels().get(*next..).unwrap_or_default();
+    *next += new.len();
+    new.iter()
+        .zip(channels)
+        .filter(|(_, c)| **c == channel)
+        .filter_map(|(p, _)| {
+            let ecu = addressing.responder(p.id())?;
+            Some((ecu, p.time().unwrap_or_default(), p.payload.clone()))
+        })
+        .collect()
+}
+
+/// Send a request and collect the responses from every ECU. New packets from `channel` are
+/// read from the log, so the logger must be reading from the same connection.
+pub fn query(
+    connection: &dyn Connection,
+    packets: &RwLock<PacketRepo>,
+    channel: u8,
+    addressing: Addressing,
+    data: &[u8],
+) -> Result<Vec<(u8, Vec<u8>)>> {
+    let mut next = packets.read().unwrap().packets().len();
+    send(connection, addressing.request_id(), data)?;
+    let start = Instant::now();
+    let mut isotp = IsoTpReceiver::default();
+    let mut responses = Vec::new();
+    while start.elapsed() < RESPONSE_TIME {
+        thread::sleep(Duration::from_millis(20));
+        let frames = new_responses(&packets.read().unwrap(), &mut next, channel, addressing);
+        for (ecu, time, payload) in frames {
+            // first frame, ask for the rest without delay
+            if payload.first().is_some_and(|b| b >> 4 == 1) {
+                let mut fc = vec![0x30, 0, 0];
+                fc.resize(8, 0x55);
+                send(connection, addressing.flow_control_id(ecu), &fc)?;
+            }
+            if let Some(m) = isotp.receive_frame((ecu, TESTER), time, &payload) {
+                responses.push((ecu, m.payload));
+            }
+        }
+    }
+    Ok(responses)
+}
+
+/// Mode 01 live data, mode 03 DTCs and mode 09 VIN.
+pub fn obd_window(connections: Connections, packets: Arc<RwLock<PacketRepo>>, timer: &Timer) {
+    let mut wind = Window::default().with_size(600, 400).with_label("OBD-II");
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut channels = Choice::default();
+    let numbers: Vec<u8> = connections
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            channels.add_choice(&c.name().replace('/', "\\/"));
+            c.number
+        })
+        .collect();
+    channels.set_value(0);
+    let mut addressing = Choice::default();
+    addressing.add_choice("11 bit (7DF)|29 bit (18DB33F1)");
+    addressing.set_value(0);
+    let mut live = Button::default().with_label("Live Data");
+    let mut dtcs = Button::default().with_label("Read DTCs");
+    let mut vin = Button::default().with_label("Read VIN");
+    row.end();
+    flex.fixed(&row, 25);
+
+    let browser = HoldBrowser::default();
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    let results: Arc<Mutex<Vec<String>>> = Default::default();
+    let run = {
+        let results = results.clone();
+        move |requests: Vec<Vec<u8>>| {
+            let addressing = if addressing.value() == 1 {
+                Addressing::Extended
+            } else {
+                Addressing::Standard
+            };
+            let Some(number) = numbers.get(channels.value() as usize).copied() else {
+                alert_default("No adapter selected.");
+                return;
+            };
+            let Some(connection) = channel::get(&connections, number) else {
+                alert_default("Channel is closed.");
+                return;
+            };
+            let packets = packets.clone();
+            let results = results.clone();
+            thread::spawn(move || {
+                for data in requests {
+                    let lines =
+                        match query(connection.as_ref(), &packets, number, addressing, &data) {
+                            Ok(r) if r.is_empty() => {
+                                vec![format!("No response to {:02X?}", &data[1..])]
+                            }
+                            Ok(r) => r
+                                .into_iter()
+                                .map(|(ecu, payload)| {
+                                    format!("ECU {ecu:02X}: {}", decode(&payload))
+                                })
+                                .collect(),
+                            Err(err) => vec![format!("{err:#}")],
+                        };
+                    let mut repo = packets.write().unwrap();
+                    let time = repo.last_time();
+                    for line in lines {
+                        repo.add_marker(time, MarkerKind::Obd, format!("OBD {line}"));
+                        results.lock().unwrap().push(line);
+                    }
+                }
+            });
+        }
+    };
+    {
+        let run = run.clone();
+        live.set_callback(move |_| run(PIDS.iter().map(|p| request(0x01, Some(p.pid))).collect()));
+    }
+    {
+        let run = run.clone();
+        dtcs.set_callback(move |_| run(vec![request(0x03, None)]));
+    }
+    vin.set_callback(move |_| run(vec![request(0x09, Some(0x02))]));
+
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(200), move || {
+            let mut browser = browser.clone();
+            let results = results.lock().unwrap();
+            if wind.shown() && browser.size() as usize != results.len() {
+                results
+                    .iter()
+                    .skip(browser.size() as usize)
+                    .for_each(|r| browser.add(r));
+                browser.bottom_line(browser.size());
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addressing() {
+        assert_eq!(Addressing::Standard.responder(frame_id(0x7E9)), Some(1));
+        assert_eq!(Addressing::Standard.responder(frame_id(0x7DF)), None);
+        assert_eq!(Addressing::Standard.flow_control_id(1), 0x7E1);
+        assert_eq!(Addressing::Extended.responder(0x18DAF110), Some(0x10));
+        assert_eq!(Addressing::Extended.responder(0x18DA10F1), None);
+        assert_eq!(Addressing::Extended.flow_control_id(0x10), 0x18DA10F1);
+        assert_eq!(
+            request(0x01, Some(0x0C)),
+            [2, 1, 0x0C, 0x55, 0x55, 0x55, 0x55, 0x55]
+        );
+    }
+
+    #[test]
+    fn logged_responses() {
+        let mut repo = PacketRepo::default();
+        let time = Some(Duration::from_millis(5));
+        let response = [4, 0x41, 0x0C, 0x1A, 0xF8, 0x55, 0x55, 0x55];
+        // the request itself and a response on another channel are skipped
+        repo.push_from(0, J1939Packet::new(time, 0, frame_id(0x7DF), &[0; 8]), 0);
+        repo.push_from(1, J1939Packet::new(time, 0, frame_id(0x7E9), &response), 0);
+        repo.push_from(0, J1939Packet::new(time, 0, frame_id(0x7E8), &response), 0);
+        repo.push_from(0, J1939Packet::new(time, 0, 0x18DAF110, &response), 0);
+        let mut next = 0;
+        assert_eq!(
+            new_responses(&repo, &mut next, 0, Addressing::Standard),
+            [(0, Duration::from_millis(5), response.to_vec())]
+        );
+        assert_eq!(next, 4);
+        assert!(new_responses(&repo, &mut next, 0, Addressing::Standard).is_empty());
+        let mut next = 0;
+        assert_eq!(
+            new_responses(&repo, &mut next, 0, Addressing::Extended),
+            [(0x10, Duration::from_millis(5), response.to_vec())]
+        );
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(
+            decode(&[0x41, 0x0C, 0x1A, 0xF8]),
+            "Engine speed = 1726.0 rpm"
+        );
+        assert_eq!(
+            decode(&[0x41, 0x05, 0x7B]),
+            "Engine coolant temperature = 83.0 °C"
+        );
+        assert_eq!(
+            decode(&[0x43, 2, 0x01, 0x33, 0xC1, 0x00]),
+            "DTCs: P0133, U0100"
+        );
+        assert_eq!(decode(&[0x43, 0]), "No DTCs");
+        let mut vin = vec![0x49, 0x02, 0x01];
+        vin.extend_from_slice(b"1HGBH41JXMN109186");
+        assert_eq!(decode(&vin), "VIN 1HGBH41JXMN109186");
+        assert_eq!(decode(&[0x7F, 0x09, 0x12]), "Mode 09 rejected: 12");
+    }
+}
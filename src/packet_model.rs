@@ -1,18 +1,213 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
 
-use simple_table::simple_model::{Order, SimpleModel};
+use fltk::{
+    draw,
+    enums::{Align, Color},
+};
+use simple_table::simple_model::{DrawDelegate, Order, SimpleModel};
 
-use crate::packet_repo::PacketRepo;
+use crate::packet_repo::{changed_bytes, marker_string, packet_string, MarkerKind, PacketRepo};
+
+/// A row of the log.
+#[derive(Debug, PartialEq)]
+enum Line {
+    /// index among the shown packets
+    Packet(usize),
+    /// index in `PacketRepo::markers`
+    Marker(usize),
+}
+
+/// Line at `row` when each marker goes before the shown packet at its position. `positions`
+/// must be sorted.
+fn line_at(positions: &[usize], row: usize) -> Line {
+    for (k, position) in positions.iter().enumerate() {
+        match (position + k).cmp(&row) {
+            Ordering::Equal => return Line::Marker(k),
+            Ordering::Greater => return Line::Packet(row - k),
+            Ordering::Less => {}
+        }
+    }
+    Line::Packet(row - positions.len())
+}
+
+/// Text positions of each payload byte in a log line, found from the end of the line.
+fn byte_columns(line: &str, payload: &[u8]) -> Option<Vec<Range<usize>>> {
+    if payload.is_empty() {
+        return None;
+    }
+    let data: Vec<String> = payload.iter().map(|b| format!("{b:02X}")).collect();
+    let start = line.rfind(&data.join(" "))?;
+    Some(
+        (0..payload.len())
+            .map(|i| start + 3 * i..start + 3 * i + 2)
+            .collect(),
+    )
+}
+
+/// Log line with the bytes that changed since the previous packet of its id highlighted.
+struct ChangedBytes {
+    text: String,
+    /// text ranges of the changed bytes
+    changed: Vec<Range<usize>>,
+}
+
+impl DrawDelegate for ChangedBytes {
+    fn draw(&self, _row: i32, _col: i32, x: i32, y: i32, w: i32, h: i32, selected: bool) {
+        draw::push_clip(x, y, w, h);
+        draw::draw_rect_fill(
+            x,
+            y,
+            w,
+            h,
+            if selected {
+                Color::Selection
+            } else {
+                Color::White
+            },
+        );
+        for range in &self.changed {
+            let left = draw::width(&self.text[..range.start]) as i32;
+            let width = draw::width(&self.text[range.clone()]) as i32;
+            draw::draw_rect_fill(x + 2 + left, y, width, h, Color::Yellow);
+        }
+        draw::set_draw_color(Color::Black);
+        draw::draw_text2(&self.text, x + 2, y, w - 4, h, Align::Left);
+        draw::pop_clip();
+    }
+}
+
+/// Marker line, such as an annotation.
+struct MarkerLine {
+    text: String,
+}
+
+impl DrawDelegate for MarkerLine {
+    fn draw(&self, _row: i32, _col: i32, x: i32, y: i32, w: i32, h: i32, selected: bool) {
+        draw::push_clip(x, y, w, h);
+        draw::draw_rect_fill(
+            x,
+            y,
+            w,
+            h,
+            if selected {
+                Color::Selection
+            } else {
+                Color::from_rgb(200, 220, 255)
+            },
+        );
+        draw::set_draw_color(Color::DarkBlue);
+        draw::draw_text2(&self.text, x + 2, y, w - 4, h, Align::Left);
+        draw::pop_clip();
+    }
+}
 
 /// simple table model to represent log
 #[derive(Clone, Default)]
 pub struct PacketModel {
     pub packets: Arc<RwLock<PacketRepo>>,
+    /// channels to show, all when None
+    channels: Option<HashSet<u8>>,
+    /// repo index of each shown row, when filtered
+    rows: Vec<usize>,
+    /// repo packets already checked against the filter
+    scanned: usize,
+    /// hide packets with the same payload as the previous packet of their id
+    changes_only: bool,
 }
 
 impl PacketModel {
     pub fn new(packets: Arc<RwLock<PacketRepo>>) -> PacketModel {
-        PacketModel { packets }
+        PacketModel {
+            packets,
+            ..Default::default()
+        }
+    }
+    /// Only show packets from `channels`.
+    pub fn set_channels(&mut self, channels: Option<HashSet<u8>>) {
+        self.channels = channels;
+        self.rows.clear();
+        self.scanned = 0;
+    }
+    pub fn channels(&self) -> Option<&HashSet<u8>> {
+        self.channels.as_ref()
+    }
+    /// Only show packets whose payload differs from the previous packet of their id.
+    pub fn set_changes_only(&mut self, changes_only: bool) {
+        self.changes_only = changes_only;
+        self.rows.clear();
+        self.scanned = 0;
+    }
+    pub fn changes_only(&self) -> bool {
+        self.changes_only
+    }
+    fn filtered(&self) -> bool {
+        self.channels.is_some() || self.changes_only
+    }
+    /// Number of packets shown.
+    fn shown(&self, repo: &PacketRepo) -> usize {
+        if self.filtered() {
+            self.rows.len()
+        } else {
+            repo.packets().len()
+        }
+    }
+    /// Position among the shown packets of each marker.
+    fn marker_positions(&self, repo: &PacketRepo) -> Vec<usize> {
+        if !self.filtered() {
+            return repo.marker_positions();
+        }
+        repo.markers()
+            .iter()
+            .map(|m| self.rows.partition_point(|i| *i < m.position))
+            .collect()
+    }
+    fn line(&self, repo: &PacketRepo, row: usize) -> Line {
+        line_at(&self.marker_positions(repo), row)
+    }
+    /// Index in the repo of a table row, None for markers.
+    pub fn index(&self, repo: &PacketRepo, row: usize) -> Option<usize> {
+        match self.line(repo, row) {
+            Line::Packet(i) if self.filtered() => self.rows.get(i).copied(),
+            Line::Packet(i) => Some(i),
+            Line::Marker(_) => None,
+        }
+    }
+    /// Table rows of the annotations, skipping other markers.
+    pub fn annotation_rows(&self) -> Vec<usize> {
+        let repo = self.packets.read().unwrap();
+        self.marker_positions(&repo)
+            .iter()
+            .zip(repo.markers())
+            .enumerate()
+            .filter(|(_, (_, m))| m.kind == MarkerKind::Annotation)
+            .map(|(k, (position, _))| position + k)
+            .collect()
+    }
+    /// add newly read packets to `rows`
+    fn scan(&mut self, repo: &PacketRepo) {
+        if !self.filtered() {
+            return;
+        }
+        if repo.channels().len() < self.scanned {
+            // cleared
+            self.rows.clear();
+            self.scanned = 0;
+        }
+        let channels = &self.channels;
+        let changes_only = self.changes_only;
+        self.rows
+            .extend((self.scanned..repo.channels().len()).filter(|i| {
+                channels
+                    .as_ref()
+                    .is_none_or(|c| c.contains(&repo.channels()[*i]))
+                    && (!changes_only || repo.is_change(*i))
+            }));
+        self.scanned = repo.channels().len();
     }
 }
 
@@ -22,31 +217,106 @@ impl SimpleModel for PacketModel {
     }
 
     fn row_count(&mut self) -> usize {
-        self.packets.read().unwrap().packets().len()
+        let packets = self.packets.clone();
+        let repo = packets.read().unwrap();
+        self.scan(&repo);
+        self.shown(&repo) + repo.markers().len()
     }
 
     fn column_count(&mut self) -> usize {
-        1
+        2
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        match col {
+            0 => "Ch".into(),
+            _ => "".into(),
+        }
     }
 
-    fn header(&mut self, _col: usize) -> String {
-        "".into()
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 20,
+            _ => 1200,
+        }
     }
 
-    fn column_width(&mut self, _col: usize) -> u32 {
-        1200
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let repo = self.packets.read().unwrap();
+        if let Line::Marker(k) = self.line(&repo, row as usize) {
+            let m = repo.markers().get(k)?;
+            return Some(match col {
+                0 => String::new(),
+                _ => marker_string(m.time, &m.text),
+            });
+        }
+        let index = self.index(&repo, row as usize)?;
+        let p = repo.packets().get(index)?;
+        match col {
+            0 => Some(repo.channels()[index].to_string()),
+            _ => Some(packet_string(p, repo.flags()[index])),
+        }
     }
 
-    fn cell(&mut self, row: i32, _col: i32) -> Option<String> {
-        self.packets
-            .read()
-            .unwrap()
-            .packets()
-            .get(row as usize)
-            .map(|p| p.to_string())
+    fn cell_delegate(&mut self, row: i32, col: i32) -> Option<Box<dyn DrawDelegate>> {
+        if col != 1 {
+            return None;
+        }
+        let repo = self.packets.read().unwrap();
+        if let Line::Marker(k) = self.line(&repo, row as usize) {
+            let m = repo.markers().get(k)?;
+            return Some(Box::new(MarkerLine {
+                text: marker_string(m.time, &m.text),
+            }) as Box<dyn DrawDelegate>);
+        }
+        let index = self.index(&repo, row as usize)?;
+        let p = repo.packets().get(index)?;
+        let previous = repo.previous(index)?;
+        let changed = changed_bytes(&previous.payload, &p.payload);
+        if changed.is_empty() {
+            return None;
+        }
+        let text = packet_string(p, repo.flags()[index]);
+        let columns = byte_columns(&text, &p.payload)?;
+        Some(Box::new(ChangedBytes {
+            changed: changed.iter().map(|i| columns[*i].clone()).collect(),
+            text,
+        }) as Box<dyn DrawDelegate>)
     }
 
     fn sort(&mut self, _col: usize, _order: Order) {
         // sorting not supported
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let lines: Vec<Line> = (0..6).map(|row| line_at(&[0, 2, 2], row)).collect();
+        assert_eq!(
+            lines,
+            [
+                Line::Marker(0),
+                Line::Packet(0),
+                Line::Packet(1),
+                Line::Marker(1),
+                Line::Marker(2),
+                Line::Packet(2)
+            ]
+        );
+        assert_eq!(line_at(&[], 3), Line::Packet(3));
+    }
+
+    #[test]
+    fn columns() {
+        let line = "    1.2500 18FEF100 [3] 00 1F 00 (FD)";
+        let columns = byte_columns(line, &[0, 0x1F, 0]).unwrap();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(&line[columns[1].clone()], "1F");
+        assert_eq!(columns[2].end, line.find(" (FD)").unwrap());
+        assert_eq!(byte_columns(line, &[0x12]), None);
+    }
+}
@@ -1,36 +1,535 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    ops::Range,
+    time::Duration,
+};
 
 use can_adapter::j1939::j1939_packet::J1939Packet;
 
+use crate::fd::flags_text;
+
+/// CAN_EFF_FLAG. Set on 29 bit ids by adapters that report the IDE bit, and by DBC files.
+pub const EXTENDED: u32 = 0x8000_0000;
+/// Marks 11 bit ids in `PacketRepo`, so they don't collide with 29 bit ids.
+pub const STANDARD: u32 = 0x4000_0000;
+
+/// Id as kept in `PacketRepo`, from an adapter frame or a DBC message id. The IDE flag makes
+/// it 29 bit. Adapters that don't report the IDE bit only read J1939, so ids over 11 bits
+/// without the flag are 29 bit too. 11 bit ids are tagged with `STANDARD`.
+///
+/// Only for ids from outside the log, as a priority 0 id from the log looks like an 11 bit id.
+pub fn frame_id(id: u32) -> u32 {
+    if id & EXTENDED != 0 {
+        id & 0x1FFF_FFFF
+    } else if id & STANDARD != 0 || id <= 0x7FF {
+        STANDARD | id & 0x7FF
+    } else {
+        id
+    }
+}
+
+/// True for 11 bit ids and keys from `PacketRepo`.
+pub fn is_standard(id: u32) -> bool {
+    id & STANDARD != 0
+}
+
+/// Key for `PacketRepo::get_for`, from an id in the log. 29 bit ids ignore priority, 11 bit
+/// ids keep their `STANDARD` tag.
+pub fn repo_key(id: u32) -> u32 {
+    if is_standard(id) {
+        STANDARD | id & 0x7FF
+    } else {
+        id & 0x3FFFFFF
+    }
+}
+
+/// Key for the id of a DBC message.
+pub fn dbc_key(id: u32) -> u32 {
+    repo_key(frame_id(id))
+}
+
+/// Bits of a `PacketRepo` key holding the channel, plus one, for channel specific lookups.
+pub const CHANNEL: u32 = 0x3C00_0000;
+
+/// Key limited to packets from `channel`, or `key` itself for any channel. Channels above 14
+/// don't fit in the key.
+pub fn channel_key(channel: Option<u8>, key: u32) -> u32 {
+    match channel {
+        Some(c) => (key & !CHANNEL) | ((c as u32 + 1) << 26 & CHANNEL),
+        None => key,
+    }
+}
+
+/// Id from the log with the IDE flag set for 29 bit ids, as used by adapters, DBC and MDF4
+/// files.
+pub fn ide_id(id: u32) -> u32 {
+    if is_standard(id) {
+        id & 0x7FF
+    } else {
+        id | EXTENDED
+    }
+}
+
+/// PGN of a 29 bit J1939 id. The destination address of PDU1 messages is not part of the PGN.
+pub fn pgn_of(id: u32) -> u32 {
+    let pgn = (id >> 8) & 0x3FFFF;
+    if (pgn & 0xFF00) < 0xF000 {
+        pgn & 0x3FF00
+    } else {
+        pgn
+    }
+}
+
+/// Source address of a 29 bit J1939 id.
+pub fn sa(id: u32) -> u8 {
+    (id & 0xFF) as u8
+}
+
+/// Destination address of a PDU1 (destination specific) id.
+pub fn da(id: u32) -> u8 {
+    ((id >> 8) & 0xFF) as u8
+}
+
+/// PGN column text. 11 bit ids have no PGN, so the id is shown instead.
+pub fn pgn_text(id: u32) -> String {
+    if is_standard(id) {
+        format!("{:03X}", id & 0x7FF)
+    } else {
+        format!("{:04X}", pgn_of(id))
+    }
+}
+
+/// SA column text, empty for 11 bit ids.
+pub fn sa_text(id: u32) -> String {
+    if is_standard(id) {
+        String::new()
+    } else {
+        format!("{:02X}", sa(id))
+    }
+}
+
+/// Index of the last item at or before `time`. `items` must be in chronological order.
+pub fn last_before<T>(
+    items: &[T],
+    time: Duration,
+    time_of: impl Fn(&T) -> Duration,
+) -> Option<usize> {
+    items.partition_point(|p| time_of(p) <= time).checked_sub(1)
+}
+
+/// Log line for a packet. 11 bit ids are shown as 3 digits so they stand out from J1939,
+/// and CAN FD frames are followed by their flags.
+pub fn packet_string(p: &J1939Packet, flags: u8) -> String {
+    let line = if is_standard(p.id()) {
+        let data: Vec<String> = p.payload.iter().map(|b| format!("{b:02X}")).collect();
+        format!(
+            "{:12.4} {:>8} [{}] {}",
+            p.time().unwrap_or_default().as_secs_f64(),
+            format!("{:03X}", p.id() & 0x7FF),
+            p.payload.len(),
+            data.join(" ")
+        )
+    } else {
+        p.to_string()
+    };
+    line + &flags_text(flags)
+}
+
+/// Indexes of the bytes of `payload` that differ from `previous`, including bytes past its
+/// end.
+pub fn changed_bytes(previous: &[u8], payload: &[u8]) -> Vec<usize> {
+    (0..payload.len())
+        .filter(|i| previous.get(*i) != Some(&payload[*i]))
+        .collect()
+}
+
+/// What added a marker to the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerKind {
+    /// a note from the user
+    Annotation,
+    Alarm,
+    /// an OBD-II response
+    Obd,
+    /// an adapter was lost or reconnected
+    Adapter,
+}
+
+/// A line in the log between packets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub time: Duration,
+    pub kind: MarkerKind,
+    pub text: String,
+    /// index in `PacketRepo::packets` the marker goes before
+    pub position: usize,
+}
+
+/// Log line for a marker, a comment so log readers skip it.
+pub fn marker_string(time: Duration, text: &str) -> String {
+    format!("{:12.4} // {text}", time.as_secs_f64())
+}
+
+/// Write packets in the log file format, one per line. Packets from channels other than 0
+/// are followed by their channel. Markers among the packets are written in time order.
+pub fn write_packets(
+    out: &mut impl Write,
+    repo: &PacketRepo,
+    range: Range<usize>,
+) -> io::Result<()> {
+    write_selected(out, repo, range, |_| true)
+}
+
+/// Write the packets with times from `start` to `end`, which are out of order when several
+/// channels are read, and the markers among them.
+pub fn write_packets_between(
+    out: &mut impl Write,
+    repo: &PacketRepo,
+    start: Duration,
+    end: Duration,
+) -> io::Result<()> {
+    let within = |p: &J1939Packet| (start..=end).contains(&p.time().unwrap_or_default());
+    let (Some(first), Some(last)) = (
+        repo.packets.iter().position(within),
+        repo.packets.iter().rposition(within),
+    ) else {
+        return Ok(());
+    };
+    write_selected(out, repo, first..last + 1, |i| within(&repo.packets[i]))
+}
+
+fn write_selected(
+    out: &mut impl Write,
+    repo: &PacketRepo,
+    range: Range<usize>,
+    selected: impl Fn(usize) -> bool,
+) -> io::Result<()> {
+    let mut markers = repo
+        .markers
+        .iter()
+        .filter(|m| range.contains(&m.position) || m.position == range.end)
+        .peekable();
+    for i in range.clone() {
+        while let Some(m) = markers.next_if(|m| m.position == i) {
+            write!(out, "{}\r\n", marker_string(m.time, &m.text))?;
+        }
+        if !selected(i) {
+            continue;
+        }
+        out.write_all(packet_string(&repo.packets[i], repo.flags[i]).as_bytes())?;
+        if repo.channels[i] != 0 {
+            write!(out, " ch{}", repo.channels[i])?;
+        }
+        out.write_all(b"\r\n")?;
+    }
+    for m in markers {
+        write!(out, "{}\r\n", marker_string(m.time, &m.text))?;
+    }
+    Ok(())
+}
 
 #[derive(Clone, Default)]
 pub struct PacketRepo {
     packets: Vec<J1939Packet>,
+    /// `fd` flags for each packet
+    flags: Vec<u8>,
+    /// channel each packet was read from
+    channels: Vec<u8>,
     map: HashMap<u32, Vec<J1939Packet>>,
+    /// packets by `channel_key`
+    by_channel: HashMap<u32, Vec<J1939Packet>>,
+    /// index of each packet in its `by_channel` list
+    positions: Vec<usize>,
+    /// in `position` order
+    markers: Vec<Marker>,
+    /// times cleared
+    generation: usize,
 }
 
 impl PacketRepo {
     pub fn push(&mut self, packet: J1939Packet) {
+        self.push_from(0, packet, 0);
+    }
+    /// Add a packet read from `channel` with its CAN FD flags. Its id is from `frame_id`.
+    pub fn push_from(&mut self, channel: u8, packet: J1939Packet, flags: u8) {
+        let key = repo_key(packet.id());
+        self.flags.push(flags);
+        self.channels.push(channel);
         self.packets.push(packet.clone());
-        self.map
-            .entry(packet.id() & (0x3FFFFFF))
-            .or_default()
-            .push(packet);
+        let same_id = self
+            .by_channel
+            .entry(channel_key(Some(channel), key))
+            .or_default();
+        self.positions.push(same_id.len());
+        same_id.push(packet.clone());
+        self.map.entry(key).or_default().push(packet);
     }
     pub fn clear(&mut self) {
         self.packets.clear();
+        self.flags.clear();
+        self.channels.clear();
         self.map.clear();
+        self.by_channel.clear();
+        self.positions.clear();
+        self.markers.clear();
+        self.generation += 1;
+    }
+    /// Times the repo was cleared, so readers can tell a clear from a quiet bus.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+    /// Mark a point in the log, such as a raised alarm or an annotation from the user. The
+    /// marker goes after the last packet at or before `time`. Packets from several channels are
+    /// not always in time order, so this is found once, searching back from the newest packet.
+    pub fn add_marker(&mut self, time: Duration, kind: MarkerKind, text: String) {
+        let position = self
+            .packets
+            .iter()
+            .rposition(|p| p.time().unwrap_or_default() <= time)
+            .map_or(0, |i| i + 1);
+        let index = self
+            .markers
+            .partition_point(|m| (m.position, m.time) <= (position, time));
+        self.markers.insert(
+            index,
+            Marker {
+                time,
+                kind,
+                text,
+                position,
+            },
+        );
     }
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+    /// Index in `packets()` each marker goes before.
+    pub fn marker_positions(&self) -> Vec<usize> {
+        self.markers.iter().map(|m| m.position).collect()
+    }
+    /// First annotation after `time`.
+    pub fn annotation_after(&self, time: Duration) -> Option<&Marker> {
+        self.annotations()
+            .filter(|m| m.time > time)
+            .min_by_key(|m| m.time)
+    }
+    /// Last annotation before `time`.
+    pub fn annotation_before(&self, time: Duration) -> Option<&Marker> {
+        self.annotations()
+            .filter(|m| m.time < time)
+            .max_by_key(|m| m.time)
+    }
+    fn annotations(&self) -> impl Iterator<Item = &Marker> {
+        self.markers
+            .iter()
+            .filter(|m| m.kind == MarkerKind::Annotation)
+    }
+    /// Ids seen, without priority.
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.map.keys().copied()
+    }
+    /// Packets for a key, or a `channel_key` for packets from one channel.
     pub fn get_for(&self, id: u32) -> Option<&Vec<J1939Packet>> {
-        self.map.get(&id)
+        if id & CHANNEL != 0 {
+            self.by_channel.get(&id)
+        } else {
+            self.map.get(&id)
+        }
+    }
+    /// Most recent packet for `id` at or before `time`, with its index in `get_for(id)`.
+    pub fn last_before(&self, id: u32, time: Duration) -> Option<(usize, &J1939Packet)> {
+        let packets = self.get_for(id)?;
+        last_before(packets, time, |p| p.time().unwrap_or_default()).map(|i| (i, &packets[i]))
+    }
+    /// Source addresses seen sending `id`, ignoring priority and the SA in `id`. A
+    /// `channel_key` limits the search to that channel.
+    pub fn source_addresses(&self, id: u32) -> Vec<u8> {
+        let keys = if id & CHANNEL != 0 {
+            self.by_channel.keys()
+        } else {
+            self.map.keys()
+        };
+        let mask = CHANNEL | 0x3FFFF00;
+        let mut addresses: Vec<u8> = keys
+            .filter(|k| !is_standard(**k) && *k & mask == id & mask)
+            .map(|k| sa(*k))
+            .collect();
+        addresses.sort();
+        addresses
+    }
+    /// The packet before `packets()[index]` with the same id on the same channel.
+    pub fn previous(&self, index: usize) -> Option<&J1939Packet> {
+        let position = self.positions.get(index)?.checked_sub(1)?;
+        let key = channel_key(
+            Some(self.channels[index]),
+            repo_key(self.packets[index].id()),
+        );
+        self.get_for(key)?.get(position)
+    }
+    /// True unless `packets()[index]` repeats the payload of the previous packet with its id.
+    pub fn is_change(&self, index: usize) -> bool {
+        self.previous(index)
+            .is_none_or(|p| p.payload != self.packets[index].payload)
     }
     pub fn last_time(&self) -> Duration {
-        self.packets.last().and_then(|p| p.time()).unwrap_or_default()
+        self.packets
+            .last()
+            .and_then(|p| p.time())
+            .unwrap_or_default()
     }
     pub fn first_time(&self) -> Duration {
-        self.packets.first().and_then(|p| p.time()).unwrap_or_default()
+        self.packets
+            .first()
+            .and_then(|p| p.time())
+            .unwrap_or_default()
     }
     pub fn packets(&self) -> &Vec<J1939Packet> {
         &self.packets
     }
+    /// CAN FD flags, in the same order as `packets()`.
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+    /// Channel of each packet, in the same order as `packets()`.
+    pub fn channels(&self) -> &[u8] {
+        &self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_ids() {
+        assert!(is_standard(frame_id(0x7DF)));
+        assert!(!is_standard(frame_id(0x18FEF100)));
+        // IDE flag wins over the value
+        assert!(!is_standard(frame_id(EXTENDED | 0x123)));
+        assert_eq!(frame_id(0x7DF), STANDARD | 0x7DF);
+        assert_eq!(repo_key(frame_id(0x7DF)), STANDARD | 0x7DF);
+        assert_eq!(repo_key(repo_key(frame_id(0x7DF))), STANDARD | 0x7DF);
+        assert_eq!(dbc_key(EXTENDED | 0x18FEF100), 0x00FEF100);
+        assert_ne!(dbc_key(0x123), dbc_key(EXTENDED | 0x123));
+        assert_eq!(ide_id(0x18FEF100), EXTENDED | 0x18FEF100);
+        assert_eq!(ide_id(frame_id(0x7E8)), 0x7E8);
+        let id = frame_id(0x7E8);
+        assert_eq!((pgn_text(id), sa_text(id)), ("7E8".into(), String::new()));
+        assert_eq!(
+            (pgn_text(0x18FEF100), sa_text(0x18FEF100)),
+            ("FEF1".into(), "00".into())
+        );
+    }
+
+    /// TSC1 to DA 0 from SA 3 at priority 0 is 29 bit, even though its id fits in 11 bits.
+    #[test]
+    fn priority_0_pgn_0() {
+        let id = frame_id(EXTENDED | 0x0000_0003);
+        assert_eq!(id, 0x3);
+        let key = repo_key(id);
+        assert!(!is_standard(key));
+        assert_eq!(repo_key(key), key);
+        assert_eq!((pgn_text(key), sa_text(key)), ("0000".into(), "03".into()));
+        assert_eq!(ide_id(id), EXTENDED | 0x3);
+        // priority 3, masked in the key
+        assert_eq!(repo_key(frame_id(0x0C00_0003)), key);
+        assert_eq!(dbc_key(EXTENDED | 0x0C00_0003), key);
+
+        let mut repo = PacketRepo::default();
+        repo.push(J1939Packet::new(None, 0, id, &[1, 2, 3]));
+        assert_eq!(repo.ids().collect::<Vec<_>>(), [key]);
+        assert_eq!(repo.source_addresses(key), [3]);
+    }
+
+    #[test]
+    fn changes() {
+        assert_eq!(changed_bytes(&[1, 2, 3], &[1, 2, 3]), Vec::<usize>::new());
+        assert_eq!(changed_bytes(&[1, 2, 3], &[1, 5, 3]), vec![1]);
+        assert_eq!(changed_bytes(&[1, 2], &[0, 2, 3, 4]), vec![0, 2, 3]);
+        assert_eq!(changed_bytes(&[1, 2, 3], &[1]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn markers() {
+        let s = Duration::from_secs;
+        let mut repo = PacketRepo::default();
+        repo.add_marker(s(5), MarkerKind::Annotation, "b".into());
+        repo.add_marker(s(1), MarkerKind::Annotation, "a".into());
+        repo.add_marker(s(5), MarkerKind::Annotation, "c".into());
+        repo.add_marker(s(3), MarkerKind::Obd, "OBD".into());
+        let texts: Vec<&str> = repo.markers().iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["a", "OBD", "b", "c"]);
+        assert_eq!(repo.annotation_after(s(1)).unwrap().text, "b");
+        assert_eq!(repo.annotation_before(s(5)).unwrap().text, "a");
+        assert_eq!(repo.annotation_before(s(1)), None);
+        assert_eq!(repo.marker_positions(), vec![0, 0, 0, 0]);
+
+        let mut out = Vec::new();
+        write_packets(&mut out, &repo, 0..0).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "      1.0000 // a\r\n      3.0000 // OBD\r\n      5.0000 // b\r\n      5.0000 // c\r\n"
+        );
+    }
+
+    /// Two channels read at once interleave their packets out of time order.
+    #[test]
+    fn markers_out_of_order() {
+        let mut repo = PacketRepo::default();
+        for (channel, ms) in [(0, 1000), (0, 3000), (1, 2000), (0, 4000)] {
+            repo.push_from(
+                channel,
+                J1939Packet::new(Some(Duration::from_millis(ms)), 0, 0x18FEF100, &[]),
+                0,
+            );
+        }
+        repo.add_marker(
+            Duration::from_millis(2500),
+            MarkerKind::Annotation,
+            "x".into(),
+        );
+        assert_eq!(repo.marker_positions(), vec![3]);
+        repo.add_marker(Duration::from_millis(500), MarkerKind::Alarm, "y".into());
+        repo.add_marker(Duration::from_secs(9), MarkerKind::Adapter, "z".into());
+        assert_eq!(repo.marker_positions(), vec![0, 3, 4]);
+
+        let mut out = Vec::new();
+        write_packets_between(
+            &mut out,
+            &repo,
+            Duration::from_millis(1500),
+            Duration::from_millis(3000),
+        )
+        .unwrap();
+        // 3000, then 2000 from channel 1, and the marker
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3, "{out}");
+        assert!(!lines[0].ends_with("ch1"));
+        assert!(lines[1].ends_with(" ch1"));
+        assert_eq!(lines[2], marker_string(Duration::from_millis(2500), "x"));
+    }
+
+    #[test]
+    fn channel_keys() {
+        let key = repo_key(0x18FEF100);
+        assert_eq!(channel_key(None, key), key);
+        assert_eq!(channel_key(Some(0), key), 0x04FEF100);
+        assert_eq!(channel_key(Some(2), key) & !CHANNEL, key);
+        assert_ne!(channel_key(Some(1), key), channel_key(Some(2), key));
+        assert!(is_standard(channel_key(Some(0), repo_key(frame_id(0x7E8)))));
+        assert_eq!(pgn_of(channel_key(Some(3), key)), 0xFEF1);
+    }
+
+    #[test]
+    fn last_before_edges() {
+        let times: Vec<Duration> = (1..=5).map(Duration::from_secs).collect();
+        let t = |d: &Duration| *d;
+        assert_eq!(last_before(&times, Duration::ZERO, t), None);
+        assert_eq!(last_before(&times, Duration::from_secs(1), t), Some(0));
+        assert_eq!(last_before(&times, Duration::from_millis(3500), t), Some(2));
+        assert_eq!(last_before(&times, Duration::MAX, t), Some(4));
+        assert_eq!(last_before(&[] as &[Duration], Duration::MAX, t), None);
+    }
 }
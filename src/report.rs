@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Error;
+use fltk::dialog::alert_default;
+
+/// Set by the GUI. Without it, such as in tests, errors only go to stderr.
+static DIALOGS: AtomicBool = AtomicBool::new(false);
+
+pub fn use_dialogs() {
+    DIALOGS.store(true, Ordering::Relaxed);
+}
+
+/// Tell the user what failed, on stderr and in a dialog when there is a GUI. Must be called
+/// from the main thread.
+pub fn report(what: &str, err: &Error) {
+    let text = format!("{what}: {err:#}");
+    eprintln!("{text}");
+    if DIALOGS.load(Ordering::Relaxed) {
+        alert_default(&text);
+    }
+}
+
+/// Report the error of `result`, if any.
+pub fn report_err<T>(what: &str, result: Result<T, Error>) -> Option<T> {
+    result.map_err(|err| report(what, &err)).ok()
+}
@@ -0,0 +1,660 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use fltk::{
+    browser::HoldBrowser,
+    button::Button,
+    dialog::{alert_default, FileDialog, FileDialogType},
+    enums::{Align, Font},
+    frame::Frame,
+    group::{Flex, PackType},
+    input::{FloatInput, Input, IntInput},
+    menu::Choice,
+    prelude::{BrowserExt, GroupExt, InputExt, MenuExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+
+use crate::{
+    dbc::{self, Dbc, Signal},
+    dbc_table::DbcTables,
+    packet_repo::{dbc_key, ide_id, is_standard, last_before, pgn_text, sa_text, PacketRepo},
+    report::report_err,
+    validate::bit_changes,
+};
+
+/// Pairs of packets needed to call a field a counter or checksum.
+const MIN_PAIRS: usize = 8;
+/// Samples needed to correlate a field with a signal.
+const MIN_SAMPLES: usize = 10;
+/// Correlations listed.
+const TOP: usize = 20;
+/// DBC files saved here start with this.
+const NEW_DBC: &str = "VERSION \"\"\n\nNS_ :\n\nBS_:\n\nBU_:\n";
+
+/// An unnamed bit range of a payload.
+pub fn field(start: usize, length: usize, little_endian: bool) -> Signal {
+    Signal {
+        start,
+        length,
+        little_endian,
+        ..Default::default()
+    }
+}
+
+fn field_text(f: &Signal) -> String {
+    format!(
+        "{}|{} {}",
+        f.start,
+        f.length,
+        if f.little_endian { "Intel" } else { "Motorola" }
+    )
+}
+
+/// Unsigned value of the bits of a field, least significant first.
+pub fn field_value(payload: &[u8], bits: &[usize]) -> Option<u64> {
+    bits.iter().enumerate().try_fold(0, |value, (i, bit)| {
+        let byte = payload.get(bit / 8)?;
+        Some(value | (((byte >> (bit % 8)) & 1) as u64) << i)
+    })
+}
+
+/// Times each byte differed from the previous payload.
+pub fn byte_changes(payloads: &[Vec<u8>]) -> Vec<usize> {
+    let len = payloads.iter().map(|p| p.len()).max().unwrap_or_default();
+    let mut changes = vec![0; len];
+    for pair in payloads.windows(2) {
+        for (byte, (a, b)) in pair[0].iter().zip(&pair[1]).enumerate() {
+            changes[byte] += (a != b) as usize;
+        }
+    }
+    changes
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Checksum {
+    /// sum of the other bytes
+    Sum,
+    /// xor of the other bytes
+    Xor,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Detected {
+    /// increments by one each packet, seen wrapping to 0
+    Counter(Signal),
+    /// a byte computed from the other bytes
+    Checksum(usize, Checksum),
+}
+
+impl Display for Detected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Detected::Counter(c) => write!(f, "counter {}", field_text(c)),
+            Detected::Checksum(byte, kind) => write!(f, "byte {byte} {kind:?} checksum"),
+        }
+    }
+}
+
+/// Share of `pairs` that pass `test`, or 0 when there are too few.
+fn ratio<T>(pairs: impl Iterator<Item = T>, test: impl Fn(&T) -> bool) -> f64 {
+    let (mut n, mut ok) = (0, 0);
+    for pair in pairs {
+        n += 1;
+        ok += test(&pair) as usize;
+    }
+    if n < MIN_PAIRS {
+        0.0
+    } else {
+        ok as f64 / n as f64
+    }
+}
+
+/// Counters in whole bytes or nibbles, and checksum bytes.
+pub fn detect(payloads: &[Vec<u8>]) -> Vec<Detected> {
+    let len = payloads.iter().map(|p| p.len()).min().unwrap_or_default();
+    let mut found = Vec::new();
+    for byte in 0..len {
+        let candidates = [
+            field(byte * 8, 8, true),
+            field(byte * 8, 4, true),
+            field(byte * 8 + 4, 4, true),
+        ];
+        for c in candidates {
+            let bits = c.bits();
+            let modulus = 1u64 << c.length;
+            let values: Vec<u64> = payloads
+                .iter()
+                .filter_map(|p| field_value(p, &bits))
+                .collect();
+            let counting = ratio(values.windows(2), |w| w[1] == (w[0] + 1) % modulus);
+            // a wrap tells a nibble counter from the byte holding it
+            let wraps = values.windows(2).any(|w| w[0] == modulus - 1 && w[1] == 0);
+            if counting >= 0.9 && wraps {
+                found.push(Detected::Counter(c));
+                // the low nibble of a byte counter counts too
+                break;
+            }
+        }
+        let constant = payloads.iter().all(|p| p[byte] == payloads[0][byte]);
+        if constant {
+            continue;
+        }
+        for kind in [Checksum::Sum, Checksum::Xor] {
+            let matching = ratio(payloads.iter(), |p| {
+                let others = p.iter().enumerate().filter(|(i, _)| *i != byte);
+                let value = others.fold(0u8, |acc, (_, b)| match kind {
+                    Checksum::Sum => acc.wrapping_add(*b),
+                    Checksum::Xor => acc ^ b,
+                });
+                value == p[byte]
+            });
+            if matching >= 0.95 {
+                found.push(Detected::Checksum(byte, kind));
+            }
+        }
+    }
+    found
+}
+
+/// Count of each value, most common first.
+pub fn histogram(values: impl Iterator<Item = u64>) -> Vec<(u64, usize)> {
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for v in values {
+        *counts.entry(v).or_default() += 1;
+    }
+    let mut counts: Vec<(u64, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+/// Pearson correlation, None when either side is constant.
+pub fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    let n = pairs.len() as f64;
+    let (mx, my) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        sxy += (x - mx) * (y - my);
+        sxx += (x - mx) * (x - mx);
+        syy += (y - my) * (y - my);
+    }
+    if sxx == 0.0 || syy == 0.0 {
+        return None;
+    }
+    Some(sxy / (sxx * syy).sqrt())
+}
+
+/// Bytes and 16 bit fields in either byte order, most correlated with `reference` first.
+/// Each packet is paired with the last reference value at or before it.
+pub fn correlate(
+    packets: &[(Duration, Vec<u8>)],
+    reference: &[(Duration, f64)],
+) -> Vec<(Signal, f64)> {
+    let len = packets.iter().map(|p| p.1.len()).min().unwrap_or_default();
+    let mut candidates: Vec<Signal> = (0..len).map(|b| field(b * 8, 8, true)).collect();
+    for b in 1..len {
+        candidates.push(field((b - 1) * 8, 16, true));
+        candidates.push(field((b - 1) * 8 + 7, 16, false));
+    }
+    let samples: Vec<(&Vec<u8>, f64)> = packets
+        .iter()
+        .filter_map(|(time, payload)| {
+            let i = last_before(reference, *time, |r| r.0)?;
+            Some((payload, reference[i].1))
+        })
+        .collect();
+    if samples.len() < MIN_SAMPLES {
+        return Vec::new();
+    }
+    let mut results: Vec<(Signal, f64)> = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let bits = c.bits();
+            let pairs: Vec<(f64, f64)> = samples
+                .iter()
+                .filter_map(|(p, r)| Some((field_value(p, &bits)? as f64, *r)))
+                .collect();
+            let r = correlation(&pairs)?;
+            Some((c, r))
+        })
+        .collect();
+    results.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    results.truncate(TOP);
+    results
+}
+
+/// Change counts of each byte of one id.
+#[derive(Default)]
+pub struct BitModel {
+    /// bit changes, 8 per byte
+    changes: Vec<usize>,
+    /// distinct values of each byte
+    values: Vec<usize>,
+    /// payload pairs compared
+    pairs: usize,
+}
+
+impl BitModel {
+    fn update(&mut self, payloads: &[Vec<u8>]) {
+        self.changes = bit_changes(payloads);
+        self.values = (0..self.changes.len() / 8)
+            .map(|byte| {
+                payloads
+                    .iter()
+                    .filter_map(|p| p.get(byte))
+                    .collect::<HashSet<_>>()
+                    .len()
+            })
+            .collect();
+        self.pairs = payloads.len().saturating_sub(1);
+    }
+}
+
+impl SimpleModel for BitModel {
+    fn row_count(&mut self) -> usize {
+        self.values.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        11
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        match col {
+            0 => "Byte".into(),
+            1 => "Changes".into(),
+            2 => "Values".into(),
+            // most significant bit first, as the bits are written
+            c => format!("b{}", 10 - c),
+        }
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0..=2 => 60,
+            _ => 40,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let byte = row as usize;
+        let bits = self.changes.get(byte * 8..byte * 8 + 8)?;
+        let percent = |n: usize| format!("{:0.0}%", 100.0 * n as f64 / self.pairs.max(1) as f64);
+        match col {
+            0 => Some(byte.to_string()),
+            1 => Some(percent(bits.iter().copied().max().unwrap_or_default())),
+            2 => Some(self.values[byte].to_string()),
+            c => Some(percent(bits[10 - c as usize])),
+        }
+    }
+
+    fn sort(&mut self, _col: usize, _order: Order) {
+        // bytes stay in order
+    }
+}
+
+/// Add a signal to a DBC file, creating it if needed.
+fn save_signal(path: &Path, id: u32, dlc: usize, signal: Signal) -> Result<()> {
+    let mut dbc = if path.exists() {
+        Dbc::read(path)?
+    } else {
+        dbc::parse(NEW_DBC).0
+    };
+    // new J1939 messages get the default priority of 6
+    let id = if is_standard(id) {
+        ide_id(id)
+    } else {
+        ide_id(id | 0x1800_0000)
+    };
+    let name = format!("MSG_{:X}", id & 0x1FFF_FFFF);
+    dbc.add_signal(id, &name, dlc, signal);
+    for m in &dbc.messages {
+        m.check()?;
+    }
+    dbc.save(path)
+}
+
+/// Analyze unknown ids, to find and save new signals.
+pub fn reverse_window(packets: Arc<RwLock<PacketRepo>>, dbc_tables: DbcTables) {
+    let mut wind = Window::default()
+        .with_size(900, 600)
+        .with_label("Reverse Engineering");
+    let mut flex = Flex::default_fill().with_type(PackType::Horizontal);
+
+    let mut left = Flex::default_fill().with_type(PackType::Vertical);
+    let mut ids_browser = HoldBrowser::default();
+    let mut refresh = Button::default().with_label("Refresh");
+    left.fixed(&refresh, 25);
+    left.end();
+    flex.fixed(&left, 200);
+
+    let mut right = Flex::default_fill().with_type(PackType::Vertical);
+    let mut table = JoeTable::new(BitModel::default());
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+    let mut detected = Frame::default().with_align(Align::Left | Align::Inside);
+    right.fixed(&detected, 20);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Start bit");
+    let mut start = IntInput::default();
+    Frame::default().with_label("Length");
+    let mut length = IntInput::default();
+    let mut order = Choice::default();
+    order.add_choice("Intel|Motorola");
+    order.set_value(0);
+    let mut histogram_button = Button::default().with_label("Histogram");
+    row.end();
+    right.fixed(&row, 25);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut reference = Choice::default();
+    let references: Vec<(usize, u32, String)> = dbc_tables
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .flat_map(|(t, table)| {
+            let names = table
+                .table
+                .lock()
+                .unwrap()
+                .model
+                .lock()
+                .unwrap()
+                .signal_names();
+            names.into_iter().map(move |(id, name)| (t, id, name))
+        })
+        .collect();
+    for (_, id, name) in &references {
+        reference.add_choice(&format!("{} {name}", pgn_text(dbc_key(*id))).replace('/', "\\/"));
+    }
+    reference.set_value(0);
+    let mut correlate_button = Button::default().with_label("Correlate");
+    row.end();
+    right.fixed(&row, 25);
+
+    let mut results = HoldBrowser::default();
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Name");
+    let name = Input::default();
+    Frame::default().with_label("Scale");
+    let mut scale = FloatInput::default();
+    scale.set_value("1");
+    Frame::default().with_label("Offset");
+    let mut offset = FloatInput::default();
+    offset.set_value("0");
+    Frame::default().with_label("Unit");
+    let unit = Input::default();
+    let mut save = Button::default().with_label("Save to DBC...");
+    row.end();
+    right.fixed(&row, 25);
+    right.end();
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    // ids in the browser, and the correlation results
+    let ids: Arc<Mutex<Vec<u32>>> = Default::default();
+    let found: Arc<Mutex<Vec<Signal>>> = Default::default();
+    let selected = {
+        let ids = ids.clone();
+        let ids_browser = ids_browser.clone();
+        move || {
+            let index = (ids_browser.value() as usize).checked_sub(1)?;
+            ids.lock().unwrap().get(index).copied()
+        }
+    };
+    let payloads = {
+        let packets = packets.clone();
+        move |id: u32| -> Vec<(Duration, Vec<u8>)> {
+            let repo = packets.read().unwrap();
+            repo.get_for(id)
+                .map(|v| {
+                    v.iter()
+                        .map(|p| (p.time().unwrap_or_default(), p.payload.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+    };
+    let chosen = {
+        let start = start.clone();
+        let length = length.clone();
+        let order = order.clone();
+        move || -> Result<Signal> {
+            let start: usize = start.value().parse()?;
+            let length: usize = length.value().parse()?;
+            if length == 0 || length > 64 {
+                return Err(anyhow!("Length must be 1 to 64 bits."));
+            }
+            Ok(field(start, length, order.value() == 0))
+        }
+    };
+
+    let mut load_ids = {
+        let ids = ids.clone();
+        let mut ids_browser = ids_browser.clone();
+        let packets = packets.clone();
+        move || {
+            let repo = packets.read().unwrap();
+            let mut list: Vec<u32> = repo.ids().collect();
+            list.sort();
+            ids_browser.clear();
+            for id in &list {
+                let count = repo.get_for(*id).map_or(0, |v| v.len());
+                ids_browser.add(&format!("{} {} ({count})", pgn_text(*id), sa_text(*id)));
+            }
+            *ids.lock().unwrap() = list;
+        }
+    };
+    load_ids();
+    refresh.set_callback(move |_| load_ids());
+    {
+        let selected = selected.clone();
+        let payloads = payloads.clone();
+        ids_browser.set_callback(move |_| {
+            let Some(id) = selected() else {
+                return;
+            };
+            let data: Vec<Vec<u8>> = payloads(id).into_iter().map(|p| p.1).collect();
+            table.model.lock().unwrap().update(&data);
+            table.redraw();
+            let text: Vec<String> = detect(&data).iter().map(|d| d.to_string()).collect();
+            detected.set_label(&text.join(", "));
+        });
+    }
+    {
+        let selected = selected.clone();
+        let payloads = payloads.clone();
+        let chosen = chosen.clone();
+        let mut results = results.clone();
+        histogram_button.set_callback(move |_| {
+            let Some(id) = selected() else {
+                return;
+            };
+            let Some(f) = report_err("Invalid field", chosen()) else {
+                return;
+            };
+            let bits = f.bits();
+            let data = payloads(id);
+            results.clear();
+            for (value, count) in histogram(data.iter().filter_map(|p| field_value(&p.1, &bits))) {
+                results.add(&format!("{value} (0x{value:X}): {count}"));
+            }
+        });
+    }
+    {
+        let selected = selected.clone();
+        let payloads = payloads.clone();
+        let found = found.clone();
+        let mut results = results.clone();
+        correlate_button.set_callback(move |_| {
+            let Some(id) = selected() else {
+                return;
+            };
+            let Some((table, ref_id, ref_name)) = references.get(reference.value() as usize) else {
+                alert_default("Open a DBC file to choose a signal.");
+                return;
+            };
+            let Some(dbc) = dbc_tables.lock().unwrap().get(*table).cloned() else {
+                return;
+            };
+            let series = dbc
+                .table
+                .lock()
+                .unwrap()
+                .model
+                .lock()
+                .unwrap()
+                .series(*ref_id, ref_name);
+            let correlated = correlate(&payloads(id), &series);
+            results.clear();
+            for (f, r) in &correlated {
+                results.add(&format!("{} r={r:0.3}", field_text(f)));
+            }
+            *found.lock().unwrap() = correlated.into_iter().map(|c| c.0).collect();
+        });
+    }
+    results.set_callback(move |b| {
+        // choose a correlated field
+        let Some(index) = (b.value() as usize).checked_sub(1) else {
+            return;
+        };
+        if let Some(f) = found.lock().unwrap().get(index) {
+            start.set_value(&f.start.to_string());
+            length.set_value(&f.length.to_string());
+            order.set_value(if f.little_endian { 0 } else { 1 });
+        }
+    });
+    save.set_callback(move |_| {
+        let Some(id) = selected() else {
+            alert_default("Select an id.");
+            return;
+        };
+        let Some(mut signal) = report_err("Invalid field", chosen()) else {
+            return;
+        };
+        let (Ok(scale), Ok(offset)) = (scale.value().parse(), offset.value().parse()) else {
+            alert_default("Invalid scale or offset.");
+            return;
+        };
+        signal.name = name.value().trim().to_string();
+        signal.scale = scale;
+        signal.offset = offset;
+        signal.unit = unit.value();
+        signal.fit_range();
+        let mut fc = FileDialog::new(FileDialogType::BrowseSaveFile);
+        fc.set_filter("*.dbc");
+        fc.show();
+        if fc.filenames().is_empty() {
+            return;
+        }
+        let dlc = payloads(id).iter().map(|p| p.1.len()).max().unwrap_or(8);
+        report_err(
+            "Unable to save signal",
+            save_signal(&fc.filename(), id, dlc, signal),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_repo::{frame_id, repo_key, EXTENDED};
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            field_value(&[0x34, 0x12], &field(0, 16, true).bits()),
+            Some(0x1234)
+        );
+        assert_eq!(
+            field_value(&[0x12, 0x34], &field(7, 16, false).bits()),
+            Some(0x1234)
+        );
+        assert_eq!(field_value(&[0xA5], &field(4, 4, true).bits()), Some(0xA));
+        assert_eq!(field_value(&[0xA5], &field(4, 8, true).bits()), None);
+        assert_eq!(
+            histogram([3, 1, 3, 2, 1, 3].into_iter()),
+            vec![(3, 3), (1, 2), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn counters_and_checksums() {
+        let payloads: Vec<Vec<u8>> = (0..20u8)
+            .map(|i| {
+                let mut p = vec![i * 3, 0xFF, (i % 16) | 0x50, 0];
+                p[3] = p[0].wrapping_add(p[1]).wrapping_add(p[2]);
+                p
+            })
+            .collect();
+        assert_eq!(
+            detect(&payloads),
+            vec![
+                Detected::Counter(field(16, 4, true)),
+                Detected::Checksum(3, Checksum::Sum)
+            ]
+        );
+        assert_eq!(byte_changes(&payloads)[..3], [19, 0, 19]);
+    }
+
+    #[test]
+    fn correlated_fields() {
+        assert_eq!(
+            correlation(&[(1.0, 2.0), (2.0, 4.0), (3.0, 6.0)]),
+            Some(1.0)
+        );
+        assert_eq!(correlation(&[(1.0, 2.0), (1.0, 4.0)]), None);
+
+        // engine speed at 0.125 rpm/bit in bytes 3 and 4
+        let ms = Duration::from_millis;
+        let mut packets = Vec::new();
+        let mut reference = Vec::new();
+        for i in 0..50u64 {
+            let rpm = 600.0 + (i * i) as f64;
+            let raw = ((rpm / 0.125) as u16).to_le_bytes();
+            packets.push((
+                ms(i * 10 + 5),
+                vec![i as u8, 0xFF, 0xFF, raw[0], raw[1], 0xFF],
+            ));
+            reference.push((ms(i * 10), rpm));
+        }
+        let results = correlate(&packets, &reference);
+        assert_eq!(results[0].0, field(24, 16, true));
+        assert!(results[0].1 > 0.999);
+    }
+
+    #[test]
+    fn saved_ids() {
+        let path = std::env::temp_dir().join(format!("reverse-test-{}.dbc", std::process::id()));
+        let signal = |name: &str| Signal {
+            name: name.into(),
+            scale: 1.0,
+            max: 255.0,
+            ..field(0, 8, true)
+        };
+        // TSC1 at priority 0 to engine 0 has key 0x3, which is not an 11 bit id
+        let tsc1 = repo_key(frame_id(EXTENDED | 0x0000_0003));
+        save_signal(&path, tsc1, 8, signal("Request")).unwrap();
+        save_signal(&path, frame_id(0x123), 4, signal("Level")).unwrap();
+        let dbc = Dbc::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let ids: Vec<u32> = dbc.unwrap().messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [0x9800_0003, 0x123]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::address_map::AddressMapping;
+
+/// Maximum number of entries in the recent files list.
+const MAX_RECENT: usize = 10;
+
+/// How the adapter was chosen, so it can be opened again.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterChoice {
+    /// Connection menu path: product/device/connection.
+    Menu(String),
+    /// Command line arguments, as given to `Cli`.
+    Args(Vec<String>),
+}
+
+/// Everything needed to put the workspace back the way it was.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    /// open adapters, in channel order
+    pub adapters: Vec<AdapterChoice>,
+    pub log_column_widths: Vec<u32>,
+    pub dbcs: Vec<DbcSession>,
+}
+
+/// State of one DBC window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DbcSession {
+    pub path: PathBuf,
+    /// source address mappings, first match wins.
+    pub mappings: Vec<AddressMapping>,
+    /// show messages for every source address that sends them.
+    pub auto_match: bool,
+    pub hide_inactive: bool,
+    pub column_widths: Vec<u32>,
+    /// column and descending
+    pub sort: Option<(usize, bool)>,
+    pub chart_duration: Duration,
+    /// only decode packets from this channel
+    pub channel: Option<u8>,
+}
+
+impl DbcSession {
+    pub fn new(path: PathBuf) -> DbcSession {
+        DbcSession {
+            path,
+            mappings: Vec::new(),
+            auto_match: false,
+            hide_inactive: false,
+            column_widths: Vec::new(),
+            sort: None,
+            chart_duration: Duration::from_secs(10),
+            channel: None,
+        }
+    }
+}
+
+impl Session {
+    /// Line oriented `key=value` format. `dbc=` starts a new DBC section.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for adapter in &self.adapters {
+            match adapter {
+                AdapterChoice::Menu(name) => writeln!(out, "adapter={name}").unwrap(),
+                AdapterChoice::Args(args) => writeln!(out, "args={}", args.join("\t")).unwrap(),
+            }
+        }
+        if !self.log_column_widths.is_empty() {
+            writeln!(out, "log_widths={}", join(&self.log_column_widths)).unwrap();
+        }
+        for dbc in &self.dbcs {
+            writeln!(out, "dbc={}", dbc.path.display()).unwrap();
+            for mapping in &dbc.mappings {
+                writeln!(out, "map={mapping}").unwrap();
+            }
+            writeln!(out, "auto_match={}", dbc.auto_match).unwrap();
+            writeln!(out, "hide_inactive={}", dbc.hide_inactive).unwrap();
+            if !dbc.column_widths.is_empty() {
+                writeln!(out, "widths={}", join(&dbc.column_widths)).unwrap();
+            }
+            if let Some((col, descending)) = dbc.sort {
+                writeln!(
+                    out,
+                    "sort={col}:{}",
+                    if descending { "desc" } else { "asc" }
+                )
+                .unwrap();
+            }
+            writeln!(out, "chart_duration={}", dbc.chart_duration.as_secs_f64()).unwrap();
+            if let Some(channel) = dbc.channel {
+                writeln!(out, "channel={channel}").unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn from_text(text: &str) -> Result<Session> {
+        let mut session = Session::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            parse_line(&mut session, line)
+                .with_context(|| format!("line {}: {line}", number + 1))?;
+        }
+        Ok(session)
+    }
+
+    pub fn load(path: &Path) -> Result<Session> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read session {}", path.display()))?;
+        Session::from_text(&text)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+            .with_context(|| format!("Unable to write session {}", path.display()))
+    }
+}
+
+fn parse_line(session: &mut Session, line: &str) -> Result<()> {
+    let (key, value) = line.split_once('=').ok_or(anyhow!("expected key=value"))?;
+    if key == "adapter" {
+        session.adapters.push(AdapterChoice::Menu(value.into()));
+        return Ok(());
+    }
+    if key == "args" {
+        session.adapters.push(AdapterChoice::Args(
+            value.split('\t').map(|s| s.into()).collect(),
+        ));
+        return Ok(());
+    }
+    if key == "log_widths" {
+        session.log_column_widths = split(value)?;
+        return Ok(());
+    }
+    if key == "dbc" {
+        session.dbcs.push(DbcSession::new(value.into()));
+        return Ok(());
+    }
+    let dbc = session.dbcs.last_mut().ok_or(anyhow!("{key} before dbc"))?;
+    match key {
+        "map" => dbc.mappings.push(value.parse()?),
+        "auto_match" => dbc.auto_match = value.parse()?,
+        "hide_inactive" => dbc.hide_inactive = value.parse()?,
+        "widths" => dbc.column_widths = split(value)?,
+        "sort" => {
+            let (col, order) = value.split_once(':').ok_or(anyhow!("expected col:order"))?;
+            dbc.sort = Some((col.parse()?, order == "desc"));
+        }
+        "chart_duration" => {
+            dbc.chart_duration = Duration::try_from_secs_f64(value.parse()?)
+                .map_err(|_| anyhow!("invalid chart duration {value}"))?
+        }
+        "channel" => dbc.channel = Some(value.parse()?),
+        _ => return Err(anyhow!("unknown key {key}")),
+    }
+    Ok(())
+}
+
+fn join(values: &[u32]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split(value: &str) -> Result<Vec<u32>> {
+    Ok(value
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<_, _>>()?)
+}
+
+/// Per user settings directory.
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("APPDATA")
+        .or_else(|| std::env::var_os("HOME"))
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".j1939logger")
+}
+
+/// Session written on exit.
+pub fn last_session_path() -> PathBuf {
+    config_dir().join("last.session")
+}
+
+fn recent_path() -> PathBuf {
+    config_dir().join("recent.txt")
+}
+
+/// Recently opened DBC and session files, newest first.
+pub fn recent_files() -> Vec<PathBuf> {
+    fs::read_to_string(recent_path())
+        .unwrap_or_default()
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+pub fn add_recent_file(path: &Path) -> Result<()> {
+    let mut recent = recent_files();
+    recent.retain(|p| p != path);
+    recent.insert(0, path.into());
+    recent.truncate(MAX_RECENT);
+    let text: Vec<String> = recent.iter().map(|p| p.display().to_string()).collect();
+    fs::create_dir_all(config_dir())?;
+    fs::write(recent_path(), text.join("\n"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let session = Session {
+            adapters: vec![
+                AdapterChoice::Menu("SLCAN/\\/dev\\/ttyACM0/500k".into()),
+                AdapterChoice::Menu("SLCAN/\\/dev\\/ttyACM1/250k".into()),
+            ],
+            log_column_widths: vec![1200],
+            dbcs: vec![
+                DbcSession {
+                    path: "/tmp/a.dbc".into(),
+                    mappings: vec![
+                        AddressMapping::new(0xFE, 0x00),
+                        "FE:*@FEF1".parse().unwrap(),
+                    ],
+                    auto_match: true,
+                    hide_inactive: true,
+                    column_widths: vec![0, 40, 40, 300],
+                    sort: Some((3, true)),
+                    chart_duration: Duration::from_secs_f64(2.5),
+                    channel: Some(1),
+                },
+                DbcSession::new("/tmp/b.dbc".into()),
+            ],
+        };
+        assert_eq!(Session::from_text(&session.to_text()).unwrap(), session);
+
+        let args = Session {
+            adapters: vec![AdapterChoice::Args(vec!["slcan".into(), "--port".into()])],
+            ..Default::default()
+        };
+        assert_eq!(Session::from_text(&args.to_text()).unwrap(), args);
+    }
+
+    #[test]
+    fn reports_line() {
+        let err = Session::from_text("dbc=a.dbc\nmap=FE").unwrap_err();
+        assert!(format!("{err:#}").contains("line 2"));
+        for value in ["-1", "inf", "NaN"] {
+            let text = format!("dbc=a.dbc\nchart_duration={value}");
+            assert!(Session::from_text(&text).is_err());
+        }
+    }
+}
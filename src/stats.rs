@@ -0,0 +1,559 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use can_adapter::j1939::j1939_packet::J1939Packet;
+use fltk::{
+    enums::Font,
+    frame::Frame,
+    group::{Pack, PackType},
+    input::IntInput,
+    output::Output,
+    prelude::{GroupExt, InputExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use regex::Regex;
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+use timer::Timer;
+
+use crate::{
+    dbc_table::{DbcModel, DbcTables},
+    etp::is_etp,
+    fd::{contained_bits, fd_frame_bits, CONTAINED, FD, RAW},
+    packet_repo::{dbc_key, is_standard, pgn_of, pgn_text, sa, sa_text, PacketRepo},
+    tp::is_tp,
+};
+
+/// Bits on the wire for a data frame with a `PacketRepo` id and `dlc` data bytes, including
+/// the interframe space and an average bit stuffing estimate.
+pub fn frame_bits(id: u32, dlc: usize) -> f64 {
+    // SOF, 11 bit id, RTR, IDE, r0, DLC, CRC
+    let mut stuffed = 1 + 11 + 1 + 1 + 1 + 4 + 8 * dlc + 15;
+    if !is_standard(id) {
+        // SRR, the rest of the 29 bit id and r1
+        stuffed += 1 + 18 + 1;
+    }
+    // CRC delimiter, ACK, EOF, IFS
+    let fixed = 1 + 2 + 7 + 3;
+    // worst case is one stuff bit per 4 bits, random data averages about half that.
+    let stuffing = (stuffed - 1) as f64 / 4.0 / 2.0;
+    (stuffed + fixed) as f64 + stuffing
+}
+
+/// Bits for a message as seen in the log. Messages over 8 bytes are CAN FD frames, or were
+/// reassembled from a transport protocol session: one TP.CM/BAM and 7 bytes per TP.DT frame.
+/// Raw transport frames are counted in their reassembled message.
+pub fn message_bits(id: u32, len: usize, flags: u8) -> f64 {
+    if flags & RAW != 0 {
+        0.0
+    } else if flags & CONTAINED != 0 {
+        contained_bits(len)
+    } else if flags & FD != 0 {
+        fd_frame_bits(id, len)
+    } else if len <= 8 {
+        frame_bits(id, len)
+    } else {
+        frame_bits(id, 8) * (1 + len.div_ceil(7)) as f64
+    }
+}
+
+/// Percentage of the bus used by messages of (id, length, flags) over `window`.
+pub fn bus_load(
+    messages: impl Iterator<Item = (u32, usize, u8)>,
+    window: Duration,
+    bitrate: u32,
+) -> f64 {
+    let bits: f64 = messages
+        .map(|(id, len, flags)| message_bits(id, len, flags))
+        .sum();
+    100.0 * bits / (bitrate as f64 * window.as_secs_f64())
+}
+
+/// Timing of one id over a window.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timing {
+    pub count: usize,
+    /// messages per second
+    pub rate: f64,
+    /// mean period
+    pub period: Duration,
+    /// standard deviation of the period
+    pub jitter: Duration,
+    /// longest gap
+    pub max_gap: Duration,
+    /// gaps longer than 1.5 times the expected period, as whole missed periods
+    pub missed: usize,
+}
+
+/// Timing statistics for chronological packet times, logged over `window`.
+pub fn timing(times: &[Duration], window: Duration, expected: Option<Duration>) -> Timing {
+    let periods: Vec<f64> = times
+        .windows(2)
+        .map(|w| w[1].saturating_sub(w[0]).as_secs_f64())
+        .collect();
+    let mean = if periods.is_empty() {
+        0.0
+    } else {
+        periods.iter().sum::<f64>() / periods.len() as f64
+    };
+    let variance = if periods.is_empty() {
+        0.0
+    } else {
+        periods.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / periods.len() as f64
+    };
+    let missed = expected.filter(|e| !e.is_zero()).map_or(0, |e| {
+        let e = e.as_secs_f64();
+        periods
+            .iter()
+            .filter(|p| **p > 1.5 * e)
+            .map(|p| (p / e).round() as usize - 1)
+            .sum()
+    });
+    Timing {
+        count: times.len(),
+        rate: if window.is_zero() {
+            0.0
+        } else {
+            times.len() as f64 / window.as_secs_f64()
+        },
+        period: Duration::from_secs_f64(mean),
+        jitter: Duration::from_secs_f64(variance.sqrt()),
+        max_gap: Duration::from_secs_f64(periods.iter().copied().fold(0.0, f64::max)),
+        missed,
+    }
+}
+
+/// `GenMsgCycleTime` attributes from DBC text, by id without priority.
+pub fn dbc_cycle_times(text: &str) -> HashMap<u32, Duration> {
+    let re = Regex::new(r#"BA_\s+"GenMsgCycleTime"\s+BO_\s+(\d+)\s+(\d+)\s*;"#).unwrap();
+    re.captures_iter(text)
+        .filter_map(|c| {
+            let id = dbc_key(c[1].parse::<u32>().ok()?);
+            let ms = c[2].parse::<u64>().ok()?;
+            Some((id, Duration::from_millis(ms)))
+        })
+        .collect()
+}
+
+/// Cycle times of the messages a DBC window shows, by their ids after address mapping.
+pub fn mapped_cycle_times(
+    cycle_times: &HashMap<u32, Duration>,
+    model: &DbcModel,
+) -> HashMap<u32, Duration> {
+    model
+        .dbc_pgns()
+        .iter()
+        .filter_map(|pgn| Some((pgn.id, *cycle_times.get(&dbc_key(pgn.id))?)))
+        .flat_map(|(id, cycle)| {
+            model
+                .mapped_ids(id)
+                .into_iter()
+                .map(move |id| (dbc_key(id), cycle))
+        })
+        .collect()
+}
+
+/// Cycle times from all open DBC windows.
+pub fn open_cycle_times(dbc_tables: &DbcTables) -> HashMap<u32, Duration> {
+    let tables = dbc_tables.lock().unwrap().clone();
+    tables
+        .iter()
+        .flat_map(|t| {
+            let cycle_times = read_cycle_times(&t.path);
+            let table = t.table.lock().unwrap();
+            let model = table.model.lock().unwrap();
+            mapped_cycle_times(&cycle_times, &model)
+        })
+        .collect()
+}
+
+fn read_cycle_times(path: &Path) -> HashMap<u32, Duration> {
+    std::fs::read(path)
+        .map(|bytes| dbc_cycle_times(&String::from_utf8_lossy(&bytes)))
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug)]
+struct Row {
+    id: u32,
+    timing: Timing,
+    expected: Option<Duration>,
+}
+
+/// Live per id message rates.
+pub struct StatsModel {
+    packets: Arc<RwLock<PacketRepo>>,
+    rows: Vec<Row>,
+    /// id -> DBC cycle time
+    cycle_times: HashMap<u32, Duration>,
+    pub window: Duration,
+    pub bitrate: u32,
+    /// channel -> bus load in percent over the last second
+    pub load: BTreeMap<u8, f64>,
+    /// channel -> peak load since opened
+    pub peak_load: BTreeMap<u8, f64>,
+    sort: Option<(usize, Order)>,
+}
+
+impl StatsModel {
+    pub fn new(
+        packets: Arc<RwLock<PacketRepo>>,
+        cycle_times: HashMap<u32, Duration>,
+    ) -> StatsModel {
+        StatsModel {
+            packets,
+            rows: Vec::new(),
+            cycle_times,
+            window: Duration::from_secs(10),
+            bitrate: 250_000,
+            load: BTreeMap::new(),
+            peak_load: BTreeMap::new(),
+            sort: None,
+        }
+    }
+
+    /// Recalculate from the repo.
+    pub fn update(&mut self) {
+        let repo = self.packets.read().unwrap();
+        // packets from several channels are not in time order
+        let packets = repo.packets();
+        let time = |p: &J1939Packet| p.time().unwrap_or_default();
+        let end = packets.iter().map(time).max().unwrap_or_default();
+        let since = |window: Duration| end.saturating_sub(window);
+
+        let second = since(Duration::from_secs(1));
+        let mut messages: BTreeMap<u8, Vec<(u32, usize, u8)>> = self
+            .peak_load
+            .keys()
+            .map(|channel| (*channel, Vec::new()))
+            .collect();
+        for ((p, flags), channel) in packets.iter().zip(repo.flags()).zip(repo.channels()) {
+            if time(p) >= second {
+                messages
+                    .entry(*channel)
+                    .or_default()
+                    .push((p.id(), p.payload.len(), *flags));
+            }
+        }
+        self.load = messages
+            .into_iter()
+            .map(|(channel, m)| {
+                let load = bus_load(m.into_iter(), Duration::from_secs(1), self.bitrate);
+                (channel, load)
+            })
+            .collect();
+        for (channel, load) in &self.load {
+            let peak = self.peak_load.entry(*channel).or_default();
+            *peak = peak.max(*load);
+        }
+
+        let start = since(self.window);
+        // a log shorter than the window only covers its own span
+        let first = packets.iter().map(time).min().unwrap_or(end);
+        let covered = end.saturating_sub(first.max(start));
+        // raw transport frames are counted in their reassembled messages
+        let mut ids: Vec<u32> = repo
+            .ids()
+            .filter(|id| !is_tp(*id) && !is_etp(*id))
+            .collect();
+        ids.sort();
+        self.rows = ids
+            .into_iter()
+            .map(|id| {
+                let packets = repo.get_for(id).map(|v| v.as_slice()).unwrap_or_default();
+                let mut times: Vec<Duration> =
+                    packets.iter().map(time).filter(|t| *t >= start).collect();
+                times.sort();
+                let expected = self.cycle_times.get(&id).copied();
+                Row {
+                    id,
+                    timing: timing(&times, covered, expected),
+                    expected,
+                }
+            })
+            .collect();
+        drop(repo);
+        if let Some((col, order)) = self.sort.take() {
+            self.sort(col, order);
+        }
+    }
+
+    /// Load and peak of each channel.
+    pub fn load_text(&self) -> String {
+        let loads: Vec<String> = self
+            .load
+            .iter()
+            .map(|(channel, load)| {
+                let peak = self.peak_load.get(channel).copied().unwrap_or_default();
+                let text = format!("{load:0.1}% (peak {peak:0.1}%)");
+                if self.load.len() == 1 {
+                    text
+                } else {
+                    format!("ch{channel} {text}")
+                }
+            })
+            .collect();
+        loads.join(", ")
+    }
+}
+
+const COLUMNS: [&str; 9] = [
+    "PGN",
+    "SA",
+    "Count",
+    "Rate (/s)",
+    "Period (ms)",
+    "Jitter (ms)",
+    "Max Gap (ms)",
+    "Expected (ms)",
+    "Missed",
+];
+
+fn ms(d: Duration) -> String {
+    format!("{:0.1}", d.as_secs_f64() * 1000.0)
+}
+
+impl SimpleModel for StatsModel {
+    fn row_count(&mut self) -> usize {
+        self.rows.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 | 1 => 50,
+            _ => 90,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let row = self.rows.get(row as usize)?;
+        let t = &row.timing;
+        match col {
+            0 => Some(pgn_text(row.id)),
+            1 => Some(sa_text(row.id)),
+            2 => Some(t.count.to_string()),
+            3 => Some(format!("{:0.1}", t.rate)),
+            4 => Some(ms(t.period)),
+            5 => Some(ms(t.jitter)),
+            6 => Some(ms(t.max_gap)),
+            7 => Some(row.expected.map(ms).unwrap_or_default()),
+            8 => Some(t.missed.to_string()),
+            _ => None,
+        }
+    }
+
+    fn sort(&mut self, col: usize, order: Order) {
+        let key = |r: &Row| -> (f64, u32) {
+            let t = &r.timing;
+            match col {
+                0 => (pgn_of(r.id) as f64, r.id),
+                1 => (sa(r.id) as f64, r.id),
+                2 => (t.count as f64, r.id),
+                3 => (t.rate, r.id),
+                4 => (t.period.as_secs_f64(), r.id),
+                5 => (t.jitter.as_secs_f64(), r.id),
+                6 => (t.max_gap.as_secs_f64(), r.id),
+                7 => (r.expected.unwrap_or_default().as_secs_f64(), r.id),
+                _ => (t.missed as f64, r.id),
+            }
+        };
+        match order {
+            Order::None => {
+                self.rows.sort_by_key(|r| r.id);
+                self.sort = None;
+                return;
+            }
+            Order::Ascending => self
+                .rows
+                .sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap()),
+            Order::Descending => self
+                .rows
+                .sort_by(|a, b| key(b).partial_cmp(&key(a)).unwrap()),
+        }
+        self.sort = Some((col, order));
+    }
+}
+
+/// Bus load and message rates, updated every second.
+pub fn stats_window(packets: Arc<RwLock<PacketRepo>>, dbc_tables: DbcTables, timer: &Timer) {
+    let mut wind = Window::default()
+        .with_size(800, 500)
+        .with_label("Bus Statistics");
+    let pack = Pack::default_fill();
+
+    let mut hbox = Pack::default()
+        .with_size(100, 20)
+        .with_type(PackType::Horizontal);
+    hbox.set_spacing(4);
+    Frame::default().with_size(80, 20).with_label("Bitrate");
+    let mut bitrate = IntInput::default().with_size(80, 20);
+    bitrate.set_value("250000");
+    Frame::default().with_size(80, 20).with_label("Window (s)");
+    let mut window = IntInput::default().with_size(40, 20);
+    window.set_value("10");
+    Frame::default().with_size(60, 20).with_label("Bus Load");
+    let mut load = Output::default().with_size(300, 20);
+    load.set_tooltip(
+        "Last second of each channel, with estimated bit stuffing. Peak since opened.",
+    );
+    hbox.end();
+
+    let model = StatsModel::new(packets, open_cycle_times(&dbc_tables));
+    let mut table = JoeTable::new(model);
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+
+    pack.resizable(&*table);
+    pack.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    table.redraw_on(timer, chrono::Duration::milliseconds(500));
+
+    let model = table.model.clone();
+    timer
+        .schedule_repeating(chrono::Duration::seconds(1), move || {
+            if !wind.shown() {
+                return;
+            }
+            let mut model = model.lock().unwrap();
+            if let Ok(b) = bitrate.value().parse::<u32>() {
+                model.bitrate = b.max(1);
+            }
+            if let Ok(w) = window.value().parse::<u64>() {
+                model.window = Duration::from_secs(w.max(1));
+            }
+            model.update();
+            load.set_value(&model.load_text());
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{address_map::AddressMapping, dbc, packet_repo::frame_id};
+
+    #[test]
+    fn frame_size() {
+        // 8 byte extended frame is 131 bits with IFS, before stuffing
+        let id = 0x18FEF100;
+        assert_eq!(frame_bits(id, 8), 131.0 + 117.0 / 8.0);
+        // 8 byte standard frame is 111 bits
+        let standard = frame_id(0x123);
+        assert_eq!(frame_bits(standard, 8), 111.0 + 97.0 / 8.0);
+        assert!(fd_frame_bits(standard, 64) < fd_frame_bits(id, 64));
+        assert_eq!(message_bits(id, 9, 0), frame_bits(id, 8) * 3.0);
+        assert_eq!(message_bits(id, 9, FD), fd_frame_bits(id, 12));
+        // a multi-PG frame is one frame, whatever it carries
+        assert_eq!(message_bits(id, 8, FD | CONTAINED), contained_bits(8));
+        assert!(message_bits(id, 8, FD | CONTAINED) < message_bits(id, 8, FD));
+        assert_eq!(message_bits(id, 8, RAW), 0.0);
+        // 1000 full frames a second at 250k is about 58%
+        let load = bus_load(
+            std::iter::repeat_n((id, 8, 0), 1000),
+            Duration::from_secs(1),
+            250_000,
+        );
+        assert!((load - 58.25).abs() < 0.01, "{load}");
+    }
+
+    #[test]
+    fn periodic_timing() {
+        let ms = Duration::from_millis;
+        let times: Vec<Duration> = [0, 100, 200, 300, 600, 700].into_iter().map(ms).collect();
+        let t = timing(&times, Duration::from_secs(1), Some(ms(100)));
+        assert_eq!(t.count, 6);
+        assert_eq!(t.max_gap, ms(300));
+        assert_eq!(t.missed, 2);
+        assert!((t.period.as_secs_f64() - 0.14).abs() < 1e-9);
+        assert_eq!(timing(&[], Duration::from_secs(1), None), Timing::default());
+    }
+
+    #[test]
+    fn cycle_times() {
+        let dbc = r#"
+BA_DEF_ BO_  "GenMsgCycleTime" INT 0 65535;
+BA_ "GenMsgCycleTime" BO_ 2364540158 100;
+BA_ "GenMsgCycleTime" BO_ 2566844158 1000;
+"#;
+        let c = dbc_cycle_times(dbc);
+        assert_eq!(c.len(), 2);
+        assert_eq!(c[&(0x0CF004FE & 0x3FFFFFF)], Duration::from_millis(100));
+        assert_eq!(c[&(0x18FEEEFE & 0x3FFFFFF)], Duration::from_secs(1));
+    }
+
+    #[test]
+    fn mapped_cycle() {
+        let mut model = DbcModel::new(dbc::load_test_messages(dbc::TEST_EEC1), Default::default());
+        model.set_mappings(vec![AddressMapping::new(0xFE, 0x00)]);
+        // ESSP is not in the DBC
+        let cycle = HashMap::from([
+            (dbc_key(2364540158), Duration::from_millis(100)),
+            (dbc_key(2566844158), Duration::from_secs(1)),
+        ]);
+        assert_eq!(
+            mapped_cycle_times(&cycle, &model),
+            HashMap::from([(dbc_key(0x8CF00400), Duration::from_millis(100))])
+        );
+    }
+
+    #[test]
+    fn channel_load() {
+        let packets: Arc<RwLock<PacketRepo>> = Default::default();
+        {
+            let mut repo = packets.write().unwrap();
+            // channel 1 is read behind channel 0
+            for i in 0..500u64 {
+                for (channel, ms) in [(0, 1000 + 2 * i), (1, 4 * i)] {
+                    let time = Some(Duration::from_millis(ms));
+                    repo.push_from(channel, J1939Packet::new(time, 0, 0x18FEF100, &[0; 8]), 0);
+                }
+            }
+        }
+        let mut model = StatsModel::new(packets, HashMap::new());
+        model.update();
+        let load = |n| {
+            bus_load(
+                std::iter::repeat_n((0x18FEF100, 8, 0), n),
+                Duration::from_secs(1),
+                250_000,
+            )
+        };
+        // from 0.998s, 500 frames on channel 0 and 250 on channel 1
+        assert_eq!(model.load, BTreeMap::from([(0, load(500)), (1, load(250))]));
+        assert!(model.load_text().starts_with("ch0 "));
+    }
+
+    #[test]
+    fn short_log() {
+        let packets: Arc<RwLock<PacketRepo>> = Default::default();
+        for ms in (0..3000).step_by(100) {
+            let time = Some(Duration::from_millis(ms));
+            packets
+                .write()
+                .unwrap()
+                .push(J1939Packet::new(time, 0, 0x18FEF100, &[0; 8]));
+        }
+        let mut model = StatsModel::new(packets, HashMap::new());
+        model.update();
+        // 30 messages over 2.9 seconds, not the 10 second window
+        let rate = model.rows[0].timing.rate;
+        assert!((rate - 30.0 / 2.9).abs() < 1e-9, "{rate}");
+    }
+}
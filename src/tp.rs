@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use fltk::{
+    button::Button,
+    enums::Font,
+    group::{Flex, PackType},
+    prelude::{GroupExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+use timer::Timer;
+
+use crate::packet_repo::{da, pgn_of, sa};
+
+/// TP.CM, connection management
+pub const TP_CM: u32 = 0xEC00;
+/// TP.DT, data transfer
+pub const TP_DT: u32 = 0xEB00;
+
+const RTS: u8 = 16;
+const CTS: u8 = 17;
+const EOM_ACK: u8 = 19;
+const BAM: u8 = 32;
+const ABORT: u8 = 255;
+
+/// J1939-21 T1, time between BAM data packets
+const BAM_TIMEOUT: Duration = Duration::from_millis(750);
+/// J1939-21 T2/T3, time waiting for data or a CTS
+const RTS_TIMEOUT: Duration = Duration::from_millis(1250);
+
+/// TP.CM and TP.DT frames.
+pub fn is_tp(id: u32) -> bool {
+    matches!(pgn_of(id), TP_CM | TP_DT)
+}
+
+/// J1939-21 connection abort reason.
+pub fn abort_reason(reason: u8) -> &'static str {
+    match reason {
+        1 => "Already in a session",
+        2 => "Resources needed elsewhere",
+        3 => "Timeout",
+        4 => "CTS while data transfer in progress",
+        5 => "Maximum retransmit requests reached",
+        6 => "Unexpected data transfer packet",
+        7 => "Bad sequence number",
+        8 => "Duplicate sequence number",
+        9 => "Message size greater than 1785 bytes",
+        _ => "Other",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Bam,
+    RtsCts,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Open,
+    Complete,
+    /// reason and the address that aborted
+    Aborted(u8, u8),
+    TimedOut,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Open => write!(f, "Open"),
+            State::Complete => write!(f, "Complete"),
+            State::Aborted(reason, by) => {
+                write!(f, "Aborted by {by:02X}: {reason} {}", abort_reason(*reason))
+            }
+            State::TimedOut => write!(f, "Timed out"),
+        }
+    }
+}
+
+/// One BAM or RTS/CTS transfer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub kind: Kind,
+    pub source: u8,
+    pub destination: u8,
+    pub pgn: u32,
+    pub size: usize,
+    /// packets announced
+    pub packets: u8,
+    /// data packets received in sequence
+    pub received: u8,
+    /// (number of packets, next sequence) for each CTS
+    pub cts: Vec<(u8, u8)>,
+    /// data packets out of sequence
+    pub sequence_errors: usize,
+    pub start: Duration,
+    /// time of the last frame
+    pub last: Duration,
+    pub state: State,
+}
+
+impl Session {
+    fn timeout(&self) -> Duration {
+        match self.kind {
+            Kind::Bam => BAM_TIMEOUT,
+            Kind::RtsCts => RTS_TIMEOUT,
+        }
+    }
+}
+
+/// Follows transport sessions from raw TP.CM and TP.DT frames.
+#[derive(Clone, Debug, Default)]
+pub struct TpTracker {
+    sessions: Vec<Session>,
+    /// (source, destination) -> index of the open session
+    open: HashMap<(u8, u8), usize>,
+    /// add raw TP frames to the log
+    pub show_raw: bool,
+    /// time of the last frame and when it was observed
+    last: Option<(Duration, Instant)>,
+}
+
+impl TpTracker {
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+        self.open.clear();
+        self.last = None;
+    }
+
+    /// Time out open sessions that have been idle too long at `now`.
+    pub fn expire(&mut self, now: Duration) {
+        let sessions = &mut self.sessions;
+        self.open.retain(|_, i| {
+            let s = &mut sessions[*i];
+            let expired = now.saturating_sub(s.last) > s.timeout();
+            if expired {
+                s.state = State::TimedOut;
+            }
+            !expired
+        });
+    }
+
+    /// Time out open sessions while no frames arrive, going by the wall clock since the last
+    /// frame.
+    pub fn expire_idle(&mut self) {
+        if let Some((time, at)) = self.last {
+            self.expire(time + at.elapsed());
+        }
+    }
+
+    fn close(&mut self, key: (u8, u8), state: State, time: Duration) {
+        if let Some(i) = self.open.remove(&key) {
+            self.sessions[i].state = state;
+            self.sessions[i].last = time;
+        }
+    }
+
+    /// Check one frame. Frames other than TP.CM and TP.DT only advance the time.
+    pub fn observe(&mut self, id: u32, time: Duration, data: &[u8]) {
+        self.expire(time);
+        self.last = Some((time, Instant::now()));
+        let (source, destination) = (sa(id), da(id));
+        match pgn_of(id) {
+            TP_CM if data.len() >= 8 => match data[0] {
+                RTS | BAM => {
+                    // a new announcement replaces any open session between the pair
+                    self.close((source, destination), State::Aborted(1, source), time);
+                    self.open.insert((source, destination), self.sessions.len());
+                    self.sessions.push(Session {
+                        kind: if data[0] == BAM {
+                            Kind::Bam
+                        } else {
+                            Kind::RtsCts
+                        },
+                        source,
+                        destination,
+                        pgn: u32::from_le_bytes([data[5], data[6], data[7], 0]),
+                        size: u16::from_le_bytes([data[1], data[2]]) as usize,
+                        packets: data[3],
+                        received: 0,
+                        cts: Vec::new(),
+                        sequence_errors: 0,
+                        start: time,
+                        last: time,
+                        state: State::Open,
+                    });
+                }
+                // sent by the receiver, so the session is the other way around
+                CTS => {
+                    if let Some(i) = self.open.get(&(destination, source)) {
+                        let s = &mut self.sessions[*i];
+                        s.cts.push((data[1], data[2]));
+                        s.last = time;
+                    }
+                }
+                EOM_ACK => self.close((destination, source), State::Complete, time),
+                ABORT => {
+                    let state = State::Aborted(data[1], source);
+                    self.close((source, destination), state, time);
+                    self.close((destination, source), state, time);
+                }
+                _ => (),
+            },
+            TP_DT if !data.is_empty() => {
+                let Some(i) = self.open.get(&(source, destination)).copied() else {
+                    return;
+                };
+                let s = &mut self.sessions[i];
+                s.last = time;
+                if data[0] == s.received.wrapping_add(1) {
+                    s.received = s.received.wrapping_add(1);
+                } else {
+                    s.sequence_errors += 1;
+                }
+                if s.kind == Kind::Bam && s.received == s.packets {
+                    self.close((source, destination), State::Complete, time);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+const COLUMNS: [&str; 11] = [
+    "Start", "Type", "SA", "DA", "PGN", "Size", "Packets", "Received", "CTS", "Errors", "State",
+];
+
+/// Transport sessions, oldest first.
+pub struct TpModel {
+    tracker: Arc<Mutex<TpTracker>>,
+}
+
+impl SimpleModel for TpModel {
+    fn row_count(&mut self) -> usize {
+        self.tracker.lock().unwrap().sessions().len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 80,
+            8 => 120,
+            10 => 300,
+            _ => 50,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let tracker = self.tracker.lock().unwrap();
+        let s = tracker.sessions().get(row as usize)?;
+        match col {
+            0 => Some(format!("{:.3}", s.start.as_secs_f64())),
+            1 => Some(
+                match s.kind {
+                    Kind::Bam => "BAM",
+                    Kind::RtsCts => "RTS",
+                }
+                .into(),
+            ),
+            2 => Some(format!("{:02X}", s.source)),
+            3 => Some(format!("{:02X}", s.destination)),
+            4 => Some(format!("{:04X}", s.pgn)),
+            5 => Some(s.size.to_string()),
+            6 => Some(s.packets.to_string()),
+            7 => Some(s.received.to_string()),
+            8 => Some(
+                s.cts
+                    .iter()
+                    .map(|(count, next)| format!("{next}+{count}"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            9 => Some(s.sequence_errors.to_string()),
+            10 => Some(s.state.to_string()),
+            _ => None,
+        }
+    }
+
+    fn sort(&mut self, _col: usize, _order: Order) {
+        // sorting not supported
+    }
+}
+
+/// Every BAM and RTS/CTS session seen on the bus.
+pub fn tp_window(tracker: Arc<Mutex<TpTracker>>, timer: &Timer) {
+    let mut wind = Window::default()
+        .with_size(900, 400)
+        .with_label("Transport Sessions");
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+
+    let mut table = JoeTable::new(TpModel {
+        tracker: tracker.clone(),
+    });
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+
+    let buttons = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut clear = Button::default().with_label("Clear");
+    {
+        let tracker = tracker.clone();
+        clear.set_callback(move |_| tracker.lock().unwrap().clear());
+    }
+    buttons.end();
+    flex.fixed(&buttons, 25);
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    table.redraw_on(timer, chrono::Duration::milliseconds(500));
+
+    timer
+        .schedule_repeating(chrono::Duration::milliseconds(500), move || {
+            if wind.shown() {
+                tracker.lock().unwrap().expire_idle();
+            }
+        })
+        .ignore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    const DM1: [u8; 3] = [0xCA, 0xFE, 0x00];
+
+    #[test]
+    fn bam() {
+        let mut tp = TpTracker::default();
+        tp.observe(
+            0x1CECFF00,
+            ms(0),
+            &[BAM, 20, 0, 3, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        for seq in 1..=3 {
+            tp.observe(0x1CEBFF00, ms(seq as u64 * 50), &[seq, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        let s = &tp.sessions()[0];
+        assert_eq!(s.kind, Kind::Bam);
+        assert_eq!(
+            (s.source, s.destination, s.pgn, s.size),
+            (0, 0xFF, 0xFECA, 20)
+        );
+        assert_eq!(s.state, State::Complete);
+    }
+
+    #[test]
+    fn rts_cts() {
+        let mut tp = TpTracker::default();
+        tp.observe(
+            0x1CECF900,
+            ms(0),
+            &[RTS, 9, 0, 2, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        tp.observe(
+            0x1CEC00F9,
+            ms(10),
+            &[CTS, 2, 1, 0xFF, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        tp.observe(0x1CEBF900, ms(20), &[1, 0, 0, 0, 0, 0, 0, 0]);
+        // out of sequence
+        tp.observe(0x1CEBF900, ms(30), &[3, 0, 0, 0, 0, 0, 0, 0]);
+        tp.observe(0x1CEBF900, ms(40), &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(tp.sessions()[0].state, State::Open);
+        tp.observe(
+            0x1CEC00F9,
+            ms(50),
+            &[EOM_ACK, 9, 0, 2, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        let s = &tp.sessions()[0];
+        assert_eq!(s.cts, [(2, 1)]);
+        assert_eq!((s.received, s.sequence_errors), (2, 1));
+        assert_eq!(s.state, State::Complete);
+    }
+
+    #[test]
+    fn abort_and_timeout() {
+        let mut tp = TpTracker::default();
+        tp.observe(
+            0x1CECF900,
+            ms(0),
+            &[RTS, 9, 0, 2, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        tp.observe(
+            0x1CEC00F9,
+            ms(10),
+            &[ABORT, 2, 0xFF, 0xFF, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        assert_eq!(tp.sessions()[0].state, State::Aborted(2, 0xF9));
+        assert_eq!(
+            tp.sessions()[0].state.to_string(),
+            "Aborted by F9: 2 Resources needed elsewhere"
+        );
+
+        tp.observe(
+            0x1CECFF00,
+            ms(100),
+            &[BAM, 20, 0, 3, 0xFF, DM1[0], DM1[1], DM1[2]],
+        );
+        tp.observe(0x18FEF100, ms(900), &[0; 8]);
+        assert_eq!(tp.sessions()[1].state, State::TimedOut);
+        assert!(!is_tp(0x18FEF100));
+        assert!(is_tp(0x1CEBFF00));
+    }
+}
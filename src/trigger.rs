@@ -0,0 +1,712 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use canparse::pgn::SpnDefinition;
+use chrono::{DateTime, Local};
+use fltk::{
+    browser::HoldBrowser,
+    button::{Button, CheckButton},
+    dialog::alert_default,
+    enums::Align,
+    frame::Frame,
+    group::{Flex, PackType},
+    input::{FloatInput, Input},
+    menu::Choice,
+    prelude::{BrowserExt, ButtonExt, GroupExt, InputExt, MenuExt, WidgetExt, WindowExt},
+    window::Window,
+};
+use timer::Timer;
+
+use crate::{
+    dbc,
+    dbc_table::DbcTables,
+    packet_repo::{dbc_key, pgn_of, repo_key, sa, write_packets_between, PacketRepo},
+    session::config_dir,
+};
+
+/// DM1 active diagnostic trouble codes
+const DM1: u32 = 0xFECA;
+
+/// What to look for in incoming packets.
+#[derive(Clone, Debug)]
+pub enum Condition {
+    /// Any packet with this PGN and/or SA.
+    Id { pgn: Option<u32>, sa: Option<u8> },
+    /// Packet with this PGN and/or SA where `payload & mask == value & mask`.
+    Payload {
+        pgn: Option<u32>,
+        sa: Option<u8>,
+        mask: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Decoded DBC signal crosses the threshold.
+    Signal {
+        /// id without priority
+        id: u32,
+        spn: SpnDefinition,
+        above: bool,
+        threshold: f64,
+    },
+    /// DM1 lamp status changed for any source address.
+    Dm1Lamps,
+}
+
+impl Condition {
+    /// Payload condition, with a value for each byte of the mask.
+    pub fn payload(
+        pgn: Option<u32>,
+        sa: Option<u8>,
+        mask: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<Condition, Error> {
+        if mask.len() != value.len() {
+            return Err(anyhow!(
+                "The mask has {} bytes and the value {}.",
+                mask.len(),
+                value.len()
+            ));
+        }
+        Ok(Condition::Payload {
+            pgn,
+            sa,
+            mask,
+            value,
+        })
+    }
+}
+
+fn id_matches(pgn: &Option<u32>, sa_filter: &Option<u8>, id: u32) -> bool {
+    pgn.is_none_or(|p| p == pgn_of(id)) && sa_filter.is_none_or(|s| s == sa(id))
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |v: &[u8]| v.iter().map(|b| format!("{b:02X}")).collect::<String>();
+        let pgn_sa = |pgn: &Option<u32>, sa: &Option<u8>| {
+            format!(
+                "PGN {} SA {}",
+                pgn.map_or("*".to_string(), |p| format!("{p:04X}")),
+                sa.map_or("*".to_string(), |s| format!("{s:02X}"))
+            )
+        };
+        match self {
+            Condition::Id { pgn, sa } => write!(f, "{}", pgn_sa(pgn, sa)),
+            Condition::Payload {
+                pgn,
+                sa,
+                mask,
+                value,
+            } => write!(f, "{} & {} == {}", pgn_sa(pgn, sa), hex(mask), hex(value)),
+            Condition::Signal {
+                spn,
+                above,
+                threshold,
+                ..
+            } => write!(
+                f,
+                "{} {} {threshold}",
+                spn.name,
+                if *above { ">" } else { "<" }
+            ),
+            Condition::Dm1Lamps => write!(f, "DM1 lamp change"),
+        }
+    }
+}
+
+/// Source of `Trigger::id`.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A condition and how much to capture around it.
+pub struct Trigger {
+    /// stays the same when other triggers are removed
+    id: usize,
+    pub name: String,
+    pub condition: Condition,
+    /// time before the trigger to save
+    pub pre: Duration,
+    /// time after the trigger to save
+    pub post: Duration,
+    /// re-arm automatically after the capture is saved
+    pub auto_rearm: bool,
+    pub armed: bool,
+    /// SA -> last DM1 lamp byte
+    lamps: HashMap<u8, u8>,
+    /// signal was beyond the threshold on the previous packet
+    beyond: bool,
+}
+
+impl Trigger {
+    pub fn new(name: String, condition: Condition, pre: Duration, post: Duration) -> Trigger {
+        Trigger {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            condition,
+            pre,
+            post,
+            auto_rearm: false,
+            armed: true,
+            lamps: HashMap::new(),
+            beyond: false,
+        }
+    }
+
+    /// Check a packet. State is tracked even when disarmed so that arming doesn't fire on
+    /// stale history.
+    fn check(&mut self, id: u32, payload: &[u8]) -> bool {
+        let hit = match &self.condition {
+            Condition::Id { pgn, sa } => id_matches(pgn, sa, id),
+            Condition::Payload {
+                pgn,
+                sa,
+                mask,
+                value,
+            } => {
+                id_matches(pgn, sa, id)
+                    && payload.len() >= mask.len()
+                    && mask
+                        .iter()
+                        .zip(value)
+                        .zip(payload)
+                        .all(|((m, v), p)| p & m == v & m)
+            }
+            Condition::Signal {
+                id: signal_id,
+                spn,
+                above,
+                threshold,
+            } => {
+                if repo_key(id) != *signal_id {
+                    return false;
+                }
+                let Some(value) = dbc::decode(spn, payload) else {
+                    return false;
+                };
+                let beyond = if *above {
+                    value > *threshold
+                } else {
+                    value < *threshold
+                };
+                let crossed = beyond && !self.beyond;
+                self.beyond = beyond;
+                crossed
+            }
+            Condition::Dm1Lamps => {
+                if pgn_of(id) != DM1 || payload.is_empty() {
+                    return false;
+                }
+                let previous = self.lamps.insert(sa(id), payload[0]);
+                previous.is_some_and(|p| p != payload[0])
+            }
+        };
+        hit && self.armed
+    }
+}
+
+/// A fired trigger.
+pub struct Capture {
+    pub trigger: String,
+    /// packet time of the trigger
+    pub time: Duration,
+    pub wall: DateTime<Local>,
+    start: Duration,
+    end: Duration,
+    /// `Trigger::id` to re-arm after saving
+    rearm: Option<usize>,
+    /// file once saved, or the reason it wasn't
+    pub result: Option<Result<PathBuf, String>>,
+}
+
+impl Display for Capture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:.3}s {}: ",
+            self.wall.format("%H:%M:%S"),
+            self.time.as_secs_f64(),
+            self.trigger
+        )?;
+        match &self.result {
+            None => write!(f, "capturing..."),
+            Some(Ok(path)) => write!(f, "{}", path.display()),
+            Some(Err(err)) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// All triggers and their history.
+pub struct Triggers {
+    pub triggers: Vec<Trigger>,
+    pub history: Vec<Capture>,
+    /// where captures are saved
+    pub directory: PathBuf,
+    /// index of the first capture not saved yet
+    unsaved: usize,
+    /// time of the last packet checked, and when it was checked
+    last: Option<(Duration, Instant)>,
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Triggers {
+            triggers: Vec::new(),
+            history: Vec::new(),
+            directory: config_dir().join("captures"),
+            unsaved: 0,
+            last: None,
+        }
+    }
+}
+
+impl Triggers {
+    /// Forget the history and what the triggers saw, when the log is cleared. Triggers waiting
+    /// on a dropped capture are re-armed if they would have been after saving it.
+    pub fn clear(&mut self) {
+        self.unsaved = 0;
+        self.last = None;
+        for capture in std::mem::take(&mut self.history) {
+            if capture.result.is_none() {
+                self.rearm(capture.rearm);
+            }
+        }
+        for t in &mut self.triggers {
+            t.lamps.clear();
+            t.beyond = false;
+        }
+    }
+
+    /// Check a packet before it is pushed to the repo.
+    pub fn check(&mut self, id: u32, time: Duration, payload: &[u8]) {
+        self.last = Some((time, Instant::now()));
+        for trigger in &mut self.triggers {
+            if trigger.check(id, payload) {
+                trigger.armed = false;
+                self.history.push(Capture {
+                    trigger: trigger.name.clone(),
+                    time,
+                    wall: Local::now(),
+                    start: time.saturating_sub(trigger.pre),
+                    end: time + trigger.post,
+                    rearm: trigger.auto_rearm.then_some(trigger.id),
+                    result: None,
+                });
+            }
+        }
+    }
+
+    /// Arm the trigger with `Trigger::id` `id`, if it wasn't removed.
+    fn rearm(&mut self, id: Option<usize>) {
+        if let Some(t) = self.triggers.iter_mut().find(|t| Some(t.id) == id) {
+            t.armed = true;
+        }
+    }
+
+    /// Save captures whose post trigger window has passed.
+    pub fn save_due(&mut self, repo: &PacketRepo) {
+        self.save_until(repo, repo.last_time());
+    }
+
+    /// Save captures whose post trigger window passed while no packets arrived, going by the
+    /// wall clock since the last packet.
+    pub fn save_idle(&mut self, repo: &PacketRepo) {
+        if let Some((time, at)) = self.last {
+            self.save_until(repo, time + at.elapsed());
+        }
+    }
+
+    fn save_until(&mut self, repo: &PacketRepo, now: Duration) {
+        if self.unsaved == self.history.len() {
+            return;
+        }
+        let mut rearm = Vec::new();
+        for capture in self.history[self.unsaved..]
+            .iter_mut()
+            .filter(|c| c.result.is_none() && c.end <= now)
+        {
+            let path = self.directory.join(format!(
+                "{}-{}.log",
+                capture.trigger.replace(|c: char| !c.is_alphanumeric(), "_"),
+                capture.wall.format("%Y%m%d-%H%M%S%.3f")
+            ));
+            let result = fs::create_dir_all(&self.directory)
+                .and_then(|_| File::create(&path))
+                .map_err(Error::from)
+                .and_then(|f| {
+                    let mut out = BufWriter::new(f);
+                    write_packets_between(&mut out, repo, capture.start, capture.end)?;
+                    out.flush()?;
+                    Ok(())
+                });
+            capture.result = Some(result.map(|_| path).map_err(|e| format!("{e:#}")));
+            rearm.push(capture.rearm);
+        }
+        for id in rearm {
+            self.rearm(id);
+        }
+        while self
+            .history
+            .get(self.unsaved)
+            .is_some_and(|c| c.result.is_some())
+        {
+            self.unsaved += 1;
+        }
+    }
+}
+
+fn parse_hex_u8(s: &str) -> Result<Option<u8>, Error> {
+    let s = s.trim();
+    Ok(if s.is_empty() || s == "*" {
+        None
+    } else {
+        Some(u8::from_str_radix(s, 16)?)
+    })
+}
+
+fn parse_hex_u32(s: &str) -> Result<Option<u32>, Error> {
+    let s = s.trim();
+    Ok(if s.is_empty() || s == "*" {
+        None
+    } else {
+        Some(u32::from_str_radix(s, 16)?)
+    })
+}
+
+fn parse_bytes(s: &str) -> Result<Vec<u8>, Error> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Expected hex digits."));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(anyhow!("Expected pairs of hex digits."));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+/// Find a signal by name in the open DBC windows.
+fn find_signal(dbc_tables: &DbcTables, name: &str) -> Option<(u32, SpnDefinition)> {
+    dbc_tables.lock().unwrap().iter().find_map(|t| {
+        let table = t.table.lock().unwrap();
+        let model = table.model.lock().unwrap();
+        model.pgns().iter().find_map(|pgn| {
+            pgn.spns
+                .values()
+                .find(|spn| spn.name == name)
+                .map(|spn| (dbc_key(pgn.id), spn.clone()))
+        })
+    })
+}
+
+const KINDS: [&str; 4] = [
+    "PGN/SA",
+    "Payload mask",
+    "Signal threshold",
+    "DM1 lamp change",
+];
+
+/// Define triggers, arm them and see what they captured.
+pub fn triggers_window(triggers: Arc<Mutex<Triggers>>, dbc_tables: DbcTables, timer: Arc<Timer>) {
+    let mut wind = Window::default().with_size(600, 500).with_label("Triggers");
+
+    let mut pack = Flex::default_fill()
+        .with_type(PackType::Vertical)
+        .size_of(&wind);
+
+    let mut list = HoldBrowser::default_fill();
+    list.set_tooltip("Triggers. * means armed.");
+    pack.fixed(&Frame::default().with_label("Captures"), 20);
+    let mut history = HoldBrowser::default_fill();
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut kind = Choice::default();
+    kind.add_choice(&KINDS.join("|"));
+    kind.set_value(0);
+    let mut name = Input::default();
+    name.set_tooltip("Name");
+    name.set_value("trigger");
+    let mut pgn = Input::default();
+    pgn.set_tooltip("PGN (hex, * for any)");
+    pgn.set_value("*");
+    let mut source = Input::default();
+    source.set_tooltip("SA (hex, * for any)");
+    source.set_value("*");
+    row.end();
+    pack.fixed(&row, 30);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut mask = Input::default();
+    mask.set_tooltip("Payload mask (hex bytes)");
+    let mut value = Input::default();
+    value.set_tooltip("Payload value (hex bytes)");
+    let mut signal = Input::default();
+    signal.set_tooltip("Signal name from an open DBC window");
+    let mut above = Choice::default();
+    above.add_choice(">|<");
+    above.set_value(0);
+    let mut threshold = FloatInput::default();
+    threshold.set_tooltip("Signal threshold");
+    threshold.set_value("0");
+    row.end();
+    pack.fixed(&row, 30);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    Frame::default().with_label("Pre (s)");
+    let mut pre = FloatInput::default();
+    pre.set_value("10");
+    Frame::default().with_label("Post (s)");
+    let mut post = FloatInput::default();
+    post.set_value("10");
+    let mut auto_rearm = CheckButton::default().with_label("Re-arm");
+    row.end();
+    pack.fixed(&row, 30);
+
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut add = Button::default().with_label("Add");
+    let mut remove = Button::default().with_label("Remove");
+    let mut arm = Button::default().with_label("Arm");
+    let mut directory = Button::default().with_label("Directory...");
+    row.end();
+    pack.fixed(&row, 30);
+    let mut directory_label = Frame::default()
+        .with_label(&triggers.lock().unwrap().directory.to_string_lossy())
+        .with_align(Align::Left | Align::Inside);
+    directory_label.set_tooltip("Captures are saved here.");
+    pack.fixed(&directory_label, 20);
+
+    pack.end();
+    wind.end();
+    wind.resizable(&pack);
+    wind.show();
+
+    let refresh = {
+        let triggers = triggers.clone();
+        let mut list = list.clone();
+        let mut history = history.clone();
+        move || {
+            let triggers = triggers.lock().unwrap();
+            let selected = list.value();
+            list.clear();
+            for t in &triggers.triggers {
+                list.add(&format!(
+                    "{} {}: {} (-{}s +{}s)",
+                    if t.armed { "*" } else { " " },
+                    t.name,
+                    t.condition,
+                    t.pre.as_secs_f64(),
+                    t.post.as_secs_f64()
+                ));
+            }
+            list.select(selected);
+            if history.size() as usize != triggers.history.len()
+                || triggers.history.iter().any(|c| c.result.is_none())
+            {
+                history.clear();
+                triggers
+                    .history
+                    .iter()
+                    .for_each(|c| history.add(&c.to_string()));
+            }
+        }
+    };
+    {
+        let mut refresh = refresh.clone();
+        let mut wind = wind.clone();
+        timer
+            .schedule_repeating(chrono::Duration::milliseconds(500), move || {
+                if wind.shown() {
+                    refresh();
+                    wind.redraw();
+                }
+            })
+            .ignore();
+    }
+    {
+        let triggers = triggers.clone();
+        let mut refresh = refresh.clone();
+        add.set_callback(move |_| {
+            let condition = || -> Result<Condition, Error> {
+                Ok(match kind.value() {
+                    0 => Condition::Id {
+                        pgn: parse_hex_u32(&pgn.value())?,
+                        sa: parse_hex_u8(&source.value())?,
+                    },
+                    1 => Condition::payload(
+                        parse_hex_u32(&pgn.value())?,
+                        parse_hex_u8(&source.value())?,
+                        parse_bytes(&mask.value())?,
+                        parse_bytes(&value.value())?,
+                    )?,
+                    2 => {
+                        let (id, spn) = find_signal(&dbc_tables, signal.value().trim())
+                            .ok_or(anyhow!("Signal {} not found.", signal.value()))?;
+                        Condition::Signal {
+                            id,
+                            spn,
+                            above: above.value() == 0,
+                            threshold: threshold.value().parse()?,
+                        }
+                    }
+                    _ => Condition::Dm1Lamps,
+                })
+            };
+            let seconds = |i: &FloatInput| {
+                i.value()
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                    .ok_or(())
+            };
+            match (condition(), seconds(&pre), seconds(&post)) {
+                (Ok(condition), Ok(pre), Ok(post)) => {
+                    let mut trigger = Trigger::new(name.value(), condition, pre, post);
+                    trigger.auto_rearm = auto_rearm.is_checked();
+                    triggers.lock().unwrap().triggers.push(trigger);
+                    refresh();
+                }
+                (Err(err), _, _) => alert_default(&format!("Invalid trigger: {err}")),
+                _ => alert_default("Invalid pre or post time."),
+            }
+        });
+    }
+    {
+        let triggers = triggers.clone();
+        let list = list.clone();
+        let mut refresh = refresh.clone();
+        remove.set_callback(move |_| {
+            let selected = list.value();
+            if selected > 0 {
+                let mut triggers = triggers.lock().unwrap();
+                if (selected as usize) <= triggers.triggers.len() {
+                    triggers.triggers.remove(selected as usize - 1);
+                }
+            }
+            refresh();
+        });
+    }
+    {
+        let triggers = triggers.clone();
+        let mut refresh = refresh.clone();
+        arm.set_callback(move |_| {
+            let selected = list.value();
+            triggers
+                .lock()
+                .unwrap()
+                .triggers
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| selected == 0 || *i + 1 == selected as usize)
+                .for_each(|(_, t)| t.armed = true);
+            refresh();
+        });
+    }
+    directory.set_callback(move |_| {
+        let mut fc = fltk::dialog::FileDialog::new(fltk::dialog::FileDialogType::BrowseDir);
+        fc.show();
+        if !fc.filenames().is_empty() {
+            directory_label.set_label(&fc.filename().to_string_lossy());
+            triggers.lock().unwrap().directory = fc.filename();
+        }
+    });
+    history.set_tooltip("Captures are saved in the log format.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(condition: Condition) -> Trigger {
+        Trigger::new("t".into(), condition, Duration::ZERO, Duration::ZERO)
+    }
+
+    #[test]
+    fn payload_mask() {
+        let mut t = trigger(Condition::Payload {
+            pgn: Some(0xF004),
+            sa: None,
+            mask: vec![0x0F, 0x00, 0xFF],
+            value: vec![0x01, 0x00, 0x7D],
+        });
+        assert!(t.check(0x0CF00400, &[0xF1, 0x12, 0x7D, 0]));
+        assert!(!t.check(0x0CF00400, &[0xF2, 0x12, 0x7D, 0]));
+        assert!(!t.check(0x0CF00400, &[0xF1, 0x12]));
+        assert!(!t.check(0x18FEF100, &[0xF1, 0x12, 0x7D, 0]));
+        t.armed = false;
+        assert!(!t.check(0x0CF00400, &[0xF1, 0x12, 0x7D, 0]));
+    }
+
+    #[test]
+    fn payload_lengths() {
+        assert!(Condition::payload(None, None, vec![0xFF, 0x0F], vec![0x01, 0x02]).is_ok());
+        assert!(Condition::payload(None, None, vec![0xFF, 0x0F], vec![0x01]).is_err());
+    }
+
+    #[test]
+    fn dm1_lamp_change() {
+        let mut t = trigger(Condition::Dm1Lamps);
+        assert!(!t.check(0x18FECA00, &[0x00, 0xFF]));
+        assert!(!t.check(0x18FECA00, &[0x00, 0xFF]));
+        assert!(!t.check(0x18FECA03, &[0x40, 0xFF]));
+        assert!(t.check(0x18FECA00, &[0x40, 0xFF]));
+    }
+
+    #[test]
+    fn capture_window() {
+        let mut triggers = Triggers::default();
+        let mut t = trigger(Condition::Id {
+            pgn: None,
+            sa: Some(0x17),
+        });
+        t.pre = Duration::from_secs(5);
+        t.post = Duration::from_secs(2);
+        triggers.triggers.push(t);
+        triggers.check(0x18FEF100, Duration::from_secs(10), &[]);
+        assert!(triggers.history.is_empty());
+        triggers.check(0x18FEF117, Duration::from_secs(11), &[]);
+        triggers.check(0x18FEF117, Duration::from_secs(12), &[]);
+        assert_eq!(triggers.history.len(), 1);
+        let capture = &triggers.history[0];
+        assert_eq!(capture.start, Duration::from_secs(6));
+        assert_eq!(capture.end, Duration::from_secs(13));
+        assert!(!triggers.triggers[0].armed);
+
+        // clearing the log drops the pending capture, leaving the trigger disarmed
+        triggers.clear();
+        assert!(triggers.history.is_empty());
+        assert!(!triggers.triggers[0].armed);
+        triggers.triggers[0].auto_rearm = true;
+        triggers.triggers[0].armed = true;
+        triggers.check(0x18FEF117, Duration::from_secs(1), &[]);
+        triggers.clear();
+        assert!(triggers.triggers[0].armed);
+
+        // removing an earlier trigger doesn't re-arm another one
+        triggers.triggers.insert(0, trigger(Condition::Dm1Lamps));
+        triggers.triggers[0].armed = false;
+        triggers.check(0x18FEF117, Duration::from_secs(1), &[]);
+        triggers.triggers.remove(0);
+        assert!(!triggers.triggers[0].armed);
+        triggers.directory = std::env::temp_dir().join(format!("captures-{}", std::process::id()));
+        triggers.save_until(&PacketRepo::default(), Duration::from_secs(9));
+        assert!(triggers.triggers[0].armed);
+        fs::remove_dir_all(&triggers.directory).unwrap();
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(parse_bytes("FF 00 7d").unwrap(), vec![0xFF, 0, 0x7D]);
+        assert!(parse_bytes("F").is_err());
+        assert!(parse_bytes("é1").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use fltk::{
+    enums::Font,
+    prelude::{GroupExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+use timer::Timer;
+
+use crate::packet_repo::{da, pgn_of, sa};
+
+/// ISO 15765-2 normal fixed addressing, physical
+pub const DIAG_PHYSICAL: u32 = 0xDA00;
+/// ISO 15765-2 normal fixed addressing, functional
+pub const DIAG_FUNCTIONAL: u32 = 0xDB00;
+
+/// Time allowed between consecutive frames, N_Cr
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A reassembled ISO-TP message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IsoTpMessage {
+    pub time: Duration,
+    pub source: u8,
+    pub target: u8,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+struct Partial {
+    data: Vec<u8>,
+    length: usize,
+    next: u8,
+    last: Duration,
+}
+
+/// Reassembles ISO-TP single, first and consecutive frames. Flow control is not checked.
+#[derive(Clone, Debug, Default)]
+pub struct IsoTpReceiver {
+    /// (source, target) -> message in progress
+    partial: HashMap<(u8, u8), Partial>,
+}
+
+impl IsoTpReceiver {
+    /// Check a frame with 29 bit normal fixed addressing.
+    pub fn receive(&mut self, id: u32, time: Duration, data: &[u8]) -> Option<IsoTpMessage> {
+        if !matches!(pgn_of(id), DIAG_PHYSICAL | DIAG_FUNCTIONAL) {
+            return None;
+        }
+        self.receive_frame((sa(id), da(id)), time, data)
+    }
+
+    /// Check a frame from `key.0` to `key.1`, for any addressing scheme.
+    pub fn receive_frame(
+        &mut self,
+        key: (u8, u8),
+        time: Duration,
+        data: &[u8],
+    ) -> Option<IsoTpMessage> {
+        if data.is_empty() {
+            return None;
+        }
+        let message = |payload: Vec<u8>| IsoTpMessage {
+            time,
+            source: key.0,
+            target: key.1,
+            payload,
+        };
+        match data[0] >> 4 {
+            0 => {
+                let len = (data[0] & 0xF) as usize;
+                // CAN FD escape, length in the next byte
+                let (start, len) = if len == 0 && data.len() > 1 {
+                    (2, data[1] as usize)
+                } else {
+                    (1, len)
+                };
+                self.partial.remove(&key);
+                data.get(start..start + len).map(|d| message(d.to_vec()))
+            }
+            1 if data.len() >= 2 => {
+                let length = ((data[0] as usize & 0xF) << 8) | data[1] as usize;
+                let (start, length) = if length == 0 && data.len() >= 6 {
+                    (
+                        6,
+                        u32::from_be_bytes([data[2], data[3], data[4], data[5]]) as usize,
+                    )
+                } else {
+                    (2, length)
+                };
+                self.partial.insert(
+                    key,
+                    Partial {
+                        data: data[start..].to_vec(),
+                        length,
+                        next: 1,
+                        last: time,
+                    },
+                );
+                None
+            }
+            2 => {
+                let partial = self.partial.get_mut(&key)?;
+                if data[0] & 0xF != partial.next || time.saturating_sub(partial.last) > TIMEOUT {
+                    self.partial.remove(&key);
+                    return None;
+                }
+                partial.next = (partial.next + 1) & 0xF;
+                partial.last = time;
+                partial.data.extend_from_slice(&data[1..]);
+                if partial.data.len() < partial.length {
+                    return None;
+                }
+                let mut partial = self.partial.remove(&key)?;
+                partial.data.truncate(partial.length);
+                Some(message(partial.data))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn service_name(sid: u8) -> Option<&'static str> {
+    Some(match sid {
+        0x10 => "DiagnosticSessionControl",
+        0x11 => "ECUReset",
+        0x14 => "ClearDiagnosticInformation",
+        0x19 => "ReadDTCInformation",
+        0x22 => "ReadDataByIdentifier",
+        0x27 => "SecurityAccess",
+        0x28 => "CommunicationControl",
+        0x2E => "WriteDataByIdentifier",
+        0x31 => "RoutineControl",
+        0x34 => "RequestDownload",
+        0x36 => "TransferData",
+        0x37 => "RequestTransferExit",
+        0x3E => "TesterPresent",
+        0x85 => "ControlDTCSetting",
+        _ => return None,
+    })
+}
+
+fn negative_response(nrc: u8) -> &'static str {
+    match nrc {
+        0x10 => "generalReject",
+        0x11 => "serviceNotSupported",
+        0x12 => "subFunctionNotSupported",
+        0x13 => "incorrectMessageLengthOrInvalidFormat",
+        0x14 => "responseTooLong",
+        0x21 => "busyRepeatRequest",
+        0x22 => "conditionsNotCorrect",
+        0x24 => "requestSequenceError",
+        0x31 => "requestOutOfRange",
+        0x33 => "securityAccessDenied",
+        0x35 => "invalidKey",
+        0x36 => "exceedNumberOfAttempts",
+        0x37 => "requiredTimeDelayNotExpired",
+        0x72 => "generalProgrammingFailure",
+        0x78 => "requestCorrectlyReceivedResponsePending",
+        0x7E => "subFunctionNotSupportedInActiveSession",
+        0x7F => "serviceNotSupportedInActiveSession",
+        _ => "unknown",
+    }
+}
+
+fn session_name(session: u8) -> &'static str {
+    match session & 0x7F {
+        1 => "default",
+        2 => "programming",
+        3 => "extended",
+        4 => "safety system",
+        _ => "vendor",
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// DTCs as 3 byte numbers with their status byte.
+fn dtcs(data: &[u8]) -> String {
+    data.chunks_exact(4)
+        .map(|d| {
+            format!(
+                "{:06X}/{:02X}",
+                u32::from_be_bytes([0, d[0], d[1], d[2]]),
+                d[3]
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Describe a UDS request or response.
+pub fn decode(payload: &[u8]) -> String {
+    let Some((&sid, data)) = payload.split_first() else {
+        return String::new();
+    };
+    if sid == 0x7F {
+        return match data {
+            [service, nrc, ..] => format!(
+                "Negative response to {}: {nrc:02X} {}",
+                service_name(*service).unwrap_or("unknown service"),
+                negative_response(*nrc)
+            ),
+            _ => "Negative response".into(),
+        };
+    }
+    let request = sid & 0x40 == 0;
+    let base = if request { sid } else { sid - 0x40 };
+    let Some(name) = service_name(base) else {
+        return format!("Unknown service {sid:02X}");
+    };
+    let detail = match (base, data) {
+        (0x10, [session, ..]) => format!("{} session", session_name(*session)),
+        (0x22, dids) if request => dids
+            .chunks_exact(2)
+            .map(|d| format!("DID {:04X}", u16::from_be_bytes([d[0], d[1]])))
+            .collect::<Vec<_>>()
+            .join(", "),
+        (0x22, [a, b, value @ ..]) => {
+            format!("DID {:04X} = {}", u16::from_be_bytes([*a, *b]), hex(value))
+        }
+        (0x19, [sub, rest @ ..]) if request => match sub {
+            0x01 => format!("number of DTCs, status mask {}", hex(rest)),
+            0x02 => format!("DTCs by status mask {}", hex(rest)),
+            0x06 => format!("extended data for {}", hex(rest)),
+            _ => format!("sub-function {sub:02X} {}", hex(rest)),
+        },
+        (0x19, [0x01, _, _, hi, lo, ..]) => {
+            format!("{} DTCs", u16::from_be_bytes([*hi, *lo]))
+        }
+        (0x19, [0x02, mask, list @ ..]) => {
+            format!("status mask {mask:02X}: {}", dtcs(list))
+        }
+        (0x27, [level, rest @ ..]) => {
+            let what = match (level % 2 == 1, request) {
+                (true, true) => "request seed",
+                (true, false) => "seed",
+                (false, true) => "send key",
+                (false, false) => "key accepted",
+            };
+            format!("level {level:02X} {what} {}", hex(rest))
+        }
+        (0x31, [control, a, b, rest @ ..]) => {
+            let control = match control {
+                1 => "start",
+                2 => "stop",
+                3 => "results",
+                _ => "control",
+            };
+            format!(
+                "{control} routine {:04X} {}",
+                u16::from_be_bytes([*a, *b]),
+                hex(rest)
+            )
+        }
+        _ => hex(data),
+    };
+    format!(
+        "{name} {}: {detail}",
+        if request { "request" } else { "response" }
+    )
+    .trim_end_matches([' ', ':'])
+    .to_string()
+}
+
+/// Decoded diagnostic traffic.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    isotp: IsoTpReceiver,
+    entries: Vec<(IsoTpMessage, String)>,
+}
+
+impl Diagnostics {
+    /// Check one packet from the log.
+    pub fn receive(&mut self, id: u32, time: Duration, data: &[u8]) {
+        if let Some(message) = self.isotp.receive(id, time, data) {
+            let text = decode(&message.payload);
+            self.entries.push((message, text));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+const COLUMNS: [&str; 5] = ["Time", "Source", "Target", "Data", "UDS"];
+
+pub struct DiagModel {
+    diagnostics: Arc<Mutex<Diagnostics>>,
+}
+
+impl SimpleModel for DiagModel {
+    fn row_count(&mut self) -> usize {
+        self.diagnostics.lock().unwrap().entries.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 80,
+            1 | 2 => 50,
+            3 => 250,
+            _ => 400,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let diagnostics = self.diagnostics.lock().unwrap();
+        let (message, text) = diagnostics.entries.get(row as usize)?;
+        match col {
+            0 => Some(format!("{:.3}", message.time.as_secs_f64())),
+            1 => Some(format!("{:02X}", message.source)),
+            2 => Some(format!("{:02X}", message.target)),
+            3 => Some(hex(&message.payload)),
+            4 => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    fn sort(&mut self, _col: usize, _order: Order) {
+        // sorting not supported
+    }
+}
+
+/// ISO-TP messages on 0x18DA/0x18DB with their UDS meaning.
+pub fn diag_window(diagnostics: Arc<Mutex<Diagnostics>>, timer: &Timer) {
+    let mut wind = Window::default()
+        .with_size(900, 400)
+        .with_label("Diagnostics (UDS)");
+    let mut table = JoeTable::new(DiagModel { diagnostics });
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    table.redraw_on(timer, chrono::Duration::milliseconds(500));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn single_and_multi_frame() {
+        let mut rx = IsoTpReceiver::default();
+        let m = rx
+            .receive(0x18DA00F9, ms(0), &[0x03, 0x22, 0xF1, 0x90, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!((m.source, m.target), (0xF9, 0x00));
+        assert_eq!(m.payload, [0x22, 0xF1, 0x90]);
+
+        // VIN response, 20 bytes
+        let vin = b"1HGBH41JXMN109186";
+        let mut payload = vec![0x62, 0xF1, 0x90];
+        payload.extend_from_slice(vin);
+        let mut first = vec![0x10, payload.len() as u8];
+        first.extend_from_slice(&payload[..6]);
+        assert_eq!(rx.receive(0x18DAF900, ms(10), &first), None);
+        // flow control from the tester is ignored
+        assert_eq!(
+            rx.receive(0x18DA00F9, ms(11), &[0x30, 0, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+        let mut result = None;
+        for (seq, chunk) in (1u8..).zip(payload[6..].chunks(7)) {
+            let mut frame = vec![0x20 | seq];
+            frame.extend_from_slice(chunk);
+            frame.resize(8, 0xAA);
+            result = rx.receive(0x18DAF900, ms(12 + seq as u64), &frame);
+        }
+        let m = result.unwrap();
+        assert_eq!(m.payload, payload);
+        assert_eq!(
+            decode(&m.payload),
+            "ReadDataByIdentifier response: DID F190 = 31 48 47 42 48 34 31 4A 58 4D 4E 31 30 39 31 38 36"
+        );
+    }
+
+    #[test]
+    fn bad_sequence() {
+        let mut rx = IsoTpReceiver::default();
+        rx.receive(0x18DAF900, ms(0), &[0x10, 10, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(rx.receive(0x18DAF900, ms(1), &[0x22, 7, 8, 9, 10]), None);
+        assert_eq!(rx.receive(0x18DAF900, ms(2), &[0x21, 7, 8, 9, 10]), None);
+        // not diagnostic
+        assert_eq!(rx.receive(0x18FEF100, ms(3), &[0x02, 0x10, 0x03]), None);
+    }
+
+    #[test]
+    fn services() {
+        assert_eq!(
+            decode(&[0x10, 0x03]),
+            "DiagnosticSessionControl request: extended session"
+        );
+        assert_eq!(
+            decode(&[0x7F, 0x27, 0x35]),
+            "Negative response to SecurityAccess: 35 invalidKey"
+        );
+        assert_eq!(
+            decode(&[0x67, 0x01, 0x12, 0x34]),
+            "SecurityAccess response: level 01 seed 12 34"
+        );
+        assert_eq!(
+            decode(&[0x59, 0x02, 0xFF, 0x12, 0x34, 0x56, 0x09]),
+            "ReadDTCInformation response: status mask FF: 123456/09"
+        );
+        assert_eq!(
+            decode(&[0x31, 0x01, 0xFF, 0x00]),
+            "RoutineControl request: start routine FF00"
+        );
+        assert_eq!(decode(&[0x3E, 0x00]), "TesterPresent request: 00");
+        assert_eq!(
+            decode(&[0x22, 0xF1, 0x90, 0xF1, 0x8C]),
+            "ReadDataByIdentifier request: DID F190, DID F18C"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use canparse::pgn::PgnDefinition;
+use fltk::{
+    button::Button,
+    enums::Font,
+    frame::Frame,
+    group::{Flex, PackType},
+    prelude::{GroupExt, WidgetBase, WidgetExt, WindowExt},
+    window::Window,
+};
+use simple_table::{
+    joe_table::JoeTable,
+    simple_model::{Order, SimpleModel},
+};
+
+use crate::{
+    dbc::{self, Message},
+    dbc_table::DbcModel,
+    packet_repo::{dbc_key, pgn_text},
+    report::report,
+};
+
+/// Packets needed before signals are judged by which bits change.
+const MIN_SAMPLES: usize = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// no packets for the message
+    Unused,
+    /// payload lengths seen that differ from the DLC
+    Dlc(Vec<usize>),
+    /// the signal ends after byte `end`, past a payload of `len` bytes
+    PastPayload { end: usize, len: usize },
+    /// the signal never changes while these bits in its bytes do
+    Constant(Vec<usize>),
+    /// the signal's most significant byte changes more than its least significant byte
+    ByteOrder,
+    /// the file doesn't parse here
+    Syntax(String),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Unused => write!(f, "not seen on the bus"),
+            Problem::Dlc(lengths) => write!(f, "payload lengths seen {lengths:?}"),
+            Problem::PastPayload { end, len } => {
+                write!(f, "ends in byte {end}, past the {len} byte payload")
+            }
+            Problem::Constant(bits) => write!(
+                f,
+                "never changes while bits {bits:?} next to it do, check the start bit and byte order"
+            ),
+            Problem::ByteOrder => write!(
+                f,
+                "changes most in its most significant byte, check the byte order"
+            ),
+            Problem::Syntax(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// A problem with a DBC message or signal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    /// line in the DBC file
+    pub line: usize,
+    pub id: u32,
+    pub message: String,
+    pub signal: Option<String>,
+    pub problem: Problem,
+}
+
+/// Times each payload bit differed from the previous payload. Bit `n` is bit `n % 8` of
+/// byte `n / 8`.
+pub fn bit_changes(payloads: &[Vec<u8>]) -> Vec<usize> {
+    let len = payloads.iter().map(|p| p.len()).max().unwrap_or_default();
+    let mut changes = vec![0; len * 8];
+    for pair in payloads.windows(2) {
+        for (byte, (a, b)) in pair[0].iter().zip(&pair[1]).enumerate() {
+            let diff = a ^ b;
+            for bit in 0..8 {
+                if diff & (1 << bit) != 0 {
+                    changes[byte * 8 + bit] += 1;
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// Compare the messages of a DBC with the payloads seen for each of them, one list per
+/// source address. Bit changes are counted within each list.
+pub fn validate(
+    messages: &[Message],
+    payloads: impl Fn(&Message) -> Vec<Vec<Vec<u8>>>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for m in messages {
+        let mut add = |signal: Option<&dbc::Signal>, problem| {
+            findings.push(Finding {
+                line: signal.map_or(m.line, |s| s.line),
+                id: m.id,
+                message: m.name.clone(),
+                signal: signal.map(|s| s.name.clone()),
+                problem,
+            })
+        };
+        let streams = payloads(m);
+        let data: Vec<&Vec<u8>> = streams.iter().flatten().collect();
+        if data.is_empty() {
+            add(None, Problem::Unused);
+            continue;
+        }
+        let lengths: BTreeSet<usize> = data.iter().map(|p| p.len()).collect();
+        if lengths.iter().any(|l| *l != m.dlc) {
+            add(None, Problem::Dlc(lengths.iter().copied().collect()));
+        }
+        let len = m.dlc.min(*lengths.first().unwrap());
+        let mut changes = vec![0; lengths.last().unwrap() * 8];
+        for stream in &streams {
+            for (total, c) in changes.iter_mut().zip(bit_changes(stream)) {
+                *total += c;
+            }
+        }
+        for s in &m.signals {
+            let bits = s.bits();
+            let end = bits.iter().max().unwrap() / 8 + 1;
+            if end > len {
+                add(Some(s), Problem::PastPayload { end, len });
+                continue;
+            }
+            if data.len() < MIN_SAMPLES {
+                continue;
+            }
+            if bits.iter().all(|b| changes[*b] == 0) {
+                let bytes: BTreeSet<usize> = bits.iter().map(|b| b / 8).collect();
+                let nearby: Vec<usize> = bytes
+                    .iter()
+                    .flat_map(|byte| byte * 8..byte * 8 + 8)
+                    .filter(|b| changes[*b] > 0 && !bits.contains(b))
+                    .collect();
+                if !nearby.is_empty() {
+                    add(Some(s), Problem::Constant(nearby));
+                }
+                continue;
+            }
+            let in_byte = |byte: usize| -> usize {
+                bits.iter()
+                    .filter(|b| *b / 8 == byte)
+                    .map(|b| changes[*b])
+                    .sum()
+            };
+            let (lsb, msb) = (bits[0] / 8, bits[bits.len() - 1] / 8);
+            if lsb != msb && in_byte(msb) > 2 * in_byte(lsb) {
+                add(Some(s), Problem::ByteOrder);
+            }
+        }
+    }
+    findings
+}
+
+/// Validation report of a DBC window.
+#[derive(Default)]
+pub struct ValidationModel {
+    findings: Vec<Finding>,
+}
+
+const COLUMNS: [&str; 4] = ["Line", "Message", "Signal", "Problem"];
+
+impl SimpleModel for ValidationModel {
+    fn row_count(&mut self) -> usize {
+        self.findings.len()
+    }
+
+    fn column_count(&mut self) -> usize {
+        COLUMNS.len()
+    }
+
+    fn header(&mut self, col: usize) -> String {
+        COLUMNS[col].into()
+    }
+
+    fn column_width(&mut self, col: usize) -> u32 {
+        match col {
+            0 => 50,
+            1 | 2 => 150,
+            _ => 500,
+        }
+    }
+
+    fn cell(&mut self, row: i32, col: i32) -> Option<String> {
+        let f = self.findings.get(row as usize)?;
+        match col {
+            0 => Some(f.line.to_string()),
+            1 if f.message.is_empty() => None,
+            1 => Some(format!("{} ({})", f.message, pgn_text(dbc_key(f.id)))),
+            2 => f.signal.clone(),
+            3 => Some(f.problem.to_string()),
+            _ => None,
+        }
+    }
+
+    fn sort(&mut self, col: usize, order: Order) {
+        let key = |f: &Finding| match col {
+            1 => (f.message.clone(), f.line),
+            2 => (f.signal.clone().unwrap_or_default(), f.line),
+            3 => (f.problem.to_string(), f.line),
+            _ => (String::new(), f.line),
+        };
+        match order {
+            Order::Descending => self.findings.sort_by_key(|f| std::cmp::Reverse(key(f))),
+            _ => self.findings.sort_by_key(key),
+        }
+    }
+}
+
+/// Messages of a DBC file as its window loaded them: only those in `pgns`, with their signal
+/// layouts. The file supplies line numbers and DLCs.
+fn loaded(messages: &[Message], pgns: &[PgnDefinition]) -> Vec<Message> {
+    messages
+        .iter()
+        .filter_map(|m| {
+            let pgn = pgns.iter().find(|p| dbc_key(p.id) == dbc_key(m.id))?;
+            let signals = m
+                .signals
+                .iter()
+                .filter_map(|s| {
+                    let spn = pgn.spns.values().find(|spn| spn.name == s.name)?;
+                    Some(dbc::Signal {
+                        start: spn.start_bit,
+                        length: spn.bit_len,
+                        little_endian: spn.little_endian,
+                        signed: spn.signed,
+                        ..s.clone()
+                    })
+                })
+                .collect();
+            Some(Message {
+                signals,
+                ..m.clone()
+            })
+        })
+        .collect()
+}
+
+/// Validate the definitions a DBC window decodes with against its packets. Problems parsing
+/// the file are findings too.
+fn check_file(path: &Path, model: &DbcModel) -> Result<Vec<Finding>> {
+    let bytes = fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let (dbc, diagnostics) = dbc::parse(&String::from_utf8_lossy(&bytes));
+    let mut findings: Vec<Finding> = diagnostics
+        .into_iter()
+        .map(|d| Finding {
+            line: d.line,
+            id: 0,
+            message: String::new(),
+            signal: None,
+            problem: Problem::Syntax(d.message),
+        })
+        .collect();
+    let messages = loaded(&dbc.messages, model.dbc_pgns());
+    findings.extend(validate(&messages, |m| model.payloads(m.id)));
+    Ok(findings)
+}
+
+/// Report on how well a DBC file matches the logged traffic.
+pub fn validation_window(path: PathBuf, dbc: Arc<Mutex<JoeTable<DbcModel>>>) {
+    let mut wind = Window::default().with_size(800, 400).with_label(&format!(
+        "Validate {}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let mut flex = Flex::default_fill().with_type(PackType::Vertical);
+    let row = Flex::default_fill().with_type(PackType::Horizontal);
+    let mut summary = Frame::default();
+    let mut refresh = Button::default().with_label("Refresh");
+    row.end();
+    flex.fixed(&row, 25);
+
+    let mut table = JoeTable::new(ValidationModel::default());
+    table.set_font(Font::Helvetica, 8);
+    table.init();
+    flex.end();
+    wind.end();
+    wind.resizable(&wind);
+    wind.show();
+
+    let mut update = move || {
+        let result = {
+            let dbc = dbc.lock().unwrap();
+            let model = dbc.model.lock().unwrap();
+            check_file(&path, &model)
+        };
+        match result {
+            Ok(findings) => {
+                summary.set_label(&format!("{} problems", findings.len()));
+                table.model.lock().unwrap().findings = findings;
+                table.redraw();
+            }
+            Err(err) => report("Unable to validate", &err),
+        }
+    };
+    update();
+    refresh.set_callback(move |_| update());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(signals: Vec<dbc::Signal>) -> Vec<Message> {
+        vec![Message {
+            line: 1,
+            id: 0x8CF00400,
+            name: "EEC1".into(),
+            dlc: 8,
+            signals,
+            ..Default::default()
+        }]
+    }
+
+    fn signal(start: usize, length: usize, little_endian: bool) -> dbc::Signal {
+        dbc::Signal {
+            line: 2,
+            name: "S".into(),
+            start,
+            length,
+            little_endian,
+            ..Default::default()
+        }
+    }
+
+    /// 16 bit counter in bytes 3 and 4, Intel byte order
+    fn counter() -> Vec<Vec<u8>> {
+        (0..20u16)
+            .map(|i| {
+                let v = (i * 37).to_le_bytes();
+                vec![0xFF, 0xFF, 0xFF, v[0], v[1], 0xFF, 0xFF, 0xFF]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn changes() {
+        let changes = bit_changes(&[vec![0, 1], vec![1, 1], vec![0, 1]]);
+        assert_eq!(changes.len(), 16);
+        assert_eq!(changes[0], 2);
+        assert_eq!(changes.iter().sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn findings() {
+        let problems = |signals, data: Vec<Vec<u8>>| -> Vec<Problem> {
+            validate(&message(signals), |_| vec![data.clone()])
+                .into_iter()
+                .map(|f| f.problem)
+                .collect()
+        };
+        assert_eq!(problems(vec![], vec![]), vec![Problem::Unused]);
+        assert_eq!(
+            problems(vec![signal(48, 16, true)], vec![vec![0; 7], vec![0; 8]]),
+            vec![
+                Problem::Dlc(vec![7, 8]),
+                Problem::PastPayload { end: 8, len: 7 }
+            ]
+        );
+        // correct layout
+        assert_eq!(problems(vec![signal(24, 16, true)], counter()), vec![]);
+        // one byte too early, only the high byte changes
+        assert_eq!(
+            problems(vec![signal(16, 16, true)], counter()),
+            vec![Problem::ByteOrder]
+        );
+        // Motorola, least significant byte first
+        assert_eq!(
+            problems(vec![signal(31, 16, false)], counter()),
+            vec![Problem::ByteOrder]
+        );
+        // bits never change, but others in the same byte do
+        assert_eq!(
+            problems(vec![signal(36, 4, true)], counter()),
+            vec![Problem::Constant(vec![32, 33])]
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        // two addresses send a different constant high nibble in byte 3, which only changes
+        // where one address's packets meet the other's
+        let with = |byte: u8| -> Vec<Vec<u8>> {
+            counter()
+                .into_iter()
+                .map(|mut p| {
+                    p[3] = byte;
+                    p
+                })
+                .collect()
+        };
+        let (a, b) = (with(0x11), with(0x21));
+        let problems = |data: Vec<Vec<Vec<u8>>>| -> Vec<Problem> {
+            validate(&message(vec![signal(24, 4, true)]), |_| data.clone())
+                .into_iter()
+                .map(|f| f.problem)
+                .collect()
+        };
+        assert_eq!(problems(vec![a.clone(), b.clone()]), vec![]);
+        assert_eq!(
+            problems(vec![a.into_iter().chain(b).collect()]),
+            vec![Problem::Constant(vec![28, 29])]
+        );
+    }
+}